@@ -0,0 +1,36 @@
+//! Hashes the compiler's sources, so that the compilation cache can tell apart builds of the
+//! compiler even when the package version stays the same.
+
+use std::path::{Path, PathBuf};
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return; };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect(&path, files);
+        }
+        else {
+            files.push(path);
+        }
+    }
+}
+
+fn main() {
+    let mut files = vec![];
+    collect(Path::new("src"), &mut files);
+    files.sort();
+
+    // 64-bit FNV-1a over every path and its contents, like `compiler::cache::hash`.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for file in &files {
+        let contents = std::fs::read(file).unwrap_or_default();
+        for byte in file.to_string_lossy().bytes().chain(contents) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    println!("cargo:rustc-env=PONYSCRIPT_SOURCE_HASH={:016x}", hash);
+    println!("cargo:rerun-if-changed=src");
+}
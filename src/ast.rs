@@ -1,194 +1,269 @@
-use std::fmt::{Debug, Display, Formatter};
-use std::io;
-use std::io::Write;
-use crate::string_pool::PoolS;
-
-mod types;
-pub mod codegen;
-mod typecheck;
-pub mod op;
-pub use types::Type;
-pub use codegen::codegen;
-pub use typecheck::typecheck;
-use crate::bindings::{Bindings, FunID, Namespace, VarID};
-
-pub enum BindPoint<Id> {
-    Unbound(PoolS),
-    BoundTo(Id)
-}
-
-impl<Id> Display for BindPoint<Id> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            BindPoint::Unbound(s) => {
-                f.write_fmt(format_args!("{}", s))
-            },
-            BindPoint::BoundTo(_) => {
-                f.write_str("[todo]")
-            }
-        }
-    }
-}
-
-impl<Id> BindPoint<Id> {
-    pub fn unresolved(name: PoolS) -> Self {
-        BindPoint::Unbound(name)
-    }
-
-    pub fn bind_to(&mut self, new_binding: Id) {
-        let old = std::mem::replace(self, BindPoint::BoundTo(new_binding));
-        drop(old);
-    }
-}
-
-pub struct Tree {
-    pub base_type: PoolS,
-    pub own_type: PoolS,
-    pub children: Vec<Node>
-}
-
-pub struct FunDecl {
-    pub bind_id: FunID,
-    pub body: Vec<Node>
-}
-
-impl FunDecl {
-    pub fn new(bind_id: FunID) -> Self {
-        FunDecl {
-            bind_id,
-            body: vec![]
-        }
-    }
-
-    pub fn to_node(self) -> Node {
-        return Node::FunDecl(self)
-    }
-
-    pub fn to_rnode(self) -> RNode {
-        return Ok(self.to_node())
-    }
-}
-
-pub struct Declaration {
-    pub bind_id: VarID,
-    pub expr: Option<Box<Node>>
-}
-
-impl Declaration {
-    pub fn new(bind_id: VarID) -> Self {
-        Self::new_expr(bind_id, None)
-    }
-
-    pub fn new_expr(bind_id: VarID, expr: Option<Box<Node>>) -> Self {
-        Declaration {
-            bind_id,
-            expr
-        }
-    }
-
-    pub fn to_node(self) -> Node { return Node::Decl(self) }
-
-    #[allow(unused)]
-    pub fn to_rnode(self) -> RNode {
-        return Ok(self.to_node())
-    }
-}
-
-pub struct NumConst {
-    pub value_str: PoolS,
-    pub typ: Type
-}
-
-impl NumConst {
-    pub fn new(value_str: PoolS, typ: Type) -> Self {
-        NumConst { value_str, typ }
-    }
-
-    pub fn to_node(self) -> Node {
-        Node::NumConst(self)
-    }
-}
-
-#[allow(unused)]
-pub enum Op {
-    Add,
-    Subtract,
-    Multiply,
-    Divide
-}
-
-impl Op {
-    pub fn impl_str(&self) -> &'static str {
-        match self {
-            Op::Add => "add",
-            Op::Subtract => "sub",
-            Op::Multiply => "mul",
-            Op::Divide => "div"
-        }
-    }
-}
-
-pub enum Node {
-    Tree(Tree),
-    FunDecl(FunDecl),
-    Decl(Declaration),
-    Assign(BindPoint<VarID>, Box<Node>),
-    VarRef(BindPoint<VarID>),
-    NumConst(NumConst),
-    FunCall(Namespace, BindPoint<FunID>, Vec<Node>),
-    BinOp(Op, Box<Node>, Box<Node>),
-    Empty
-}
-
-impl Debug for Node {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Node::Tree(tree) => {
-                f.write_str("[tree ")?;
-                for node in &tree.children {
-                    node.fmt(f)?;
-                }
-                f.write_str("]")?;
-            }
-            Node::FunDecl(_) => {
-                f.write_fmt(format_args!("[func]"))?;
-            }
-            _ => { f.write_str("[unknown]")?; }
-        }
-        Ok(())
-    }
-}
-
-impl Node {
-    pub fn get_expr_type(&self, bindings: &Bindings) -> Type {
-        match &self {
-            Node::Tree(_) => { Type::Error }
-            Node::FunDecl(_) => {
-                Type::Error
-            }
-            Node::Decl(_) => { Type::Error }
-            Node::Assign(_, _) => { Type::Error }
-            Node::NumConst(num) => {
-                num.typ.clone()
-            }
-            Node::VarRef(point) => {
-                match point {
-                    BindPoint::Unbound(_) => Type::Error,
-                    BindPoint::BoundTo(bind_id) => bindings.get_var(*bind_id).typ.clone()
-                }
-            }
-            Node::FunCall(_, point, _) => {
-                match point {
-                    BindPoint::Unbound(_) => Type::Error,
-                    BindPoint::BoundTo(bind_id) => bindings.get_fun(*bind_id).return_type.clone()
-                }
-            }
-            Node::BinOp(_, lhs, _) => {
-                lhs.get_expr_type(bindings)
-            }
-            Node::Empty => { Type::Error }
-        }
-    }
-}
-
-pub type RNode = Result<Node, String>;
\ No newline at end of file
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::io::Write;
+use crate::string_pool::PoolS;
+
+mod types;
+pub mod codegen;
+mod typecheck;
+pub mod op;
+mod sexpr;
+pub use types::Type;
+pub use codegen::codegen;
+pub use typecheck::typecheck;
+pub use sexpr::{to_sexpr, type_sexpr};
+use crate::bindings::{Bindings, FunID, Namespace, VarID};
+use crate::diagnostic::Diagnostic;
+
+pub enum BindPoint<Id> {
+    Unbound(PoolS),
+    BoundTo(Id)
+}
+
+/// Displays the name of an unbound point, or the ID a bound one is bound to.
+impl<Id: Display> Display for BindPoint<Id> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindPoint::Unbound(s) => {
+                f.write_fmt(format_args!("{}", s))
+            },
+            BindPoint::BoundTo(id) => {
+                f.write_fmt(format_args!("{}", id))
+            }
+        }
+    }
+}
+
+impl<Id> BindPoint<Id> {
+    pub fn unresolved(name: PoolS) -> Self {
+        BindPoint::Unbound(name)
+    }
+
+    pub fn bind_to(&mut self, new_binding: Id) {
+        let old = std::mem::replace(self, BindPoint::BoundTo(new_binding));
+        drop(old);
+    }
+}
+
+impl<Id: Copy> BindPoint<Id> {
+    /// The ID this point is bound to. Backends use this on typechecked trees, where everything is
+    /// bound, so an unbound point is reported as an internal error.
+    pub fn expect_bound(&self) -> Result<Id, String> {
+        match self {
+            BindPoint::BoundTo(id) => Ok(*id),
+            BindPoint::Unbound(name) => Err(format!("{} was never bound; was the tree typechecked?", name))
+        }
+    }
+}
+
+/// A position in a source file. Lines and columns start at 1. Spans order by their position.
+#[derive(Copy, Clone)]
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Span {
+    pub line: i32,
+    pub column: i32
+}
+
+impl Span {
+    pub fn new(line: i32, column: i32) -> Self {
+        Span { line, column }
+    }
+}
+
+pub struct Tree {
+    /// The name of the file this tree was parsed from, as given to the lexer.
+    pub source: String,
+    pub base_type: PoolS,
+    pub own_type: PoolS,
+    /// The `##` doc comment before the `extends` line.
+    pub doc: Option<String>,
+    pub children: Vec<Node>
+}
+
+/// A statement inside a function body, along with where it starts.
+pub struct Stmt {
+    pub span: Span,
+    pub node: Node
+}
+
+impl Stmt {
+    pub fn new(span: Span, node: Node) -> Self {
+        Stmt { span, node }
+    }
+}
+
+pub struct FunDecl {
+    pub bind_id: FunID,
+    pub span: Span,
+    pub body: Vec<Stmt>
+}
+
+impl FunDecl {
+    pub fn new(bind_id: FunID, span: Span) -> Self {
+        FunDecl {
+            bind_id,
+            span,
+            body: vec![]
+        }
+    }
+
+    pub fn to_node(self) -> Node {
+        return Node::FunDecl(self)
+    }
+
+    pub fn to_rnode(self) -> RNode {
+        return Ok(self.to_node())
+    }
+}
+
+pub struct Declaration {
+    pub bind_id: VarID,
+    pub expr: Option<Box<Node>>
+}
+
+impl Declaration {
+    pub fn new(bind_id: VarID) -> Self {
+        Self::new_expr(bind_id, None)
+    }
+
+    pub fn new_expr(bind_id: VarID, expr: Option<Box<Node>>) -> Self {
+        Declaration {
+            bind_id,
+            expr
+        }
+    }
+
+    pub fn to_node(self) -> Node { return Node::Decl(self) }
+
+    #[allow(unused)]
+    pub fn to_rnode(self) -> RNode {
+        return Ok(self.to_node())
+    }
+}
+
+pub struct NumConst {
+    pub value_str: PoolS,
+    pub typ: Type
+}
+
+impl NumConst {
+    pub fn new(value_str: PoolS, typ: Type) -> Self {
+        NumConst { value_str, typ }
+    }
+
+    pub fn to_node(self) -> Node {
+        Node::NumConst(self)
+    }
+}
+
+#[allow(unused)]
+pub enum Op {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual
+}
+
+impl Op {
+    pub fn impl_str(&self) -> &'static str {
+        match self {
+            Op::Add => "add",
+            Op::Subtract => "sub",
+            Op::Multiply => "mul",
+            Op::Divide => "div",
+            Op::Equal => "eq",
+            Op::NotEqual => "ne"
+        }
+    }
+
+    /// Comparisons give an `int` that is 1 if they hold and 0 otherwise, like in C.
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, Op::Equal | Op::NotEqual)
+    }
+}
+
+pub enum Node {
+    Tree(Tree),
+    FunDecl(FunDecl),
+    Decl(Declaration),
+    /// The span is where the assigned variable's name is written.
+    Assign(BindPoint<VarID>, Span, Box<Node>),
+    VarRef(BindPoint<VarID>, Span),
+    NumConst(NumConst),
+    /// The span is where the called function's name is written.
+    FunCall(Namespace, BindPoint<FunID>, Span, Vec<Node>),
+    BinOp(Op, Box<Node>, Box<Node>),
+    Return(Option<Box<Node>>),
+    /// `assert(cond, "message")` in a test function. The span is where `assert` is written.
+    Assert(Span, Box<Node>, Option<String>),
+    Empty
+}
+
+/// Prints the node as an S-expression, with IDs for bound names. See `to_sexpr` for one with
+/// names and types.
+impl Debug for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        sexpr::write_bare(self, f)
+    }
+}
+
+impl Node {
+    /// Calls `f` on this node and on every node below it, parents before their children.
+    pub fn visit<'n, F: FnMut(&'n Node)>(&'n self, f: &mut F) {
+        f(self);
+        match self {
+            Node::Tree(tree) => tree.children.iter().for_each(|child| child.visit(f)),
+            Node::FunDecl(fun) => fun.body.iter().for_each(|stmt| stmt.node.visit(f)),
+            Node::Decl(decl) => {
+                if let Some(expr) = &decl.expr {
+                    expr.visit(f);
+                }
+            }
+            Node::Assign(_, _, expr) => expr.visit(f),
+            Node::FunCall(_, _, _, args) => args.iter().for_each(|arg| arg.visit(f)),
+            Node::BinOp(_, lhs, rhs) => {
+                lhs.visit(f);
+                rhs.visit(f);
+            }
+            Node::Return(Some(expr)) => expr.visit(f),
+            Node::Assert(_, cond, _) => cond.visit(f),
+            Node::Return(None) | Node::VarRef(..) | Node::NumConst(_) | Node::Empty => {}
+        }
+    }
+
+    pub fn get_expr_type(&self, bindings: &Bindings) -> Type {
+        match &self {
+            Node::Tree(_) => { Type::Error }
+            Node::FunDecl(_) => {
+                Type::Error
+            }
+            Node::Decl(_) => { Type::Error }
+            Node::Assign(..) => { Type::Error }
+            Node::NumConst(num) => {
+                num.typ.clone()
+            }
+            Node::VarRef(point, _) => {
+                match point {
+                    BindPoint::Unbound(_) => Type::Error,
+                    BindPoint::BoundTo(bind_id) => bindings.get_var(*bind_id).typ.clone()
+                }
+            }
+            Node::FunCall(_, point, _, _) => {
+                match point {
+                    BindPoint::Unbound(_) => Type::Error,
+                    BindPoint::BoundTo(bind_id) => bindings.get_fun(*bind_id).return_type.clone()
+                }
+            }
+            Node::BinOp(op, lhs, _) => {
+                if op.is_comparison() { Type::Int32 } else { lhs.get_expr_type(bindings) }
+            }
+            Node::Return(_) => { Type::Error }
+            Node::Assert(..) => { Type::Error }
+            Node::Empty => { Type::Error }
+        }
+    }
+}
+
+pub type RNode = Result<Node, Diagnostic>;
\ No newline at end of file
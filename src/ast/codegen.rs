@@ -1,118 +1,195 @@
-use crate::bindings::{Bindings, FunBinding};
-use super::*;
-
-mod prelude;
-mod forward;
-
-pub use prelude::write_prelude;
-pub use forward::write_forward_declarations;
-
-/// Writes all parts of the function declaration, including the return type, parameter types, and
-/// parameter names, as well as the closing parenthesis. Does not write a brace or a semicolon,
-/// however.
-fn codegen_fun_decl<W: Write>(bindings: &Bindings, fun: &FunBinding, writer: &mut W) -> io::Result<()> {
-    writer.write_fmt(format_args!("{} {}(", fun.return_type, fun.output_name))?;
-
-    let mut generate_comma = false;
-    for param in &fun.args {
-        if generate_comma {
-            writer.write(b", ")?;
-        }
-
-        let binding = bindings.get_var(*param);
-        writer.write_fmt(format_args!("{} {}", binding.typ, binding.output_name))?;
-
-        generate_comma = true;
-    }
-    writer.write(b")")?;
-
-    Ok(())
-}
-
-pub fn codegen<W: Write>(bindings: &Bindings, node: &Node, writer: &mut W) -> io::Result<()> {
-    match node {
-        Node::FunDecl(f) => {
-            let fun = bindings.get_fun(f.bind_id);
-            codegen_fun_decl(bindings, fun, writer)?;
-
-            writer.write(b" {\n")?;
-
-            for s in &f.body {
-                codegen(bindings,s, writer)?;
-            }
-            writer.write(b"}\n")?;
-        }
-        Node::FunCall(_, point, args) => {
-            if let BindPoint::BoundTo(fun) = point {
-                let fun = bindings.get_fun(*fun);
-
-                writer.write_fmt(format_args!("{}(", fun.output_name))?;
-                let mut generate_comma = false;
-                for arg in args {
-                    if generate_comma { writer.write(b", ")?; }
-
-                    codegen(bindings, arg, writer)?;
-
-                    generate_comma = true;
-                }
-                writer.write(b");\n")?;
-            }
-        }
-        Node::Tree(tree) => {
-            for child in &tree.children {
-                codegen::<W>(bindings,child, writer)?;
-            }
-        }
-        Node::Decl(dec) => {
-            let binding = bindings.get_var(dec.bind_id);
-            writer.write_fmt(format_args!("{} {}", binding.typ, binding.output_name))?;
-
-            if let Some(expr) = &dec.expr {
-                writer.write(b" = ")?;
-                codegen(bindings, expr, writer)?;
-            }
-
-            writer.write(b";\n")?;
-        }
-        Node::Assign(bind, expr) => {
-            if let BindPoint::BoundTo(bind_id) = bind {
-                let binding = bindings.get_var(*bind_id);
-                writer.write_fmt(format_args!("{} = ", binding.output_name))?;
-                codegen(bindings, expr.as_ref(), writer)?;
-                writer.write(b";\n")?;
-            }
-            // TODO: Return an error, maybe...?
-        }
-        Node::NumConst(str) => {
-            writer.write_fmt(format_args!("{}", str.value_str))?;
-        }
-        Node::BinOp(op, lhs, rhs) => {
-            codegen_op(bindings, op, lhs, rhs, writer)?;
-        }
-        Node::VarRef(point) => {
-            if let BindPoint::BoundTo(bind_id) = point {
-                let binding = bindings.get_var(*bind_id);
-                writer.write_fmt(format_args!("{}", binding.output_name))?;
-            }
-            // TODO: Return an error, maybe...?
-        }
-        _ => {
-
-        }
-    }
-    Ok(())
-}
-
-fn codegen_op<W: Write>(bindings: &Bindings, op: &Op, lhs: &Box<Node>, rhs: &Box<Node>, writer: &mut W) -> io::Result<()> {
-    // Write the operator function name. This could even allow user-defined operators...
-    writer.write_fmt(format_args!("{}_op_{}(", lhs.get_expr_type(bindings), op.impl_str()))?;
-
-    // Write the operator arguments
-    codegen(bindings, lhs, writer)?;
-    writer.write(b", ")?;
-    codegen(bindings, rhs, writer)?;
-
-    writer.write(b")")?;
-
-    Ok(())
+use crate::bindings::{Bindings, FunBinding, VarBinding};
+use super::*;
+
+mod prelude;
+mod forward;
+mod header;
+mod reload;
+mod state;
+
+pub use prelude::{write_prelude, write_header_prelude};
+pub use forward::write_forward_declarations;
+pub use header::{include_guard, write_header};
+pub use reload::{LOADER_HEADER, LOADER_SOURCE, write_function_table, write_loader_header, write_loader_source};
+pub use state::{write_state, write_state_table};
+
+/// Settings that change the shape of the generated code, but not its meaning.
+pub struct Options {
+    /// Emit `#line` directives before each function and statement, so that C compiler errors and
+    /// debuggers point at the original `.pony.script` source instead of the generated code.
+    pub line_directives: bool,
+    /// Emit a table of all functions and script variables after the code, for the hot reload
+    /// loader.
+    pub function_table: bool
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            line_directives: cfg!(debug_assertions),
+            function_table: false
+        }
+    }
+}
+
+/// Writes a `#line` directive for the given span. When `source` is given, the directive also
+/// switches the file name that the C compiler reports.
+fn codegen_line<W: Write>(options: &Options, span: Span, source: Option<&str>, writer: &mut W) -> io::Result<()> {
+    if !options.line_directives {
+        return Ok(());
+    }
+
+    match source {
+        Some(source) => {
+            let escaped = source.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(writer, "#line {} \"{}\"", span.line, escaped)
+        }
+        None => writeln!(writer, "#line {}", span.line)
+    }
+}
+
+/// How generated code refers to a variable. Script variables are members of the state struct (see
+/// `write_state`), everything else is a C local.
+fn var_access(binding: &VarBinding) -> String {
+    if binding.state {
+        format!("ps_state->{}", binding.output_name)
+    }
+    else {
+        binding.output_name.clone()
+    }
+}
+
+/// Writes all parts of the function declaration, including the return type, parameter types, and
+/// parameter names, as well as the closing parenthesis. Does not write a brace or a semicolon,
+/// however.
+fn codegen_fun_decl<W: Write>(bindings: &Bindings, fun: &FunBinding, writer: &mut W) -> io::Result<()> {
+    writer.write_fmt(format_args!("{} {}(", fun.return_type, fun.output_name))?;
+
+    let mut generate_comma = false;
+    for param in &fun.args {
+        if generate_comma {
+            writer.write(b", ")?;
+        }
+
+        let binding = bindings.get_var(*param);
+        writer.write_fmt(format_args!("{} {}", binding.typ, binding.output_name))?;
+
+        generate_comma = true;
+    }
+    writer.write(b")")?;
+
+    Ok(())
+}
+
+pub fn codegen<W: Write>(bindings: &Bindings, options: &Options, node: &Node, writer: &mut W) -> io::Result<()> {
+    match node {
+        Node::FunDecl(f) => {
+            let fun = bindings.get_fun(f.bind_id);
+            if fun.test {
+                return Ok(());
+            }
+            codegen_line(options, f.span, None, writer)?;
+            codegen_fun_decl(bindings, fun, writer)?;
+
+            writer.write(b" {\n")?;
+
+            for s in &f.body {
+                codegen_line(options, s.span, None, writer)?;
+                codegen(bindings, options, &s.node, writer)?;
+
+                // Calls are also expressions, so they only get terminated as statements.
+                if let Node::FunCall(..) = s.node {
+                    writer.write_all(b";\n")?;
+                }
+            }
+            writer.write(b"}\n")?;
+        }
+        Node::FunCall(_, point, _, args) => {
+            if let BindPoint::BoundTo(fun) = point {
+                let fun = bindings.get_fun(*fun);
+
+                writer.write_fmt(format_args!("{}(", fun.output_name))?;
+                let mut generate_comma = false;
+                for arg in args {
+                    if generate_comma { writer.write(b", ")?; }
+
+                    codegen(bindings, options, arg, writer)?;
+
+                    generate_comma = true;
+                }
+                writer.write_all(b")")?;
+            }
+        }
+        Node::Tree(tree) => {
+            // Everything after this directive is attributed to the tree's source file; the
+            // directives for the individual statements then only need to update the line.
+            codegen_line(options, Span::new(1, 1), Some(&tree.source), writer)?;
+            for child in &tree.children {
+                // Script variables are written along with the state struct instead.
+                if let Node::Decl(_) = child {
+                    continue;
+                }
+                codegen::<W>(bindings, options, child, writer)?;
+            }
+        }
+        Node::Decl(dec) => {
+            let binding = bindings.get_var(dec.bind_id);
+            writer.write_fmt(format_args!("{} {}", binding.typ, binding.output_name))?;
+
+            if let Some(expr) = &dec.expr {
+                writer.write(b" = ")?;
+                codegen(bindings, options, expr, writer)?;
+            }
+
+            writer.write(b";\n")?;
+        }
+        Node::Assign(bind, _, expr) => {
+            if let BindPoint::BoundTo(bind_id) = bind {
+                let binding = bindings.get_var(*bind_id);
+                writer.write_fmt(format_args!("{} = ", var_access(binding)))?;
+                codegen(bindings, options, expr.as_ref(), writer)?;
+                writer.write(b";\n")?;
+            }
+            // TODO: Return an error, maybe...?
+        }
+        Node::NumConst(str) => {
+            writer.write_fmt(format_args!("{}", str.value_str))?;
+        }
+        Node::BinOp(op, lhs, rhs) => {
+            codegen_op(bindings, options, op, lhs, rhs, writer)?;
+        }
+        Node::Return(expr) => {
+            writer.write_all(b"return")?;
+            if let Some(expr) = expr {
+                writer.write_all(b" ")?;
+                codegen(bindings, options, expr, writer)?;
+            }
+            writer.write_all(b";\n")?;
+        }
+        Node::VarRef(point, _) => {
+            if let BindPoint::BoundTo(bind_id) = point {
+                let binding = bindings.get_var(*bind_id);
+                writer.write_fmt(format_args!("{}", var_access(binding)))?;
+            }
+            // TODO: Return an error, maybe...?
+        }
+        _ => {
+
+        }
+    }
+    Ok(())
+}
+
+fn codegen_op<W: Write>(bindings: &Bindings, options: &Options, op: &Op, lhs: &Node, rhs: &Node, writer: &mut W) -> io::Result<()> {
+    // Write the operator function name. This could even allow user-defined operators...
+    writer.write_fmt(format_args!("{}_op_{}(", lhs.get_expr_type(bindings), op.impl_str()))?;
+
+    // Write the operator arguments
+    codegen(bindings, options, lhs, writer)?;
+    writer.write(b", ")?;
+    codegen(bindings, options, rhs, writer)?;
+
+    writer.write(b")")?;
+
+    Ok(())
 }
\ No newline at end of file
@@ -1,14 +1,15 @@
-use std::io;
-use std::io::Write;
-use crate::bindings::Bindings;
-
-use super::*;
-
-pub fn write_forward_declarations<W: Write>(bindings: &Bindings, writer: &mut W) -> io::Result<()> {
-    for fun in bindings.fun_bindings() {
-        codegen_fun_decl(bindings, fun, writer)?;
-        writer.write(b";\n")?;
-    }
-
-    Ok(())
+use std::io;
+use std::io::Write;
+use crate::bindings::Bindings;
+
+use super::*;
+
+pub fn write_forward_declarations<W: Write>(bindings: &Bindings, writer: &mut W) -> io::Result<()> {
+    // Tests only run on the interpreter, so they are not part of the generated code.
+    for fun in bindings.fun_bindings().filter(|fun| !fun.test) {
+        codegen_fun_decl(bindings, fun, writer)?;
+        writer.write(b";\n")?;
+    }
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,51 @@
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use crate::bindings::{Bindings, mangle};
+
+use super::*;
+
+/// Builds an include guard from the file name of a header, e.g. `scripts.h` becomes
+/// `PONYSCRIPT_SCRIPTS_H`.
+pub fn include_guard(header: &Path) -> String {
+    let name = header.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+    let mut guard = String::from("PONYSCRIPT_");
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            guard.push(c.to_ascii_uppercase());
+        }
+        else {
+            guard.push('_');
+        }
+    }
+    guard
+}
+
+/// Writes a header declaring everything that engine code may use from the generated source: the
+/// struct of every node type, and every function.
+pub fn write_header<W: Write>(bindings: &Bindings, trees: &[Node], guard: &str, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "#ifndef {}", guard)?;
+    writeln!(writer, "#define {}", guard)?;
+    writeln!(writer)?;
+
+    write_header_prelude(writer)?;
+    writeln!(writer)?;
+
+    for tree in trees {
+        if let Node::Tree(tree) = tree {
+            // Node types do not have any members yet, so the structs stay opaque.
+            let name = mangle::mangle_node(tree.own_type);
+            writeln!(writer, "/* {} extends {} */", tree.own_type, tree.base_type)?;
+            writeln!(writer, "typedef struct {} {};", name, name)?;
+        }
+    }
+    writeln!(writer)?;
+
+    write_forward_declarations(bindings, writer)?;
+    writeln!(writer)?;
+
+    writeln!(writer, "#endif")?;
+
+    Ok(())
+}
@@ -1,18 +1,28 @@
-use std::io;
-use std::io::Write;
-
-
-pub fn write_prelude<W: Write>(writer: &mut W) -> io::Result<()> {
-    let prelude =
-br##"#include <stdint.h>
-#include <stdlib.h>
-#include <stdio.h>
-
-#define int32_t_op_add(a, b) ((a) + (b))
-#define float_op_add(a, b) ((a) + (b))
-"##;
-
-    writer.write(prelude)?;
-
-    Ok(())
+use std::io;
+use std::io::Write;
+
+
+/// The headers needed by the declarations in a generated header file.
+pub fn write_header_prelude<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(b"#include <stdint.h>\n")
+}
+
+pub fn write_prelude<W: Write>(writer: &mut W) -> io::Result<()> {
+    let prelude =
+br##"#include <stdint.h>
+#include <stdlib.h>
+#include <stdio.h>
+
+/* Integers wrap around on overflow, as they do in the interpreter and the VM. */
+#define int32_t_op_add(a, b) ((int32_t) ((uint32_t) (a) + (uint32_t) (b)))
+#define float_op_add(a, b) ((a) + (b))
+#define int32_t_op_eq(a, b) ((a) == (b))
+#define float_op_eq(a, b) ((a) == (b))
+#define int32_t_op_ne(a, b) ((a) != (b))
+#define float_op_ne(a, b) ((a) != (b))
+"##;
+
+    writer.write_all(prelude)?;
+
+    Ok(())
 }
\ No newline at end of file
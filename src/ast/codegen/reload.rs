@@ -0,0 +1,213 @@
+use std::io;
+use std::io::Write;
+use crate::bindings::Bindings;
+
+/// The file names of the loader written by [`write_loader_header`] and [`write_loader_source`].
+pub const LOADER_HEADER: &str = "ponyscript_loader.h";
+pub const LOADER_SOURCE: &str = "ponyscript_loader.c";
+
+/// Writes the table that the hot reload loader looks functions up in: the PonyScript name and
+/// address of every function defined by the scripts, ending with a null entry.
+pub fn write_function_table<W: Write>(bindings: &Bindings, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "struct ps_function {{ const char *name; void (*address)(void); }};")?;
+    writeln!(writer, "const struct ps_function ps_function_table[] = {{")?;
+    for fun in bindings.fun_bindings().filter(|fun| !fun.native && !fun.test) {
+        writeln!(writer, "\t{{ \"{}\", (void (*)(void)) {} }},", fun.source_name(), fun.output_name)?;
+    }
+    writeln!(writer, "\t{{ 0, 0 }}")?;
+    writeln!(writer, "}};")
+}
+
+pub fn write_loader_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    let header =
+br##"#ifndef PONYSCRIPT_LOADER_H
+#define PONYSCRIPT_LOADER_H
+
+#include <stddef.h>
+
+/*
+ * Hot reloading for libraries built by `ponyscript watch`.
+ *
+ * Every build is written to a new versioned library, and the `.current` file next to the library
+ * names the newest one. The loader opens that version and looks functions up in its table. Calling
+ * ps_loader_reload switches to a newer version when there is one.
+ *
+ * Script variables live in a block owned by the loader rather than in the library, so that they
+ * survive a switch. When a new version is loaded, it gets a fresh block: every variable that has
+ * the same name and type as in the old version takes the old value along, and variables that are
+ * new (or changed type) start at their initial value. The old block is freed with the old version.
+ */
+
+struct ps_function {
+	const char *name;
+	void (*address)(void);
+};
+
+struct ps_state_member {
+	const char *name;
+	const char *type;
+	size_t offset;
+	size_t size;
+};
+
+typedef void (*ps_address)(void);
+
+typedef struct ps_loader {
+	/* The `.current` file written by `ponyscript watch`. */
+	const char *current_path;
+	/* The file name of the loaded version. */
+	char loaded[1024];
+	void *handle;
+	const struct ps_function *table;
+	/* The script variables of the loaded version, and where each of them is kept. Both are null
+	 * when the scripts have no variables. */
+	void *state;
+	const struct ps_state_member *members;
+} ps_loader;
+
+/* Loads the newest version. Returns 0 on success. */
+int ps_loader_open(ps_loader *loader, const char *current_path);
+
+/* Switches to a newer version if there is one. Returns 1 after switching, 0 when there was nothing
+ * new, and -1 when the new version could not be loaded (the old one then stays in use). Function
+ * addresses found before a switch must not be used after it. */
+int ps_loader_reload(ps_loader *loader);
+
+/* Finds a function by its PonyScript name, e.g. "Player.jump(float)". Returns 0 if there is none. */
+ps_address ps_loader_find(const ps_loader *loader, const char *name);
+
+/* Finds a script variable by its PonyScript name, e.g. "Player.health". Returns 0 if there is none.
+ * Like function addresses, the pointer must not be used after a switch. */
+void *ps_loader_state(const ps_loader *loader, const char *name);
+
+void ps_loader_close(ps_loader *loader);
+
+#endif
+"##;
+
+    writer.write_all(header)
+}
+
+pub fn write_loader_source<W: Write>(writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "#include \"{}\"", LOADER_HEADER)?;
+
+    let source =
+br##"
+#include <dlfcn.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static int ps_read_current(const char *current_path, char *name, size_t size) {
+	FILE *file = fopen(current_path, "r");
+	if (!file) return -1;
+
+	size_t length = fread(name, 1, size - 1, file);
+	fclose(file);
+	while (length > 0 && (name[length - 1] == '\n' || name[length - 1] == '\r')) length--;
+	name[length] = '\0';
+
+	return length > 0 ? 0 : -1;
+}
+
+static const struct ps_state_member *ps_find_member(const struct ps_state_member *members, const char *name) {
+	for (const struct ps_state_member *member = members; member && member->name; member++) {
+		if (strcmp(member->name, name) == 0) return member;
+	}
+	return 0;
+}
+
+/* Gives a newly opened version a state block of its own, starting from its initial values and
+ * taking over the values of the variables it shares with the loaded version. */
+static int ps_attach_state(ps_loader *loader, void *handle, void **state, const struct ps_state_member **members) {
+	*state = 0;
+	*members = 0;
+
+	void **pointer = (void **) dlsym(handle, "ps_state");
+	if (!pointer) return 0;
+	const struct ps_state_member *new_members = (const struct ps_state_member *) dlsym(handle, "ps_state_members");
+	const size_t *size = (const size_t *) dlsym(handle, "ps_state_size");
+	if (!new_members || !size) return -1;
+
+	void *block = malloc(*size ? *size : 1);
+	if (!block) return -1;
+	memcpy(block, *pointer, *size);
+
+	for (const struct ps_state_member *member = new_members; member->name; member++) {
+		const struct ps_state_member *old = ps_find_member(loader->members, member->name);
+		if (old && old->size == member->size && strcmp(old->type, member->type) == 0) {
+			memcpy((char *) block + member->offset, (char *) loader->state + old->offset, member->size);
+		}
+	}
+
+	*pointer = block;
+	*state = block;
+	*members = new_members;
+	return 0;
+}
+
+int ps_loader_reload(ps_loader *loader) {
+	char name[sizeof loader->loaded];
+	if (ps_read_current(loader->current_path, name, sizeof name) != 0) return -1;
+	if (loader->handle && strcmp(name, loader->loaded) == 0) return 0;
+
+	/* The versioned library lives next to the `.current` file. */
+	char path[sizeof loader->loaded + 4096];
+	const char *slash = strrchr(loader->current_path, '/');
+	int dir_length = slash ? (int) (slash - loader->current_path + 1) : 0;
+	snprintf(path, sizeof path, "%.*s%s", dir_length, loader->current_path, name);
+
+	void *handle = dlopen(path, RTLD_NOW | RTLD_LOCAL);
+	if (!handle) return -1;
+	const struct ps_function *table = (const struct ps_function *) dlsym(handle, "ps_function_table");
+	if (!table) {
+		dlclose(handle);
+		return -1;
+	}
+
+	void *state;
+	const struct ps_state_member *members;
+	if (ps_attach_state(loader, handle, &state, &members) != 0) {
+		dlclose(handle);
+		return -1;
+	}
+
+	void *old = loader->handle;
+	free(loader->state);
+	loader->handle = handle;
+	loader->table = table;
+	loader->state = state;
+	loader->members = members;
+	strcpy(loader->loaded, name);
+	if (old) dlclose(old);
+
+	return 1;
+}
+
+int ps_loader_open(ps_loader *loader, const char *current_path) {
+	memset(loader, 0, sizeof *loader);
+	loader->current_path = current_path;
+	return ps_loader_reload(loader) < 0 ? -1 : 0;
+}
+
+ps_address ps_loader_find(const ps_loader *loader, const char *name) {
+	for (const struct ps_function *entry = loader->table; entry && entry->name; entry++) {
+		if (strcmp(entry->name, name) == 0) return entry->address;
+	}
+	return 0;
+}
+
+void *ps_loader_state(const ps_loader *loader, const char *name) {
+	const struct ps_state_member *member = ps_find_member(loader->members, name);
+	return member ? (char *) loader->state + member->offset : 0;
+}
+
+void ps_loader_close(ps_loader *loader) {
+	free(loader->state);
+	if (loader->handle) dlclose(loader->handle);
+	memset(loader, 0, sizeof *loader);
+}
+"##;
+
+    writer.write_all(source)
+}
@@ -0,0 +1,71 @@
+use std::io;
+use std::io::Write;
+use crate::bindings::{Bindings, mangle, VarID};
+
+use super::*;
+
+/// The script variables of all trees, with the number each is given, if any.
+fn state_vars(trees: &[Node]) -> Vec<(VarID, Option<PoolS>)> {
+    let mut vars = vec![];
+    for tree in trees {
+        let Node::Tree(tree) = tree else { continue; };
+        for child in &tree.children {
+            if let Node::Decl(decl) = child {
+                let value = match decl.expr.as_deref() {
+                    Some(Node::NumConst(num)) => Some(num.value_str),
+                    _ => None
+                };
+                vars.push((decl.bind_id, value));
+            }
+        }
+    }
+    vars
+}
+
+/// Writes the struct holding every script variable, and the `ps_state` pointer that functions
+/// reach them through. The pointer starts out at a struct with the initial values, and the hot
+/// reload loader points it at memory of its own. Writes nothing if no script has variables.
+pub fn write_state<W: Write>(bindings: &Bindings, trees: &[Node], writer: &mut W) -> io::Result<()> {
+    let vars = state_vars(trees);
+    if vars.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(writer, "struct ps_state {{")?;
+    for (id, _) in &vars {
+        let binding = bindings.get_var(*id);
+        writeln!(writer, "\t{} {};", binding.typ, binding.output_name)?;
+    }
+    writeln!(writer, "}};")?;
+
+    writeln!(writer, "static struct ps_state ps_initial_state = {{")?;
+    for (id, value) in &vars {
+        if let Some(value) = value {
+            writeln!(writer, "\t.{} = {},", bindings.get_var(*id).output_name, value)?;
+        }
+    }
+    writeln!(writer, "}};")?;
+    writeln!(writer, "struct ps_state *ps_state = &ps_initial_state;")
+}
+
+/// Writes the table that the hot reload loader carries script variables over with: the PonyScript
+/// name, type and place of every member of the state struct, ending with a null entry.
+pub fn write_state_table<W: Write>(bindings: &Bindings, trees: &[Node], writer: &mut W) -> io::Result<()> {
+    let vars = state_vars(trees);
+    if vars.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(writer, "#include <stddef.h>")?;
+    writeln!(writer, "struct ps_state_member {{ const char *name; const char *type; size_t offset; size_t size; }};")?;
+    writeln!(writer, "const struct ps_state_member ps_state_members[] = {{")?;
+    for (id, _) in &vars {
+        let binding = bindings.get_var(*id);
+        let name = mangle::demangle(&binding.output_name).unwrap_or_else(|| binding.source_name());
+        writeln!(writer, "\t{{ \"{}\", \"{}\", offsetof(struct ps_state, {}), sizeof({}) }},",
+                 name, binding.typ.source_name(), binding.output_name, binding.typ)?;
+    }
+    writeln!(writer, "\t{{ 0, 0, 0, 0 }}")?;
+    writeln!(writer, "}};")?;
+    writeln!(writer, "const size_t ps_state_size = sizeof(struct ps_state);")
+}
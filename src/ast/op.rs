@@ -1,5 +1,9 @@
-use super::*;
-
-pub fn add(lhs: Node, rhs: Node) -> RNode {
-    Ok(Node::BinOp(Op::Add, Box::new(lhs), Box::new(rhs)))
-}
\ No newline at end of file
+use super::*;
+
+pub fn add(lhs: Node, rhs: Node) -> RNode {
+    Ok(Node::BinOp(Op::Add, Box::new(lhs), Box::new(rhs)))
+}
+
+pub fn compare(op: Op, lhs: Node, rhs: Node) -> RNode {
+    Ok(Node::BinOp(op, Box::new(lhs), Box::new(rhs)))
+}
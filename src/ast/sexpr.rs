@@ -0,0 +1,255 @@
+//! Prints trees as S-expressions, for `emit --stage ast` and for snapshot tests.
+//!
+//! Every node is a list that starts with its kind. Functions, and the statements in them, go on
+//! lines of their own; expressions stay on the line of their statement. Bound names are followed
+//! by the ID they are bound to, and given bindings, by their type. Spans are written `@line:column`.
+//!
+//! ```text
+//! (tree "calc.pony.script" Calc extends Node
+//!   (fun add f2 @3:1 (params (a v0 int) (b v1 int)) (returns int)
+//!     (return @4:2 (+ int (var a v0 int @4:9) (var b v1 int @4:13)))))
+//! ```
+
+use std::fmt::Write;
+use crate::bindings::Bindings;
+use super::*;
+
+/// A type as an S-expression: a plain name, or a list for types made from other types.
+pub fn type_sexpr(typ: &Type) -> String {
+    match typ {
+        Type::Optional(inner) => format!("(? {})", type_sexpr(inner)),
+        Type::Deref(inner) => format!("(+ {})", type_sexpr(inner)),
+        Type::Parameterized(id, others) => {
+            let others: Vec<String> = others.iter().map(type_sexpr).collect();
+            format!("({} {})", id, others.join(" "))
+        }
+        _ => typ.source_name()
+    }
+}
+
+fn op_str(op: &Op) -> &'static str {
+    match op {
+        Op::Add => "+",
+        Op::Subtract => "-",
+        Op::Multiply => "*",
+        Op::Divide => "/",
+        Op::Equal => "==",
+        Op::NotEqual => "!="
+    }
+}
+
+struct Printer<'b> {
+    /// Without bindings, only IDs can be printed for bound names, and no types.
+    bindings: Option<&'b Bindings>,
+    out: String
+}
+
+impl Printer<'_> {
+    fn span(&mut self, span: Span) {
+        write!(self.out, " @{}:{}", span.line, span.column).unwrap();
+    }
+
+    fn typ(&mut self, typ: &Type) {
+        write!(self.out, " {}", type_sexpr(typ)).unwrap();
+    }
+
+    fn var(&mut self, id: VarID) {
+        match self.bindings {
+            Some(bindings) => {
+                let var = bindings.get_var(id);
+                write!(self.out, "{} {} {}", var.name, id, type_sexpr(&var.typ)).unwrap();
+            }
+            None => write!(self.out, "{}", id).unwrap()
+        }
+    }
+
+    fn var_point(&mut self, point: &BindPoint<VarID>) {
+        match point {
+            BindPoint::BoundTo(id) => self.var(*id),
+            BindPoint::Unbound(name) => write!(self.out, "{} unbound", name).unwrap()
+        }
+    }
+
+    fn newline(&mut self, depth: usize) {
+        self.out.push('\n');
+        self.out.push_str(&"  ".repeat(depth));
+    }
+
+    fn node(&mut self, node: &Node, span: Option<Span>, depth: usize) {
+        match node {
+            Node::Tree(tree) => {
+                write!(self.out, "(tree {:?} {} extends {}", tree.source, tree.own_type, tree.base_type).unwrap();
+                for child in &tree.children {
+                    self.newline(depth + 1);
+                    self.node(child, None, depth + 1);
+                }
+                self.out.push(')');
+            }
+            Node::FunDecl(f) => self.fun(f, depth),
+            Node::Decl(decl) => {
+                self.out.push_str("(let ");
+                self.var(decl.bind_id);
+                if let Some(span) = span {
+                    self.span(span);
+                }
+                if let Some(expr) = &decl.expr {
+                    self.out.push(' ');
+                    self.node(expr, None, depth);
+                }
+                self.out.push(')');
+            }
+            Node::Assign(point, name_span, expr) => {
+                self.out.push_str("(set ");
+                self.var_point(point);
+                self.span(*name_span);
+                self.out.push(' ');
+                self.node(expr, None, depth);
+                self.out.push(')');
+            }
+            Node::VarRef(point, span) => {
+                self.out.push_str("(var ");
+                self.var_point(point);
+                self.span(*span);
+                self.out.push(')');
+            }
+            Node::NumConst(num) => {
+                write!(self.out, "(num {}", num.value_str).unwrap();
+                self.typ(&num.typ);
+                self.out.push(')');
+            }
+            Node::FunCall(namespace, point, name_span, args) => {
+                self.out.push_str("(call ");
+                match point {
+                    BindPoint::BoundTo(id) => match self.bindings {
+                        Some(bindings) => {
+                            let fun = bindings.get_fun(*id);
+                            write!(self.out, "{}{} {}", fun.namespace, fun.name, id).unwrap();
+                            self.typ(&fun.return_type);
+                        }
+                        None => write!(self.out, "{}", id).unwrap()
+                    },
+                    BindPoint::Unbound(name) => write!(self.out, "{}{} unbound", namespace, name).unwrap()
+                }
+                self.span(*name_span);
+                for arg in args {
+                    self.out.push(' ');
+                    self.node(arg, None, depth);
+                }
+                self.out.push(')');
+            }
+            Node::BinOp(op, lhs, rhs) => {
+                write!(self.out, "({}", op_str(op)).unwrap();
+                if let Some(bindings) = self.bindings {
+                    self.typ(&node.get_expr_type(bindings));
+                }
+                self.out.push(' ');
+                self.node(lhs, None, depth);
+                self.out.push(' ');
+                self.node(rhs, None, depth);
+                self.out.push(')');
+            }
+            Node::Return(expr) => {
+                self.out.push_str("(return");
+                if let Some(span) = span {
+                    self.span(span);
+                }
+                if let Some(expr) = expr {
+                    self.out.push(' ');
+                    self.node(expr, None, depth);
+                }
+                self.out.push(')');
+            }
+            Node::Assert(assert_span, cond, message) => {
+                self.out.push_str("(assert");
+                self.span(*assert_span);
+                self.out.push(' ');
+                self.node(cond, None, depth);
+                if let Some(message) = message {
+                    write!(self.out, " {:?}", message).unwrap();
+                }
+                self.out.push(')');
+            }
+            Node::Empty => self.out.push_str("(empty)")
+        }
+    }
+
+    fn fun(&mut self, f: &FunDecl, depth: usize) {
+        match self.bindings {
+            Some(bindings) => {
+                let fun = bindings.get_fun(f.bind_id);
+                let kind = if fun.test { "test" } else { "fun" };
+                write!(self.out, "({} {} {}", kind, fun.name, f.bind_id).unwrap();
+                self.span(f.span);
+                self.out.push_str(" (params");
+                for arg in &fun.args {
+                    self.out.push_str(" (");
+                    self.var(*arg);
+                    self.out.push(')');
+                }
+                self.out.push_str(") (returns");
+                self.typ(&fun.return_type);
+                self.out.push(')');
+            }
+            None => {
+                write!(self.out, "(fun {}", f.bind_id).unwrap();
+                self.span(f.span);
+            }
+        }
+
+        for stmt in &f.body {
+            self.newline(depth + 1);
+            self.node(&stmt.node, Some(stmt.span), depth + 1);
+        }
+        self.out.push(')');
+    }
+}
+
+/// Prints a tree, or any node in it, with the names and types of everything bound.
+pub fn to_sexpr(bindings: &Bindings, node: &Node) -> String {
+    let mut printer = Printer { bindings: Some(bindings), out: String::new() };
+    printer.node(node, None, 0);
+    printer.out
+}
+
+/// Prints a node with IDs in place of bound names, and without types.
+pub(super) fn write_bare(node: &Node, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let mut printer = Printer { bindings: None, out: String::new() };
+    printer.node(node, None, 0);
+    f.write_str(&printer.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{Compiler, Output};
+    use super::*;
+
+    #[test]
+    fn prints_every_node() {
+        let source = "extends Node as Calc\n\nfun add(a: int, b: int) -> int:\n\treturn a + b\n\nfun f(o: ?Array[int, +Node]):\n\tlet x = 1\n\tlet y: int\n\ty = add(2, 3) + 1\n\tx = x + 2\n\treturn\n\ntest fun t():\n\tassert(add(1, 1) == 2, \"sum\")\n";
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_str("calc.pony.script", source).unwrap();
+
+        let tree = &compiler.trees()[0];
+        let parsed = to_sexpr(compiler.bindings(), tree);
+        assert!(parsed.contains("(var a v0 int @4:9)"), "{}", parsed);
+        assert!(parsed.contains("(call Calc.add unbound @9:6 (num 2 <numeric>) (num 3 <numeric>))"), "{}", parsed);
+
+        compiler.typecheck().unwrap();
+        let tree = &compiler.trees()[0];
+        let expected = "\
+(tree \"calc.pony.script\" Calc extends Node
+  (fun add f2 @3:1 (params (a v0 int) (b v1 int)) (returns int)
+    (return @4:2 (+ int (var a v0 int @4:9) (var b v1 int @4:13))))
+  (fun f f4 @6:1 (params (o v3 (? (Array int (+ Node))))) (returns void)
+    (let x v5 float @7:2 (num 1 float))
+    (let y v6 int @8:2)
+    (set y v6 int @9:2 (+ int (call Calc.add f2 int @9:6 (num 2 int) (num 3 int)) (num 1 int)))
+    (set x v5 float @10:2 (+ float (var x v5 float @10:6) (num 2 float)))
+    (return @11:2))
+  (test t f7 @13:1 (params) (returns void)
+    (assert @14:2 (== int (call Calc.add f2 int @14:9 (num 1 int) (num 1 int)) (num 2 int)) \"sum\")))";
+        assert_eq!(to_sexpr(compiler.bindings(), tree), expected);
+
+        assert_eq!(format!("{:?}", tree).lines().nth(2), Some("    (return @4:2 (+ (var v0 @4:9) (var v1 @4:13))))"));
+    }
+}
@@ -1,166 +1,414 @@
-use crate::ast::{BindPoint, Node, Type};
-use crate::bindings::{Bindings, VarID};
-
-pub fn type_match_var(var_type: &mut Type, expr_type: &Type) -> bool {
-    match var_type {
-        Type::Unset => {
-            match expr_type {
-                Type::UnspecificNumeric => {
-                    *var_type = Type::Float;
-                    true
-                }
-                _ => {
-                    *var_type = expr_type.clone();
-                    true
-                }
-            }
-        }
-        Type::Optional(inner) => {
-            if let Type::Optional(expr_inner) = expr_type {
-                return type_match_var(inner, expr_inner);
-            }
-            else {
-                return type_match_var(inner, expr_type);
-            }
-        }
-        Type::Deref(inner) => {
-            if let Type::Deref(expr_inner) = expr_type {
-                return type_match_var(inner, expr_inner);
-            }
-            else {
-                return type_match_var(inner, expr_type);
-            }
-        }
-        _ => {
-            if let Type::UnspecificNumeric = expr_type {
-                return var_type.is_specific_numeric();
-            }
-            return var_type == expr_type
-        }
-    }
-}
-
-fn propagate_numeric(node: &mut Node, typ: &Type) {
-    match node {
-        Node::NumConst(num) => {
-            if num.typ == Type::UnspecificNumeric {
-                num.typ = typ.clone();
-            }
-        }
-        Node::BinOp(_, lhs, rhs) => {
-            propagate_numeric(rhs, typ);
-            propagate_numeric(lhs, typ);
-        }
-        Node::FunCall(_, _, args) => {
-            for arg in args {
-                propagate_numeric(arg, typ);
-            }
-        }
-        _ => {}
-    }
-}
-
-fn typecheck_assignment(bindings: &mut Bindings, expr: &mut Node, id: VarID) -> Result<Type, String> {
-    let expr_type = typecheck(bindings, expr)?;
-    let bound = bindings.get_var_mut(id);
-    if type_match_var(&mut bound.typ, &expr_type) {
-        // Var is matched to type, try propagating type to RHS
-        if bound.typ.is_specific_numeric() && expr_type == Type::UnspecificNumeric {
-            propagate_numeric(expr, &bound.typ);
-        }
-
-        return Ok(Type::Error); // Not an expression
-    }
-    return Err(String::from("Could not match types"));
-}
-
-pub fn typecheck<'a>(bindings: &mut Bindings, node: &mut Node) -> Result<Type, String> {
-    match node {
-        Node::Tree(nodes) => {
-            for node in nodes.children.iter_mut() {
-                typecheck(bindings, node)?;
-            }
-            return Ok(Type::Error);
-        }
-        Node::FunDecl(f) => {
-            for node in f.body.iter_mut() {
-                typecheck(bindings, node)?;
-            }
-            return Ok(Type::Error);
-        }
-        Node::Decl(decl) => {
-
-            match &mut decl.expr {
-                Some(expr) => {
-                    return typecheck_assignment(bindings, expr, decl.bind_id);
-                }
-                None => { }
-            }
-        }
-        Node::Assign(bind, expr) => {
-            let _expr_type = typecheck(bindings, expr)?;
-            // TODO: Re-update bindings
-
-            match bind {
-                BindPoint::Unbound(_) => {
-                    return Err(String::from("Unbound ID"));
-                }
-                BindPoint::BoundTo(id) => {
-                    return typecheck_assignment(bindings, expr, *id);
-                }
-            }
-        }
-        Node::NumConst(num) => {
-            return Ok(num.typ.clone());
-        }
-        Node::VarRef(point) => {
-            match point {
-                BindPoint::Unbound(_) => {
-                    return Err(String::from("Unbound ID"));
-                }
-                BindPoint::BoundTo(id) => {
-                    return Ok(bindings.get_var(*id).typ.clone());
-                }
-            }
-        }
-        Node::BinOp(_, lhs, rhs) => {
-            let mut left = typecheck(bindings, lhs)?;
-            let mut right = typecheck(bindings, rhs)?;
-
-            if left == Type::UnspecificNumeric && right.is_specific_numeric() {
-                propagate_numeric(lhs, &right);
-                left = right.clone();
-            }
-            else if right == Type::UnspecificNumeric && left.is_specific_numeric() {
-                propagate_numeric(rhs, &left);
-                right = left.clone();
-            }
-
-            if left == right {
-                return Ok(left);
-            }
-            return Err(String::from("Could not match types in binary expression"));
-        }
-        Node::FunCall(namespace, point, args) => {
-            for arg in args.iter_mut() {
-                typecheck(bindings, arg)?;
-            }
-            match point {
-                BindPoint::Unbound(name) => {
-                    let binding = bindings
-                        .find_fun_from_compat_nodes(*namespace,*name, args)
-                        .ok_or(format!("In call to {}, could not find matching arg list", name))?;
-
-                    point.bind_to(binding);
-
-                    return Ok(bindings.get_fun(binding).return_type.clone());
-                }
-                BindPoint::BoundTo(id) => {
-                    return Ok(bindings.get_fun(*id).return_type.clone());
-                }
-            }
-        }
-        Node::Empty => {}
-    }
-    return Ok(Type::Error);
+use std::collections::{BTreeSet, HashMap, HashSet};
+use crate::ast::{BindPoint, FunDecl, Node, Span, Type};
+use crate::bindings::{Bindings, VarID};
+use crate::diagnostic::Diagnostic;
+
+/// Numeric variables declared without a type, whose type is decided by how the function uses them.
+///
+/// While a function is checked the first time, such a variable has the type `UnspecificNumeric`,
+/// just like a literal. Every use that needs a specific type is recorded, and variables whose
+/// values flow into each other are joined, since they must end up with the same type. Afterwards
+/// each group takes the type its uses agree on, or `Float` if nothing needs a specific type, and
+/// the function is checked again with those types.
+#[derive(Default)]
+struct Inference {
+    /// The variable each pending variable was joined to, if any. Following the chain ends at the
+    /// variable that stands for the whole group.
+    joined: HashMap<VarID, VarID>,
+    pending: BTreeSet<VarID>,
+    /// The uses that need a specific type, in the order they were found.
+    uses: Vec<(VarID, Span, Type)>
+}
+
+impl Inference {
+    fn group(&self, mut id: VarID) -> VarID {
+        while let Some(next) = self.joined.get(&id) {
+            id = *next;
+        }
+        id
+    }
+
+    fn join(&mut self, a: VarID, b: VarID) {
+        let (a, b) = (self.group(a), self.group(b));
+        if a != b {
+            self.joined.insert(a, b);
+        }
+    }
+
+    /// The pending variables an expression of type `UnspecificNumeric` is made of.
+    fn pending_in(&self, node: &Node) -> Vec<VarID> {
+        match node {
+            Node::VarRef(BindPoint::BoundTo(id), _) if self.pending.contains(id) => vec![*id],
+            Node::BinOp(_, lhs, rhs) => {
+                let mut ids = self.pending_in(lhs);
+                ids.extend(self.pending_in(rhs));
+                ids
+            }
+            _ => vec![]
+        }
+    }
+
+    /// Joins the pending variables in an expression with each other, and with `id` if given.
+    fn join_all(&mut self, id: Option<VarID>, node: &Node) {
+        let mut ids = self.pending_in(node);
+        ids.extend(id);
+        for pair in ids.windows(2) {
+            self.join(pair[0], pair[1]);
+        }
+    }
+
+    /// Gives every group of pending variables its type, or fails if the uses of a group disagree.
+    fn resolve(&self, bindings: &mut Bindings) -> Result<(), Diagnostic> {
+        let groups: BTreeSet<VarID> = self.pending.iter().map(|id| self.group(*id)).collect();
+        for group in groups {
+            let uses: Vec<&(VarID, Span, Type)> = self.uses.iter().filter(|(id, _, _)| self.group(*id) == group).collect();
+            let typ = uses.first().map_or(Type::Float, |(_, _, typ)| typ.clone());
+
+            if let Some((id, span, _)) = uses.iter().find(|(_, _, other)| *other != typ) {
+                let mut message = format!("cannot infer a type for {}, its uses need different types", bindings.get_var(*id).name);
+                for (id, span, typ) in &uses {
+                    message += &format!("\n  {} used as {} at {}:{}", bindings.get_var(*id).name, typ.source_name(), span.line, span.column);
+                }
+                return Err(Diagnostic::spanned(*span, message));
+            }
+
+            for id in &self.pending {
+                if self.group(*id) == group {
+                    bindings.get_var_mut(*id).typ = typ.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn type_match_var(var_type: &mut Type, expr_type: &Type) -> bool {
+    match var_type {
+        Type::Unset => {
+            match expr_type {
+                Type::UnspecificNumeric => {
+                    *var_type = Type::Float;
+                    true
+                }
+                _ => {
+                    *var_type = expr_type.clone();
+                    true
+                }
+            }
+        }
+        Type::Optional(inner) => {
+            if let Type::Optional(expr_inner) = expr_type {
+                return type_match_var(inner, expr_inner);
+            }
+            else {
+                return type_match_var(inner, expr_type);
+            }
+        }
+        Type::Deref(inner) => {
+            if let Type::Deref(expr_inner) = expr_type {
+                return type_match_var(inner, expr_inner);
+            }
+            else {
+                return type_match_var(inner, expr_type);
+            }
+        }
+        _ => {
+            if let Type::UnspecificNumeric = expr_type {
+                return var_type.is_specific_numeric();
+            }
+            return var_type == expr_type
+        }
+    }
+}
+
+fn propagate_numeric(inference: &mut Inference, node: &mut Node, typ: &Type) {
+    match node {
+        Node::NumConst(num) => {
+            if num.typ == Type::UnspecificNumeric {
+                num.typ = typ.clone();
+            }
+        }
+        Node::VarRef(BindPoint::BoundTo(id), span) => {
+            if inference.pending.contains(id) {
+                inference.uses.push((*id, *span, typ.clone()));
+            }
+        }
+        Node::BinOp(_, lhs, rhs) => {
+            propagate_numeric(inference, rhs, typ);
+            propagate_numeric(inference, lhs, typ);
+        }
+        Node::FunCall(_, _, _, args) => {
+            for arg in args {
+                propagate_numeric(inference, arg, typ);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Gives `Float` to the literals in an expression that nothing else decides the type of. If the
+/// expression has pending variables, they are joined instead, and the second check decides.
+fn default_numeric(inference: &mut Inference, node: &mut Node) {
+    if inference.pending_in(node).is_empty() {
+        propagate_numeric(inference, node, &Type::Float);
+    }
+    else {
+        inference.join_all(None, node);
+    }
+}
+
+fn typecheck_assignment(bindings: &mut Bindings, inference: &mut Inference, expr: &mut Node, id: VarID, span: Span) -> Result<Type, Diagnostic> {
+    let expr_type = check(bindings, inference, expr)?;
+    let bound = bindings.get_var_mut(id);
+
+    if inference.pending.contains(&id) {
+        if expr_type == Type::UnspecificNumeric {
+            inference.join_all(Some(id), expr);
+            return Ok(Type::Error);
+        }
+        if expr_type.is_specific_numeric() {
+            inference.uses.push((id, span, expr_type));
+            return Ok(Type::Error);
+        }
+        return Err(String::from("Could not match types").into());
+    }
+
+    // A variable declared without a type that is only given literals so far waits for its uses.
+    if bound.typ == Type::Unset && expr_type == Type::UnspecificNumeric {
+        bound.typ = Type::UnspecificNumeric;
+        inference.pending.insert(id);
+        inference.join_all(Some(id), expr);
+        return Ok(Type::Error);
+    }
+
+    if type_match_var(&mut bound.typ, &expr_type) {
+        // Var is matched to type, try propagating type to RHS
+        if bound.typ.is_specific_numeric() && expr_type == Type::UnspecificNumeric {
+            let typ = bound.typ.clone();
+            propagate_numeric(inference, expr, &typ);
+        }
+
+        return Ok(Type::Error); // Not an expression
+    }
+    return Err(String::from("Could not match types").into());
+}
+
+fn typecheck_return(bindings: &mut Bindings, inference: &mut Inference, expr: &mut Option<Box<Node>>, return_type: &Type) -> Result<(), Diagnostic> {
+    match expr {
+        None => {
+            if *return_type != Type::Void {
+                return Err(String::from("Missing return value in function that returns a value").into());
+            }
+        }
+        Some(expr) => {
+            let expr_type = check(bindings, inference, expr)?;
+            if *return_type == Type::Void || !return_type.eq_or_may_coerce(&expr_type) {
+                return Err(String::from("Returned value does not match the return type").into());
+            }
+            if expr_type == Type::UnspecificNumeric {
+                propagate_numeric(inference, expr, return_type);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fails if a local is read before it is given a value. Function bodies have no branches, so a
+/// local has a value after the first statement that assigns one.
+fn check_assigned(bindings: &Bindings, f: &FunDecl) -> Result<(), Diagnostic> {
+    let mut unassigned = HashSet::new();
+    for stmt in &f.body {
+        let mut read = None;
+        stmt.node.visit(&mut |node| {
+            if let Node::VarRef(BindPoint::BoundTo(id), span) = node {
+                if read.is_none() && unassigned.contains(id) {
+                    read = Some((*id, *span));
+                }
+            }
+        });
+        if let Some((id, span)) = read {
+            let message = format!("{} is read before it is given a value", bindings.get_var(id).name);
+            return Err(Diagnostic::spanned(span, message));
+        }
+
+        match &stmt.node {
+            Node::Decl(decl) if decl.expr.is_none() => {
+                unassigned.insert(decl.bind_id);
+            }
+            Node::Assign(BindPoint::BoundTo(id), _, _) => {
+                unassigned.remove(id);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Fails if a local declared without a type was never given a value to take its type from.
+fn check_resolved(bindings: &Bindings, f: &FunDecl) -> Result<(), Diagnostic> {
+    for stmt in &f.body {
+        if let Node::Decl(decl) = &stmt.node {
+            let var = bindings.get_var(decl.bind_id);
+            if matches!(var.typ, Type::Unset | Type::UnspecificNumeric) {
+                let message = format!("cannot infer a type for {}, give it a type or a value", var.name);
+                return Err(Diagnostic::spanned(var.span.unwrap_or(stmt.span), message));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_body(bindings: &mut Bindings, inference: &mut Inference, f: &mut FunDecl) -> Result<(), Diagnostic> {
+    let return_type = bindings.get_fun(f.bind_id).return_type.clone();
+    for stmt in f.body.iter_mut() {
+        if let Node::Return(expr) = &mut stmt.node {
+            typecheck_return(bindings, inference, expr, &return_type)?;
+        }
+        else {
+            check(bindings, inference, &mut stmt.node)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn typecheck(bindings: &mut Bindings, node: &mut Node) -> Result<Type, Diagnostic> {
+    check(bindings, &mut Inference::default(), node)
+}
+
+fn check(bindings: &mut Bindings, inference: &mut Inference, node: &mut Node) -> Result<Type, Diagnostic> {
+    match node {
+        Node::Tree(nodes) => {
+            for node in nodes.children.iter_mut() {
+                check(bindings, inference, node)?;
+            }
+            return Ok(Type::Error);
+        }
+        Node::FunDecl(f) => {
+            check_assigned(bindings, f)?;
+
+            let mut inference = Inference::default();
+            check_body(bindings, &mut inference, f)?;
+
+            // Once the pending variables have their types, check again, so that the literals
+            // they were given take on those types too.
+            if !inference.pending.is_empty() {
+                inference.resolve(bindings)?;
+                check_body(bindings, &mut Inference::default(), f)?;
+            }
+            check_resolved(bindings, f)?;
+            return Ok(Type::Error);
+        }
+        Node::Decl(decl) => {
+
+            match &mut decl.expr {
+                Some(expr) => {
+                    let span = bindings.get_var(decl.bind_id).span.unwrap_or(Span::new(0, 0));
+                    return typecheck_assignment(bindings, inference, expr, decl.bind_id, span);
+                }
+                None => { }
+            }
+        }
+        Node::Assign(bind, name_span, expr) => {
+            match bind {
+                BindPoint::Unbound(name) => {
+                    unreachable!("the parser binds every assigned variable, but {} is unbound", name);
+                }
+                BindPoint::BoundTo(id) => {
+                    return typecheck_assignment(bindings, inference, expr, *id, *name_span);
+                }
+            }
+        }
+        Node::NumConst(num) => {
+            return Ok(num.typ.clone());
+        }
+        Node::VarRef(point, _) => {
+            match point {
+                BindPoint::Unbound(name) => {
+                    unreachable!("the parser binds every variable reference, but {} is unbound", name);
+                }
+                BindPoint::BoundTo(id) => {
+                    return Ok(bindings.get_var(*id).typ.clone());
+                }
+            }
+        }
+        Node::BinOp(op, lhs, rhs) => {
+            let mut left = check(bindings, inference, lhs)?;
+            let mut right = check(bindings, inference, rhs)?;
+
+            if left == Type::UnspecificNumeric && right.is_specific_numeric() {
+                propagate_numeric(inference, lhs, &right);
+                left = right.clone();
+            }
+            else if right == Type::UnspecificNumeric && left.is_specific_numeric() {
+                propagate_numeric(inference, rhs, &left);
+                right = left.clone();
+            }
+
+            if left != right {
+                return Err(String::from("Could not match types in binary expression").into());
+            }
+            if op.is_comparison() {
+                // Comparing two literals leaves nothing to take a type from.
+                if left == Type::UnspecificNumeric {
+                    default_numeric(inference, node);
+                }
+                return Ok(Type::Int32);
+            }
+            if left == Type::UnspecificNumeric {
+                inference.join_all(None, node);
+            }
+            return Ok(left);
+        }
+        Node::FunCall(namespace, point, name_span, args) => {
+            let mut arg_types = vec![];
+            for arg in args.iter_mut() {
+                arg_types.push(check(bindings, inference, arg)?);
+            }
+            match point {
+                BindPoint::Unbound(name) => {
+                    let binding = match bindings.resolve_overload(*namespace, *name, &arg_types) {
+                        Ok(binding) => binding,
+                        Err(message) => {
+                            // Pending arguments only get their types from inference, so the
+                            // overload is picked by the second check. Until then, the call has
+                            // the return type all fitting overloads share.
+                            let pending = args.iter().any(|arg| !inference.pending_in(arg).is_empty());
+                            return match bindings.fitting_return_type(*namespace, *name, &arg_types) {
+                                Some(typ) if pending => Ok(typ),
+                                _ => Err(Diagnostic::spanned(*name_span, message))
+                            };
+                        }
+                    };
+
+                    point.bind_to(binding);
+
+                    // Unspecific numbers passed as arguments take on the parameter's type.
+                    for (arg, param) in args.iter_mut().zip(&bindings.get_fun(binding).args) {
+                        if arg.get_expr_type(bindings) == Type::UnspecificNumeric {
+                            propagate_numeric(inference, arg, &bindings.get_var(*param).typ);
+                        }
+                    }
+
+                    return Ok(bindings.get_fun(binding).return_type.clone());
+                }
+                BindPoint::BoundTo(id) => {
+                    return Ok(bindings.get_fun(*id).return_type.clone());
+                }
+            }
+        }
+        Node::Return(_) => {
+            return Err(String::from("'return' outside of a function").into());
+        }
+        Node::Assert(_, cond, _) => {
+            let typ = check(bindings, inference, cond)?;
+            if typ == Type::UnspecificNumeric {
+                default_numeric(inference, cond);
+            }
+            else if !typ.is_specific_numeric() {
+                return Err(String::from("Asserted condition must be a number").into());
+            }
+        }
+        Node::Empty => {}
+    }
+    return Ok(Type::Error);
 }
\ No newline at end of file
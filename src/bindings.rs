@@ -1,175 +1,373 @@
-use std::collections::hash_map::Values;
-use std::collections::HashMap;
-use std::fmt::{Display, Formatter};
-use std::iter::zip;
-use crate::ast::{Node, Type};
-use crate::string_pool::PoolS;
-
-#[derive(Debug)]
-#[derive(Copy, Clone)]
-#[derive(Eq, Hash, PartialEq)]
-pub struct VarID(u64);
-
-#[derive(Debug)]
-#[derive(Copy, Clone)]
-#[derive(Eq, Hash, PartialEq)]
-pub struct FunID(u64);
-
-#[derive(Copy, Clone)]
-#[derive(Eq, Hash, PartialEq)]
-#[allow(unused)]
-pub enum Namespace {
-    Global,
-    StaticCall(PoolS),
-    DynamicCall(PoolS)
-}
-
-impl Display for Namespace {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Namespace::Global => f.write_str("g_"),
-            Namespace::StaticCall(typ) => f.write_fmt(format_args!("s{}_", typ)),
-            Namespace::DynamicCall(typ) => f.write_fmt(format_args!("d{}_", typ))
-        }
-    }
-}
-
-pub struct VarBinding {
-    pub output_name: String,
-    pub typ: Type
-}
-
-impl VarBinding {
-    pub fn new(output_name: String, typ: Type) -> Self {
-        VarBinding { output_name, typ }
-    }
-}
-
-pub struct FunBinding {
-    pub output_name: String,
-    pub return_type: Type,
-    pub args: Vec<VarID>
-}
-
-impl FunBinding {
-    pub fn new(output_name: String, return_type: Type, args: Vec<VarID>) -> Self {
-        FunBinding { output_name, return_type, args }
-    }
-}
-
-pub struct Bindings {
-    next: u64,
-    var_map: HashMap<VarID, VarBinding>,
-    fun_map: HashMap<FunID, FunBinding>,
-    reverse_fun_map: HashMap<(Namespace, PoolS), Vec<(FunID, Vec<VarID>)>>,
-    names: HashMap<PoolS, u64>
-}
-
-impl Bindings {
-    pub fn new() -> Self {
-        Bindings {
-            next: 0,
-            var_map: HashMap::new(),
-            fun_map: HashMap::new(),
-            reverse_fun_map: HashMap::new(),
-            names: HashMap::new()
-        }
-    }
-
-    fn grab_id(&mut self) -> u64 {
-        let result = self.next;
-        self.next += 1;
-        result
-    }
-
-    pub fn new_var_binding(&mut self, name: PoolS, typ: Type) -> VarID {
-        let num = *self.names.entry(name).and_modify(|x| *x += 1).or_insert(0);
-        let output_name = format!("{}_{}", name, num);
-
-        let id = VarID(self.grab_id());
-        self.var_map.insert(id, VarBinding::new(output_name, typ));
-
-        id
-    }
-
-    pub fn get_var(&self, id: VarID) -> &VarBinding {
-        self.var_map.get(&id).unwrap() // TODO: Determine if this unwrap is safe
-    }
-
-    pub fn get_var_mut(&mut self, id: VarID) -> &mut VarBinding {
-        self.var_map.get_mut(&id).unwrap() // TODO: Determine if this unwrap is safe
-    }
-
-    pub fn new_fun_binding(&mut self, namespace: Namespace, name: PoolS, return_type: Type, args: Vec<VarID>) -> Result<FunID, String> {
-        let existing = self.find_fun_from_vars(namespace, name, &args);
-
-        if existing.is_some() {
-            return Err(format!("function {} already defined with these arguments", name));
-        }
-
-        // TODO: Possibly support functions with different arguments. Actually, we probably
-        // definitely want to do that...
-        let mut output_name = format!("{}{}_", namespace, name);
-        for arg in &args {
-            let typ = &self.get_var(*arg).typ;
-            output_name += &typ.to_string();
-            output_name.push('_');
-        }
-
-        let id = FunID(self.grab_id());
-
-        let list = self.reverse_fun_map.entry((namespace, name)).or_insert(vec![]);
-        list.push((id, args.clone()));
-
-        self.fun_map.insert(id, FunBinding::new(output_name, return_type, args));
-
-        Ok(id)
-    }
-
-    pub fn find_fun_from_vars(&self, namespace: Namespace, name: PoolS, args: &Vec<VarID>) -> Option<FunID> {
-        let options = self.reverse_fun_map.get(&(namespace, name))?;
-
-        for option in options {
-            if &option.1 == args {
-                return Some(option.0)
-            }
-        }
-
-        None
-    }
-
-    pub fn find_fun_from_compat_nodes(&self, namespace: Namespace, name: PoolS, args: &Vec<Node>) -> Option<FunID> {
-        let options = self.reverse_fun_map.get(&(namespace, name))?;
-
-        for option in options {
-            if option.1.len() != args.len() { continue; }
-
-            let matches_all = zip(&option.1, args).map(|pair| {
-                let left = &self.get_var(*pair.0).typ;
-                let right = &pair.1.get_expr_type(self);
-
-                left.eq_or_may_coerce(right)
-            }).filter(|entry| *entry == false).count() == 0;
-
-            if matches_all {
-                return Some(option.0);
-            }
-        }
-
-        None
-    }
-
-    pub fn get_fun(&self, id: FunID) -> &FunBinding {
-        self.fun_map.get(&id).unwrap() // TODO: Determine if this unwrap is safe
-    }
-
-    #[allow(unused)]
-    pub fn get_fun_mut(&mut self, id: FunID) -> &mut FunBinding {
-        self.fun_map.get_mut(&id).unwrap() // TODO: Determine if this unwrap is safe
-    }
-
-    pub fn fun_bindings(&self) -> Values<'_, FunID, FunBinding> {
-        self.fun_map.values()
-    }
-
-}
\ No newline at end of file
+use std::collections::btree_map::Values;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::iter::zip;
+use crate::ast::{Span, Type};
+use crate::diagnostic::did_you_mean;
+use crate::string_pool::PoolS;
+
+pub mod mangle;
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Eq, Hash, PartialEq, Ord, PartialOrd)]
+pub struct VarID(u64);
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Eq, Hash, PartialEq, Ord, PartialOrd)]
+pub struct FunID(u64);
+
+impl Display for VarID {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl Display for FunID {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "f{}", self.0)
+    }
+}
+
+#[derive(Copy, Clone)]
+#[derive(Eq, Hash, PartialEq)]
+#[allow(unused)]
+pub enum Namespace {
+    Global,
+    StaticCall(PoolS),
+    DynamicCall(PoolS)
+}
+
+/// Displays the namespace the way it is written in PonyScript, as a prefix of a function name.
+/// The C names are built by [`mangle`] instead.
+impl Display for Namespace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Namespace::Global => Ok(()),
+            Namespace::StaticCall(typ) => f.write_fmt(format_args!("{}::", typ)),
+            Namespace::DynamicCall(typ) => f.write_fmt(format_args!("{}.", typ))
+        }
+    }
+}
+
+/// How well an argument fits a parameter, best first.
+#[derive(Copy, Clone)]
+#[derive(Eq, PartialEq, Ord, PartialOrd)]
+enum Fit {
+    Exact,
+    /// A number literal that takes on the parameter's type.
+    Coerced
+}
+
+pub struct VarBinding {
+    pub name: PoolS,
+    pub output_name: String,
+    pub typ: Type,
+    /// Where the variable's name is written in its declaration, if it comes from a script.
+    pub span: Option<Span>,
+    /// The `##` doc comment before the `let` declaring the variable.
+    pub doc: Option<String>,
+    /// Whether this is a script variable, declared outside of any function. Those keep their
+    /// value between calls, in the scripts' state rather than in a function's frame.
+    pub state: bool
+}
+
+impl VarBinding {
+    pub fn new(name: PoolS, output_name: String, typ: Type) -> Self {
+        VarBinding { name, output_name, typ, span: None, doc: None, state: false }
+    }
+
+    /// The variable's name as written in the script.
+    pub fn source_name(&self) -> String {
+        self.name.to_utf8()
+    }
+}
+
+pub struct FunBinding {
+    pub namespace: Namespace,
+    pub name: PoolS,
+    pub output_name: String,
+    pub return_type: Type,
+    pub args: Vec<VarID>,
+    /// Implemented by the host rather than by a script, see `new_native_fun_binding`.
+    pub native: bool,
+    /// Where the function's name is written in its declaration, if it comes from a script.
+    pub span: Option<Span>,
+    /// The `##` doc comment before the declaration.
+    pub doc: Option<String>,
+    /// Declared with `test fun`. Tests are run by `ponyscript test`, and cannot be called.
+    pub test: bool
+}
+
+impl FunBinding {
+    pub fn new(namespace: Namespace, name: PoolS, output_name: String, return_type: Type, args: Vec<VarID>) -> Self {
+        FunBinding { namespace, name, output_name, return_type, args, native: false, span: None, doc: None, test: false }
+    }
+
+    /// The function's name and parameter types as written in the script, e.g. `Type.fun(int)`.
+    pub fn source_name(&self) -> String {
+        mangle::demangle(&self.output_name).unwrap_or_else(|| self.output_name.clone())
+    }
+}
+
+/// All variable and function bindings of a compilation.
+///
+/// IDs are handed out in declaration order, and the maps that are ever iterated are keyed by ID,
+/// so anything generated by walking the bindings comes out in the same order on every run. The
+/// remaining hash maps are only used for lookups.
+pub struct Bindings {
+    next: u64,
+    var_map: BTreeMap<VarID, VarBinding>,
+    fun_map: BTreeMap<FunID, FunBinding>,
+    reverse_fun_map: HashMap<(Namespace, PoolS), Vec<(FunID, Vec<VarID>)>>,
+    names: HashMap<PoolS, u64>
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        Bindings {
+            next: 0,
+            var_map: BTreeMap::new(),
+            fun_map: BTreeMap::new(),
+            reverse_fun_map: HashMap::new(),
+            names: HashMap::new()
+        }
+    }
+
+    fn grab_id(&mut self) -> u64 {
+        let result = self.next;
+        self.next += 1;
+        result
+    }
+
+    /// Starts a new function body. Local variable names are numbered per function, so that the
+    /// names generated for one function do not depend on anything parsed before it.
+    pub fn begin_fun(&mut self) {
+        self.names.clear();
+    }
+
+    pub fn new_var_binding(&mut self, name: PoolS, typ: Type) -> VarID {
+        let num = *self.names.entry(name).and_modify(|x| *x += 1).or_insert(0);
+        let output_name = mangle::mangle_local(name, num);
+
+        let id = VarID(self.grab_id());
+        self.var_map.insert(id, VarBinding::new(name, output_name, typ));
+
+        id
+    }
+
+    /// Binds a script variable of the script declaring `own_type`.
+    pub fn new_state_binding(&mut self, own_type: PoolS, name: PoolS, typ: Type) -> VarID {
+        let id = VarID(self.grab_id());
+        let mut binding = VarBinding::new(name, mangle::mangle_state(own_type, name), typ);
+        binding.state = true;
+        self.var_map.insert(id, binding);
+
+        id
+    }
+
+    /// The binding of a variable. IDs are only handed out by `new_var_binding` and never taken
+    /// back, so this only fails for an ID that came from another `Bindings`.
+    pub fn get_var(&self, id: VarID) -> &VarBinding {
+        self.var_map.get(&id).expect("ID from another Bindings")
+    }
+
+    pub fn get_var_mut(&mut self, id: VarID) -> &mut VarBinding {
+        self.var_map.get_mut(&id).expect("ID from another Bindings")
+    }
+
+    pub fn new_fun_binding(&mut self, namespace: Namespace, name: PoolS, return_type: Type, args: Vec<VarID>) -> Result<FunID, String> {
+        let existing = self.find_fun_from_vars(namespace, name, &args);
+
+        if existing.is_some() {
+            return Err(format!("function {} already defined with these arguments", name));
+        }
+
+        let output_name = mangle::mangle_fun(namespace, name, args.iter().map(|arg| &self.get_var(*arg).typ));
+
+        let id = FunID(self.grab_id());
+
+        let list = self.reverse_fun_map.entry((namespace, name)).or_insert(vec![]);
+        list.push((id, args.clone()));
+
+        self.fun_map.insert(id, FunBinding::new(namespace, name, output_name, return_type, args));
+
+        Ok(id)
+    }
+
+    /// Declares a function that has no PonyScript body, because the embedding host provides it.
+    /// It takes part in overload resolution like any other function.
+    pub fn new_native_fun_binding(&mut self, namespace: Namespace, name: PoolS, return_type: Type, params: &[(PoolS, Type)]) -> Result<FunID, String> {
+        self.begin_fun();
+        let args = params.iter().map(|(param, typ)| self.new_var_binding(*param, typ.clone())).collect();
+
+        let id = self.new_fun_binding(namespace, name, return_type, args)?;
+        self.get_fun_mut(id).native = true;
+        Ok(id)
+    }
+
+    /// Finds the overload whose parameters have the same types as `args`.
+    pub fn find_fun_from_vars(&self, namespace: Namespace, name: PoolS, args: &Vec<VarID>) -> Option<FunID> {
+        let options = self.reverse_fun_map.get(&(namespace, name))?;
+
+        for option in options {
+            let same = option.1.len() == args.len()
+                && zip(&option.1, args).all(|(a, b)| self.get_var(*a).typ == self.get_var(*b).typ);
+            if same {
+                return Some(option.0)
+            }
+        }
+
+        None
+    }
+
+    /// Picks the overload of a function that fits arguments of the given types best. An argument
+    /// fits a parameter of the same type exactly, and a number literal fits any number parameter
+    /// by coercion. One overload fits better than another if it fits every argument at least as
+    /// well, and some argument better. Tests are never found.
+    ///
+    /// Fails with a message listing the candidates if no overload fits, or if no single overload
+    /// fits better than all others.
+    pub fn resolve_overload(&self, namespace: Namespace, name: PoolS, arg_types: &[Type]) -> Result<FunID, String> {
+        let candidates: Vec<FunID> = self.overloads(namespace, name).filter(|id| !self.get_fun(*id).test).collect();
+        let call = format!("{}{}({})", namespace, name, arg_types.iter().map(Type::source_name).collect::<Vec<_>>().join(", "));
+        if candidates.is_empty() {
+            return match self.overloads(namespace, name).next() {
+                Some(_) => Err(format!("{}{} is a test, and cannot be called", namespace, name)),
+                None => {
+                    let others = self.fun_bindings()
+                        .filter(|fun| fun.namespace == namespace && !fun.test)
+                        .map(|fun| fun.name.to_utf8());
+                    Err(did_you_mean(format!("no function named {}{}", namespace, name), &name.to_utf8(), others))
+                }
+            };
+        }
+
+        let mut fitting = vec![];
+        let mut rejected = vec![];
+        for id in candidates {
+            match self.fits(id, arg_types) {
+                Ok(fits) => fitting.push((id, fits)),
+                Err(reason) => rejected.push(format!("\n  {}: {}", self.describe_overload(id), reason))
+            }
+        }
+
+        let better = |a: &[Fit], b: &[Fit]| zip(a, b).all(|(a, b)| a <= b) && a != b;
+        let best: Vec<FunID> = fitting.iter()
+            .filter(|(_, fits)| !fitting.iter().any(|(_, other)| better(other, fits)))
+            .map(|(id, _)| *id)
+            .collect();
+
+        match best.as_slice() {
+            [id] => Ok(*id),
+            [] => Err(format!("no overload fits the call {}{}", call, rejected.concat())),
+            _ => {
+                let listed: Vec<String> = best.iter().map(|id| format!("\n  {}", self.describe_overload(*id))).collect();
+                Err(format!("ambiguous call to {}, {} overloads fit equally well{}", call, best.len(), listed.concat()))
+            }
+        }
+    }
+
+    /// The return type of every overload that fits arguments of the given types, if they all
+    /// have the same one.
+    pub fn fitting_return_type(&self, namespace: Namespace, name: PoolS, arg_types: &[Type]) -> Option<Type> {
+        let mut types = self.overloads(namespace, name)
+            .filter(|id| !self.get_fun(*id).test && self.fits(*id, arg_types).is_ok())
+            .map(|id| &self.get_fun(id).return_type);
+        let first = types.next()?;
+        types.all(|typ| typ == first).then(|| first.clone())
+    }
+
+    /// How well each argument fits the function's parameters, or why they do not.
+    fn fits(&self, id: FunID, arg_types: &[Type]) -> Result<Vec<Fit>, String> {
+        let params = &self.get_fun(id).args;
+        if params.len() != arg_types.len() {
+            return Err(format!("expects {} argument(s), but got {}", params.len(), arg_types.len()));
+        }
+
+        let mut fits = vec![];
+        for (i, (param, arg)) in zip(params, arg_types).enumerate() {
+            let param = &self.get_var(*param).typ;
+            if param == arg {
+                fits.push(Fit::Exact);
+            }
+            else if param.eq_or_may_coerce(arg) {
+                fits.push(Fit::Coerced);
+            }
+            else {
+                return Err(format!("argument {} is {}, but the parameter is {}", i + 1, arg.source_name(), param.source_name()));
+            }
+        }
+        Ok(fits)
+    }
+
+    /// An overload's signature, and where it is declared if it comes from a script.
+    fn describe_overload(&self, id: FunID) -> String {
+        let fun = self.get_fun(id);
+        match fun.span {
+            Some(span) => format!("{} (declared at {}:{})", fun.source_name(), span.line, span.column),
+            None => fun.source_name()
+        }
+    }
+
+    /// All overloads of a function, in declaration order.
+    pub fn overloads(&self, namespace: Namespace, name: PoolS) -> impl Iterator<Item = FunID> + '_ {
+        self.reverse_fun_map.get(&(namespace, name)).into_iter().flatten().map(|option| option.0)
+    }
+
+    /// The binding of a function. Like `get_var`, this only fails for an ID from another
+    /// `Bindings`.
+    pub fn get_fun(&self, id: FunID) -> &FunBinding {
+        self.fun_map.get(&id).expect("ID from another Bindings")
+    }
+
+    pub fn get_fun_mut(&mut self, id: FunID) -> &mut FunBinding {
+        self.fun_map.get_mut(&id).expect("ID from another Bindings")
+    }
+
+    /// Iterates over all function bindings, in declaration order.
+    pub fn fun_bindings(&self) -> Values<'_, FunID, FunBinding> {
+        self.fun_map.values()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Type;
+    use crate::string_pool::StringPool;
+    use super::*;
+
+    #[test]
+    fn fun_bindings_in_declaration_order() {
+        let pool = StringPool::new();
+        let mut bindings = Bindings::new();
+        let namespace = Namespace::DynamicCall(pool.pool_str("Test"));
+
+        let names = ["j", "a", "x", "b", "some", "c"];
+        for name in names {
+            bindings.begin_fun();
+            let arg = bindings.new_var_binding(pool.pool_str("arg"), Type::Int32);
+            bindings.new_fun_binding(namespace, pool.pool_str(name), Type::Void, vec![arg]).unwrap();
+        }
+
+        let order: Vec<String> = bindings.fun_bindings()
+            .map(|fun| mangle::demangle(&fun.output_name).unwrap())
+            .collect();
+
+        let expected: Vec<String> = names.iter().map(|name| format!("Test.{}(int)", name)).collect();
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn shadowed_variables_keep_their_names() {
+        let pool = StringPool::new();
+        let mut bindings = Bindings::new();
+        bindings.begin_fun();
+        let first = bindings.new_var_binding(pool.pool_str("x"), Type::Int32);
+        let second = bindings.new_var_binding(pool.pool_str("x"), Type::Float);
+        assert_ne!(bindings.get_var(first).output_name, bindings.get_var(second).output_name);
+        assert_eq!(bindings.get_var(second).source_name(), "x");
+    }
+}
@@ -0,0 +1,383 @@
+//! C name mangling for PonyScript symbols.
+//!
+//! Every name we emit into C is built by this module, so that two different PonyScript entities
+//! can never end up with the same C identifier, and so that any emitted identifier can be turned
+//! back into something a script author recognizes (see [`demangle`]).
+//!
+//! The grammar is:
+//!
+//! ```text
+//! symbol    = "ps" ( function | local | state | node )
+//! function  = "F" namespace ident type* "E"
+//! local     = "L" ident index
+//! state     = "V" ident ident      (a script variable: the script's type, then the name)
+//! node      = "N" ident            (the struct for a script's node type)
+//! namespace = "G" | "S" ident | "D" ident
+//! ident     = length chars         (length is the decimal length of chars)
+//! type      = "i" | "f" | "v"      (int, float, void)
+//!           | "P" ident            (named type)
+//!           | "O" type | "R" type  (?type, +type)
+//!           | "T" ident type* "E"  (parameterized type)
+//!           | "Xu" | "Xe" | "Xn"   (unset, error and unspecific numeric; never valid output)
+//! index     = decimal number of earlier locals with the same name in the function
+//! ```
+//!
+//! Identifier characters in `[A-Za-z0-9]` are copied through, except for a leading digit. Every
+//! other byte (including `_`) is escaped as `_` followed by two uppercase hex digits. Because each
+//! identifier is length-prefixed and escaping is reversible, the mapping is injective, and because
+//! every symbol begins with `ps` followed by an uppercase kind letter, it cannot collide with C
+//! keywords or with the names defined by the prelude.
+
+use crate::ast::Type;
+use crate::bindings::Namespace;
+use crate::string_pool::PoolS;
+
+pub const PREFIX: &str = "ps";
+
+fn push_ident_bytes(bytes: &[u8], out: &mut String) {
+    let mut encoded = String::new();
+    for (index, byte) in bytes.iter().enumerate() {
+        let plain = byte.is_ascii_alphabetic() || (index > 0 && byte.is_ascii_digit());
+        if plain {
+            encoded.push(*byte as char);
+        }
+        else {
+            encoded.push_str(&format!("_{:02X}", byte));
+        }
+    }
+
+    out.push_str(&encoded.len().to_string());
+    out.push_str(&encoded);
+}
+
+fn push_ident(name: PoolS, out: &mut String) {
+    push_ident_bytes(&name.to_vec(), out);
+}
+
+fn push_namespace(namespace: Namespace, out: &mut String) {
+    match namespace {
+        Namespace::Global => out.push('G'),
+        Namespace::StaticCall(typ) => {
+            out.push('S');
+            push_ident(typ, out);
+        }
+        Namespace::DynamicCall(typ) => {
+            out.push('D');
+            push_ident(typ, out);
+        }
+    }
+}
+
+pub fn push_type(typ: &Type, out: &mut String) {
+    match typ {
+        Type::Int32 => out.push('i'),
+        Type::Float => out.push('f'),
+        Type::Void => out.push('v'),
+        Type::Primitive(name) => {
+            out.push('P');
+            push_ident(*name, out);
+        }
+        Type::Optional(inner) => {
+            out.push('O');
+            push_type(inner, out);
+        }
+        Type::Deref(inner) => {
+            out.push('R');
+            push_type(inner, out);
+        }
+        Type::Parameterized(name, params) => {
+            out.push('T');
+            push_ident(*name, out);
+            for param in params {
+                push_type(param, out);
+            }
+            out.push('E');
+        }
+        Type::Unset => out.push_str("Xu"),
+        Type::Error => out.push_str("Xe"),
+        Type::UnspecificNumeric => out.push_str("Xn"),
+    }
+}
+
+/// Mangles a function with the given namespace, name and parameter types.
+pub fn mangle_fun<'t>(namespace: Namespace, name: PoolS, params: impl Iterator<Item = &'t Type>) -> String {
+    let mut out = String::from(PREFIX);
+    out.push('F');
+    push_namespace(namespace, &mut out);
+    push_ident(name, &mut out);
+    for param in params {
+        push_type(param, &mut out);
+    }
+    out.push('E');
+    out
+}
+
+/// Mangles a local variable (or parameter). `index` counts the earlier variables with the same
+/// name inside the same function, so it only depends on the function body itself.
+pub fn mangle_local(name: PoolS, index: u64) -> String {
+    let mut out = String::from(PREFIX);
+    out.push('L');
+    push_ident(name, &mut out);
+    out.push_str(&index.to_string());
+    out
+}
+
+/// Mangles a script variable, which is a member of the C struct holding the scripts' state.
+pub fn mangle_state(own_type: PoolS, name: PoolS) -> String {
+    let mut out = String::from(PREFIX);
+    out.push('V');
+    push_ident(own_type, &mut out);
+    push_ident(name, &mut out);
+    out
+}
+
+/// Mangles the name of the C struct that backs a script's node type.
+pub fn mangle_node(own_type: PoolS) -> String {
+    let mut out = String::from(PREFIX);
+    out.push('N');
+    push_ident(own_type, &mut out);
+    out
+}
+
+/// A cursor over a mangled symbol, used by [`demangle`].
+struct Demangler<'a> {
+    input: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Demangler<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            true
+        }
+        else { false }
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn number(&mut self) -> Option<u64> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.input[start..self.pos]).ok()?.parse().ok()
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        let len = self.number()? as usize;
+        let end = self.pos.checked_add(len)?;
+        let encoded = self.input.get(self.pos..end)?;
+        self.pos = end;
+
+        let mut bytes = vec![];
+        let mut i = 0;
+        while i < encoded.len() {
+            if encoded[i] == b'_' {
+                let hex = std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            else {
+                bytes.push(encoded[i]);
+                i += 1;
+            }
+        }
+
+        String::from_utf8(bytes).ok()
+    }
+
+    fn namespace(&mut self) -> Option<String> {
+        match self.next()? {
+            b'G' => Some(String::new()),
+            b'S' => Some(format!("{}::", self.ident()?)),
+            b'D' => Some(format!("{}.", self.ident()?)),
+            _ => None
+        }
+    }
+
+    fn types_until_end(&mut self) -> Option<Vec<String>> {
+        let mut types = vec![];
+        while !self.eat(b'E') {
+            types.push(self.typ()?);
+        }
+        Some(types)
+    }
+
+    fn typ(&mut self) -> Option<String> {
+        match self.next()? {
+            b'i' => Some(String::from("int")),
+            b'f' => Some(String::from("float")),
+            b'v' => Some(String::from("void")),
+            b'P' => self.ident(),
+            b'O' => Some(format!("?{}", self.typ()?)),
+            b'R' => Some(format!("+{}", self.typ()?)),
+            b'T' => {
+                let name = self.ident()?;
+                let params = self.types_until_end()?;
+                Some(format!("{}[{}]", name, params.join(", ")))
+            }
+            b'X' => match self.next()? {
+                b'u' => Some(String::from("<unset>")),
+                b'e' => Some(String::from("<error>")),
+                b'n' => Some(String::from("<numeric>")),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    fn symbol(&mut self) -> Option<String> {
+        if !self.input.starts_with(PREFIX.as_bytes()) {
+            return None;
+        }
+        self.pos = PREFIX.len();
+
+        let result = match self.next()? {
+            b'F' => {
+                let namespace = self.namespace()?;
+                let name = self.ident()?;
+                let params = self.types_until_end()?;
+                format!("{}{}({})", namespace, name, params.join(", "))
+            }
+            b'L' => {
+                let name = self.ident()?;
+                let index = self.number()?;
+                if index == 0 { name } else { format!("{}@{}", name, index) }
+            }
+            b'V' => format!("{}.{}", self.ident()?, self.ident()?),
+            b'N' => self.ident()?,
+            _ => return None
+        };
+
+        // A valid symbol is consumed exactly.
+        if self.pos == self.input.len() { Some(result) } else { None }
+    }
+}
+
+/// Turns a single mangled symbol back into its PonyScript spelling, e.g. `psFD5First5firstfE`
+/// becomes `First.first(float)`. Returns `None` if `symbol` is not a valid mangled name.
+pub fn demangle(symbol: &str) -> Option<String> {
+    Demangler { input: symbol.as_bytes(), pos: 0 }.symbol()
+}
+
+/// Replaces every mangled symbol in `text` (typically the output of a C compiler or debugger) with
+/// its demangled form. Everything else is left untouched.
+pub fn demangle_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut word = String::new();
+
+    let flush = |word: &mut String, result: &mut String| {
+        match demangle(word) {
+            Some(demangled) => result.push_str(&demangled),
+            None => result.push_str(word)
+        }
+        word.clear();
+    };
+
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            word.push(c);
+        }
+        else {
+            flush(&mut word, &mut result);
+            result.push(c);
+        }
+    }
+    flush(&mut word, &mut result);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::string_pool::StringPool;
+    use super::*;
+
+    #[test]
+    fn mangle_fun_roundtrip() {
+        let pool = StringPool::new();
+        let name = pool.pool_str("first");
+        let namespace = Namespace::DynamicCall(pool.pool_str("First"));
+
+        let mangled = mangle_fun(namespace, name, [Type::Float, Type::Int32].iter());
+        assert_eq!(mangled, "psFD5First5firstfiE");
+        assert_eq!(demangle(&mangled).unwrap(), "First.first(float, int)");
+    }
+
+    #[test]
+    fn mangle_escapes_underscores() {
+        let pool = StringPool::new();
+
+        // Without escaping, these would both be "a_b_c" in some form.
+        let first = mangle_fun(Namespace::Global, pool.pool_str("a_b"), [Type::Primitive(pool.pool_str("c"))].iter());
+        let second = mangle_fun(Namespace::Global, pool.pool_str("a"), [Type::Primitive(pool.pool_str("b_c"))].iter());
+
+        assert_ne!(first, second);
+        assert!(first.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_'));
+        assert_eq!(demangle(&first).unwrap(), "a_b(c)");
+        assert_eq!(demangle(&second).unwrap(), "a(b_c)");
+    }
+
+    #[test]
+    fn mangle_keywords_are_prefixed() {
+        let pool = StringPool::new();
+
+        let mangled = mangle_local(pool.pool_str("int"), 0);
+        assert_eq!(mangled, "psL3int0");
+        assert_eq!(demangle(&mangled).unwrap(), "int");
+        assert_eq!(demangle(&mangle_local(pool.pool_str("int"), 2)).unwrap(), "int@2");
+    }
+
+    #[test]
+    fn mangle_nested_types() {
+        let pool = StringPool::new();
+        let array = Type::Parameterized(pool.pool_str("Map"), vec![
+            Type::Int32,
+            Type::Optional(Box::new(Type::Deref(Box::new(Type::Primitive(pool.pool_str("Node"))))))
+        ]);
+
+        let mangled = mangle_fun(Namespace::StaticCall(pool.pool_str("T")), pool.pool_str("f"), [array].iter());
+        assert_eq!(demangle(&mangled).unwrap(), "T::f(Map[int, ?+Node])");
+    }
+
+    #[test]
+    fn mangle_state_roundtrip() {
+        let pool = StringPool::new();
+
+        let mangled = mangle_state(pool.pool_str("Hero"), pool.pool_str("health"));
+        assert_eq!(mangled, "psV4Hero6health");
+        assert_eq!(demangle(&mangled).unwrap(), "Hero.health");
+    }
+
+    #[test]
+    fn mangle_node_roundtrip() {
+        let pool = StringPool::new();
+
+        let mangled = mangle_node(pool.pool_str("First"));
+        assert_eq!(mangled, "psN5First");
+        assert_eq!(demangle(&mangled).unwrap(), "First");
+    }
+
+    #[test]
+    fn demangle_rejects_garbage() {
+        assert_eq!(demangle("main"), None);
+        assert_eq!(demangle("psF"), None);
+        assert_eq!(demangle("psL9abc0"), None);
+        assert_eq!(demangle("psL3abc0extra"), None);
+    }
+
+    #[test]
+    fn demangle_compiler_output() {
+        let text = "<stdin>:12: error: 'psL4test0' undeclared (in psFD4Test4tickfE)";
+        assert_eq!(demangle_text(text), "<stdin>:12: error: 'test' undeclared (in Test.tick(float))");
+    }
+}
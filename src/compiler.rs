@@ -1,104 +1,872 @@
-use std::fs::File;
-use std::io::{BufReader, Write};
-use std::path::{PathBuf};
-use std::process::{Command, Stdio};
-use crate::ast::{codegen, Node, typecheck};
-use crate::bindings::Bindings;
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-use crate::string_pool::StringPool;
-
-pub enum Output {
-    Stdout,
-    TccLib(PathBuf)
-}
-
-impl Output {
-    fn codegen(&self, compiler: &Compiler) -> std::io::Result<()> {
-        match &self {
-            Output::Stdout => {
-                let out = &mut std::io::stdout();
-                compiler.codegen_impl(out)?;
-            }
-            Output::TccLib(lib_file) => {
-
-                let mut child = Command::new("tcc")
-                    .stdin(Stdio::piped())
-                    .arg("-shared")
-                    .arg("-o")
-                    .arg(lib_file)
-                    .arg("-")
-                    .spawn()?;
-
-                let mut stdin = child.stdin.take().expect("Failed to attach to tcc");
-                compiler.codegen_impl(&mut stdin)?;
-                drop(stdin);
-
-                let ecode = child.wait()?;
-                println!("error code = {}", ecode);
-            }
-        }
-
-        Ok(())
-    }
-}
-
-pub struct Compiler {
-    pool: StringPool,
-    bindings: Bindings,
-    trees: Vec<Node>,
-
-    output: Output
-}
-
-impl Compiler {
-    pub fn new(output: Output) -> Self {
-        Compiler {
-            pool: StringPool::new(),
-            bindings: Bindings::new(),
-            trees: vec![],
-
-            output
-        }
-    }
-
-    pub fn parse_source_file(&mut self, path: &PathBuf) -> Result<(), String> {
-        let file = File::open(&path).map_err(|error| error.to_string())?;
-
-        let lexer = Lexer::new(&self.pool,
-                                   path.to_string_lossy().to_string(),
-                                   BufReader::new(file));
-        let mut parser = Parser::new(lexer, &mut self.bindings);
-
-        let tree = parser.parse()?;
-
-        self.trees.push(tree);
-
-        Ok(())
-    }
-
-    pub fn typecheck(&mut self) -> Result<(), String> {
-        for tree in self.trees.iter_mut(){
-            typecheck(&mut self.bindings, tree)?;
-        }
-
-        Ok(())
-    }
-
-    pub fn output(&self) -> std::io::Result<()> {
-        self.output.codegen(&self)
-    }
-
-    /// Actually implements the codegen itself, with a generic Writer type. This function is called
-    /// by Output with the correct writer.
-    fn codegen_impl<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        codegen::write_prelude(writer)?;
-        codegen::write_forward_declarations(&self.bindings, writer)?;
-
-        for tree in self.trees.iter() {
-            codegen::codegen(&self.bindings, tree, writer)?;
-        }
-
-        Ok(())
-    }
-}
\ No newline at end of file
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::ast::{codegen, Node, Span, Type, typecheck};
+use crate::bindings::{Bindings, FunID, Namespace, VarID};
+use crate::diagnostic::{did_you_mean, Diagnostic};
+use crate::interp::{self, Interpreter, Value};
+use crate::vm::{self, Module, Natives, Vm};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::string_pool::StringPool;
+use cache::Cache;
+
+mod cache;
+mod native;
+
+pub use native::{CCompiler, CDiagnostic};
+
+pub enum Output {
+    Stdout,
+    /// Compiles the generated code into a shared library at the given path, using `cc`.
+    NativeLib { library: PathBuf, cc: CCompiler },
+    /// Writes the implementation to `source`, and a matching header declaring the node structs and
+    /// functions to `header`, so that engine code built with its own toolchain can call scripts.
+    CFile { source: PathBuf, header: PathBuf },
+    /// Writes a bytecode module for the VM.
+    Bytecode(PathBuf)
+}
+
+/// The generic types a script may name, each with its type parameters in brackets.
+const GENERIC_TYPES: &[&str] = &["Array", "Set", "Map"];
+
+/// The first name in `typ` that is not in `known`, or not a generic type if it is parameterized.
+fn unknown_type(typ: &Type, known: &[String]) -> Option<String> {
+    match typ {
+        Type::Primitive(name) => Some(name.to_utf8()).filter(|name| !known.contains(name)),
+        Type::Parameterized(name, inner) => {
+            let name = name.to_utf8();
+            if !GENERIC_TYPES.contains(&name.as_str()) {
+                return Some(name);
+            }
+            inner.iter().find_map(|typ| unknown_type(typ, known))
+        }
+        Type::Optional(inner) | Type::Deref(inner) => unknown_type(inner, known),
+        _ => None
+    }
+}
+
+impl Output {
+    fn codegen(&self, compiler: &Compiler) -> Result<(), String> {
+        match &self {
+            Output::Stdout => {
+                let out = &mut std::io::stdout();
+                compiler.codegen_impl(out).map_err(|error| error.to_string())?;
+            }
+            Output::NativeLib { library, cc } => {
+                // Not every C compiler reads from stdin, so hand it a temporary file instead.
+                let stem = library.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+                let source = std::env::temp_dir().join(format!("ponyscript-{}-{}.c", std::process::id(), stem));
+
+                let result = File::create(&source)
+                    .and_then(|mut file| compiler.codegen_impl(&mut file))
+                    .map_err(|error| format!("failed to write {}: {}", source.display(), error))
+                    .and_then(|_| cc.compile(&source, library));
+                let _ = std::fs::remove_file(&source);
+
+                for warning in result? {
+                    eprintln!("{}", warning);
+                }
+            }
+            Output::CFile { source, header } => {
+                let write = || -> std::io::Result<()> {
+                    let mut header_file = File::create(header)?;
+                    compiler.codegen_header_impl(header, &mut header_file)?;
+
+                    let mut source_file = File::create(source)?;
+                    compiler.codegen_source_impl(source, header, &mut source_file)
+                };
+                write().map_err(|error| error.to_string())?;
+            }
+            Output::Bytecode(path) => {
+                let module = compiler.compile_bytecode()?;
+                std::fs::write(path, module.to_bytes()?).map_err(|error| error.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Compiles a set of scripts: parse each source, typecheck them together, then emit the result to
+/// the configured `Output` (or run it directly).
+pub struct Compiler {
+    // Pooled strings point back at the pool, so it must not move when the compiler does.
+    pool: Box<StringPool>,
+    bindings: Bindings,
+    trees: Vec<Node>,
+    /// The hash of each tree's source, in the same order as `trees`.
+    hashes: Vec<u64>,
+
+    /// The cached C code of each tree that was not changed since the last build, in the same order
+    /// as `trees`. These trees skip typecheck.
+    reused: Vec<Option<Vec<u8>>>,
+    cache: Option<Cache>,
+
+    codegen_options: codegen::Options,
+    output: Output
+}
+
+impl Compiler {
+    pub fn new(output: Output) -> Self {
+        Compiler {
+            pool: Box::new(StringPool::new()),
+            bindings: Bindings::new(),
+            trees: vec![],
+            hashes: vec![],
+
+            reused: vec![],
+            cache: None,
+
+            codegen_options: codegen::Options::default(),
+            output
+        }
+    }
+
+    /// Enables or disables `#line` directives in the generated code. They are on by default in
+    /// debug builds.
+    pub fn set_line_directives(&mut self, enabled: bool) {
+        self.codegen_options.line_directives = enabled;
+    }
+
+    /// Appends a table of every function and script variable to the generated code, which the hot
+    /// reload loader finds functions and carries variables over through (see
+    /// `codegen::write_loader_source`).
+    pub fn set_function_table(&mut self, enabled: bool) {
+        self.codegen_options.function_table = enabled;
+    }
+
+    /// Keeps an incremental compilation cache in `dir`. Scripts that did not change since they
+    /// were last compiled with the cache, and that call no function whose signature changed, then
+    /// skip typecheck and reuse their generated C code.
+    ///
+    /// The cache only stores C code: scripts restored from it cannot be run or compiled to
+    /// bytecode. Must be called before `typecheck`.
+    pub fn set_cache_dir(&mut self, dir: PathBuf) {
+        self.cache = Some(Cache::new(dir));
+    }
+
+    pub fn parse_source_file(&mut self, path: &Path) -> Result<(), Diagnostic> {
+        let tagline = path.to_string_lossy().to_string();
+        let source = std::fs::read(path).map_err(|error| Diagnostic::new(error.to_string()).in_file(&tagline))?;
+
+        let lexer = Lexer::new(&self.pool, tagline, BufReader::new(&source[..]));
+        Self::parse(&mut Parser::new(lexer, &mut self.bindings), &mut self.trees)?;
+        self.hashes.push(cache::hash(&source));
+        Ok(())
+    }
+
+    /// Parses a script held in memory. `name` is used in diagnostics and `#line` directives, in
+    /// place of a file name.
+    pub fn parse_source_str(&mut self, name: &str, source: &str) -> Result<(), Diagnostic> {
+        let lexer = Lexer::from_named_str(&self.pool, name.to_string(), source);
+        Self::parse(&mut Parser::new(lexer, &mut self.bindings), &mut self.trees)?;
+        self.hashes.push(cache::hash(source.as_bytes()));
+        Ok(())
+    }
+
+    /// Parses a script held in memory like `parse_source_str`, but skips over broken statements
+    /// and functions instead of stopping at the first error, so that editors can still make sense
+    /// of the rest of the script. Returns every error found, and if `probe` is given, the variables
+    /// in scope at that position (`None` when it is outside of any function).
+    pub fn parse_source_str_recovering(&mut self, name: &str, source: &str, probe: Option<Span>) -> (Vec<Diagnostic>, Option<Vec<VarID>>) {
+        let lexer = Lexer::from_named_str(&self.pool, name.to_string(), source);
+        let mut parser = Parser::new(lexer, &mut self.bindings);
+        parser.recover_errors();
+        if let Some(at) = probe {
+            parser.probe_scope(at);
+        }
+
+        let result = Self::parse(&mut parser, &mut self.trees);
+        let mut errors = parser.errors().to_vec();
+        let scope = parser.probed_scope().map(<[VarID]>::to_vec);
+
+        match result {
+            Ok(()) => self.hashes.push(cache::hash(source.as_bytes())),
+            Err(error) => errors.push(error)
+        }
+        (errors, scope)
+    }
+
+    fn parse<R: Read>(parser: &mut Parser<'_, R>, trees: &mut Vec<Node>) -> Result<(), Diagnostic> {
+        parser.declared_types(trees.iter()
+            .filter_map(|node| match node {
+                Node::Tree(tree) => Some((tree.own_type, tree.source.clone())),
+                _ => None
+            })
+            .collect());
+
+        let tree = parser.parse()?;
+        trees.push(tree);
+
+        Ok(())
+    }
+
+    pub fn typecheck(&mut self) -> Result<(), Diagnostic> {
+        self.check_types()?;
+
+        let sets = self.cache.as_ref().map(|_| cache::overload_sets(&self.bindings));
+        self.reused.clear();
+
+        for (tree, hash) in self.trees.iter_mut().zip(&self.hashes) {
+            let source = match tree {
+                Node::Tree(tree) => tree.source.clone(),
+                _ => String::new()
+            };
+
+            let entry = match (&self.cache, &sets, &*tree) {
+                (Some(cache), Some(sets), Node::Tree(tree)) => {
+                    let exports = cache::exports(&self.bindings, tree);
+                    cache.load(&source, &self.codegen_options).filter(|entry| entry.is_fresh(*hash, &exports, sets))
+                }
+                _ => None
+            };
+            if let Some(entry) = entry {
+                self.reused.push(Some(entry.fragment));
+                continue;
+            }
+
+            typecheck(&mut self.bindings, tree).map_err(|error| error.in_file(&source))?;
+            self.reused.push(None);
+        }
+
+        Ok(())
+    }
+
+    /// Typechecks only the script that was parsed `index`-th, for editors that look at one script
+    /// of a project at a time. The other scripts still declare the types and functions it can
+    /// refer to, but their own errors are not reported. Does not use the cache.
+    pub fn typecheck_script(&mut self, index: usize) -> Result<(), Diagnostic> {
+        let known = self.known_types();
+        self.check_tree_types(&self.trees[index], &known)?;
+
+        let tree = &mut self.trees[index];
+        let source = match tree {
+            Node::Tree(tree) => tree.source.clone(),
+            _ => String::new()
+        };
+        typecheck(&mut self.bindings, tree).map_err(|error| error.in_file(&source))?;
+        Ok(())
+    }
+
+    /// The names of the types that scripts declare or extend, and of the built-in ones.
+    fn known_types(&self) -> Vec<String> {
+        let mut known = vec![String::from("int"), String::from("float")];
+        for tree in &self.trees {
+            if let Node::Tree(tree) = tree {
+                known.push(tree.own_type.to_utf8());
+                known.push(tree.base_type.to_utf8());
+            }
+        }
+        known
+    }
+
+    /// Fails if a parameter, return value or local has a type that no script declares or extends.
+    /// The names of generic types like `Array` are not checked, since scripts cannot declare those.
+    fn check_types(&self) -> Result<(), Diagnostic> {
+        let known = self.known_types();
+        for tree in &self.trees {
+            self.check_tree_types(tree, &known)?;
+        }
+        Ok(())
+    }
+
+    fn check_tree_types(&self, tree: &Node, known: &[String]) -> Result<(), Diagnostic> {
+        let Node::Tree(tree) = tree else { return Ok(()); };
+        for child in &tree.children {
+            let Node::FunDecl(f) = child else { continue; };
+            let fun = self.bindings.get_fun(f.bind_id);
+
+            let mut typed = vec![(&fun.return_type, fun.span.unwrap_or(f.span))];
+            let locals = f.body.iter().filter_map(|stmt| match &stmt.node {
+                Node::Decl(decl) => Some(decl.bind_id),
+                _ => None
+            });
+            for id in fun.args.iter().copied().chain(locals) {
+                let var = self.bindings.get_var(id);
+                typed.push((&var.typ, var.span.unwrap_or(f.span)));
+            }
+
+            for (typ, span) in typed {
+                if let Some(name) = unknown_type(typ, known) {
+                    let message = did_you_mean(format!("unknown type {}", name), &name, known.iter().cloned());
+                    return Err(Diagnostic::at(&tree.source, span, message));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if some script was restored from the cache, since only its C code is known then.
+    fn expect_all_typechecked(&self) -> Result<(), String> {
+        if self.reused.iter().any(Option::is_some) {
+            return Err(String::from("scripts restored from the compilation cache can only be compiled to C"));
+        }
+        Ok(())
+    }
+
+    /// The bindings of everything parsed so far.
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    /// The trees of the parsed scripts, in the order they were parsed.
+    pub fn trees(&self) -> &[Node] {
+        &self.trees
+    }
+
+    /// Generates the C code for all scripts, the same way `Output::Stdout` would print it.
+    pub fn emit_c(&self) -> String {
+        let mut out = vec![];
+        self.codegen_impl(&mut out).expect("writing to a Vec cannot fail");
+        String::from_utf8(out).expect("generated code is always UTF-8")
+    }
+
+    /// Declares the host functions in `natives`, so that scripts parsed afterwards can call them.
+    pub fn declare_natives(&mut self, natives: &Natives) -> Result<(), String> {
+        natives.declare(&self.pool, &mut self.bindings)
+    }
+
+    /// Lowers the typechecked scripts into a bytecode module for the VM.
+    pub fn compile_bytecode(&self) -> Result<Module, String> {
+        self.expect_all_typechecked()?;
+        vm::lower(&self.bindings, &self.trees)
+    }
+
+    /// Finds the function that `call` (written as `Type.function(args...)`) refers to, picking the
+    /// overload from the arguments just like in a script, and converts the arguments for it.
+    pub(crate) fn resolve_call(&self, call: &str) -> Result<(FunID, Vec<Value>), String> {
+        let (typ, fun, args) = interp::parse_call(call)?;
+
+        let namespace = Namespace::DynamicCall(self.pool.pool_ref(&typ.into_bytes()));
+        let name = self.pool.pool_ref(&fun.into_bytes());
+        let arg_types: Vec<Type> = args.iter().map(|arg| Value::literal_type(arg)).collect();
+
+        // An integer is still accepted by a float parameter if no overload takes it as an int.
+        let id = self.bindings.resolve_overload(namespace, name, &arg_types).or_else(|error| {
+            let coerced: Vec<Type> = arg_types.iter()
+                .map(|typ| if *typ == Type::Int32 { Type::UnspecificNumeric } else { typ.clone() })
+                .collect();
+            self.bindings.resolve_overload(namespace, name, &coerced).map_err(|_| error)
+        })?;
+
+        let params = &self.bindings.get_fun(id).args;
+        let mut values = vec![];
+        for (arg, param) in args.iter().zip(params) {
+            values.push(Value::parse(arg, &self.bindings.get_var(*param).typ)?);
+        }
+
+        Ok((id, values))
+    }
+
+    /// Runs a function on the interpreter instead of generating code. `call` is written as
+    /// `Type.function(args...)`.
+    pub fn run(&self, call: &str) -> Result<Value, String> {
+        self.expect_all_typechecked()?;
+        let (id, values) = self.resolve_call(call)?;
+        Interpreter::new(&self.bindings, &self.trees).call(id, &values)
+    }
+
+    /// The `test fun`s whose name, written as `Type.test`, contains `filter`, in declaration order.
+    pub fn tests(&self, filter: Option<&str>) -> Vec<(String, FunID)> {
+        self.trees.iter()
+            .filter_map(|tree| if let Node::Tree(tree) = tree { Some(tree) } else { None })
+            .flat_map(|tree| &tree.children)
+            .filter_map(|child| match child {
+                Node::FunDecl(f) if self.bindings.get_fun(f.bind_id).test => {
+                    let fun = self.bindings.get_fun(f.bind_id);
+                    Some((format!("{}{}", fun.namespace, fun.name), f.bind_id))
+                }
+                _ => None
+            })
+            .filter(|(name, _)| filter.is_none_or(|filter| name.contains(filter)))
+            .collect()
+    }
+
+    /// Runs a test on the interpreter. Nothing is shared between calls, so every test runs on its
+    /// own. A failed assertion gives its location and message.
+    pub fn run_test(&self, id: FunID) -> Result<(), String> {
+        self.expect_all_typechecked()?;
+        Interpreter::new(&self.bindings, &self.trees).call(id, &[]).map(|_| ())
+    }
+
+    /// Like `run`, but compiles the scripts to bytecode and runs the function on the VM.
+    pub fn run_vm(&self, call: &str, natives: Natives) -> Result<Value, String> {
+        let (_, values) = self.resolve_call(call)?;
+        let (typ, fun, _) = interp::parse_call(call)?;
+
+        let mut vm = Vm::load(self.compile_bytecode()?, natives)?;
+        vm.call(&format!("{}.{}", typ, fun), &values)
+    }
+
+    /// Generates the code and writes it to the configured output. Fails if the output cannot be
+    /// written, or if the C compiler rejects the generated code.
+    pub fn output(&self) -> Result<(), String> {
+        self.output.codegen(self)
+    }
+
+    /// Actually implements the codegen itself, with a generic Writer type. This function is called
+    /// by Output with the correct writer.
+    fn codegen_impl<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        codegen::write_prelude(writer)?;
+        codegen::write_forward_declarations(&self.bindings, writer)?;
+        codegen::write_state(&self.bindings, &self.trees, writer)?;
+
+        self.codegen_trees(writer)?;
+        self.codegen_function_table(writer)
+    }
+
+    fn codegen_function_table<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        if self.codegen_options.function_table {
+            codegen::write_function_table(&self.bindings, writer)?;
+            codegen::write_state_table(&self.bindings, &self.trees, writer)?;
+        }
+        Ok(())
+    }
+
+    fn codegen_trees<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let sets = self.cache.as_ref().map(|_| cache::overload_sets(&self.bindings));
+
+        for (index, tree) in self.trees.iter().enumerate() {
+            if let Some(Some(fragment)) = self.reused.get(index) {
+                writer.write_all(fragment)?;
+                continue;
+            }
+
+            let mut fragment = vec![];
+            codegen::codegen(&self.bindings, &self.codegen_options, tree, &mut fragment)?;
+
+            if let (Some(cache), Some(sets), Node::Tree(tree)) = (&self.cache, &sets, tree) {
+                let entry = cache::Entry {
+                    hash: self.hashes[index],
+                    exports: cache::exports(&self.bindings, tree),
+                    dependencies: cache::dependencies(&self.bindings, tree, sets),
+                    fragment
+                };
+                cache.store(&tree.source, &self.codegen_options, &entry)?;
+                writer.write_all(&entry.fragment)?;
+            }
+            else {
+                writer.write_all(&fragment)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn codegen_header_impl<W: Write>(&self, header: &Path, writer: &mut W) -> std::io::Result<()> {
+        let guard = codegen::include_guard(header);
+        codegen::write_header(&self.bindings, &self.trees, &guard, writer)
+    }
+
+    /// Writes the implementation that goes along with the header written by `codegen_header_impl`.
+    /// The header is included by file name when both live in the same directory.
+    fn codegen_source_impl<W: Write>(&self, source: &Path, header: &Path, writer: &mut W) -> std::io::Result<()> {
+        let include = match (source.parent(), header.parent(), header.file_name()) {
+            (Some(source_dir), Some(header_dir), Some(name)) if source_dir == header_dir => {
+                PathBuf::from(name)
+            }
+            _ => header.to_path_buf()
+        };
+        writeln!(writer, "#include \"{}\"", include.display())?;
+
+        codegen::write_prelude(writer)?;
+        codegen::write_state(&self.bindings, &self.trees, writer)?;
+        self.codegen_trees(writer)?;
+        self.codegen_function_table(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    fn compile_to_vec(path: &Path) -> Vec<u8> {
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_file(path).unwrap();
+        compiler.typecheck().unwrap();
+
+        let mut out = vec![];
+        compiler.codegen_impl(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn line_directives_point_at_source() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/second.pony.script");
+
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.set_line_directives(true);
+        compiler.parse_source_file(&path).unwrap();
+        compiler.typecheck().unwrap();
+
+        let mut out = vec![];
+        compiler.codegen_impl(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains(&format!("#line 1 \"{}\"\n", path.display())));
+        assert!(out.contains("#line 3\nvoid psFD6Second6secondfE(float psL1x0) {\n#line 4\nfloat psL4test0 = 5;\n"));
+
+        compiler.set_line_directives(false);
+        let mut out = vec![];
+        compiler.codegen_impl(&mut out).unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("#line"));
+    }
+
+    #[test]
+    fn cfile_writes_source_and_header() {
+        let dir = std::env::temp_dir().join(format!("ponyscript-cfile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("scripts.c");
+        let header = dir.join("scripts.h");
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/second.pony.script");
+        let mut compiler = Compiler::new(Output::CFile { source: source.clone(), header: header.clone() });
+        compiler.parse_source_file(&path).unwrap();
+        compiler.typecheck().unwrap();
+        compiler.output().unwrap();
+
+        let header_text = std::fs::read_to_string(&header).unwrap();
+        assert!(header_text.starts_with("#ifndef PONYSCRIPT_SCRIPTS_H\n#define PONYSCRIPT_SCRIPTS_H\n"));
+        assert!(header_text.contains("typedef struct psN6Second psN6Second;\n"));
+        assert!(header_text.contains("void psFD6Second6secondfE(float psL1x0);\n"));
+        assert!(header_text.trim_end().ends_with("#endif"));
+
+        let source_text = std::fs::read_to_string(&source).unwrap();
+        assert!(source_text.starts_with("#include \"scripts.h\"\n"));
+        assert!(source_text.contains("void psFD6Second6secondfE(float psL1x0) {\n"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn c_compiler() -> Option<CCompiler> {
+        let cc = CCompiler::from_env();
+        let available = std::process::Command::new(&cc.program).arg("--version").output().is_ok();
+        if available { Some(cc) } else { None }
+    }
+
+    #[test]
+    fn native_lib_builds_and_reports_errors() {
+        let Some(cc) = c_compiler() else { return; };
+
+        let dir = std::env::temp_dir().join(format!("ponyscript-native-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let library = dir.join("libscripts.so");
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/test.pony.script");
+        let mut compiler = Compiler::new(Output::NativeLib { library: library.clone(), cc });
+        compiler.parse_source_file(&path).unwrap();
+        compiler.typecheck().unwrap();
+        compiler.output().unwrap();
+        assert!(library.exists());
+
+        // An unknown type never reaches the C compiler.
+        let bad = dir.join("bad.pony.script");
+        std::fs::write(&bad, "extends Node as Bad\n\nfun f():\n\tlet x: Foo\n").unwrap();
+        let mut compiler = Compiler::new(Output::NativeLib { library: dir.join("libbad.so"), cc: CCompiler::from_env() });
+        compiler.parse_source_file(&bad).unwrap();
+        let error = compiler.typecheck().unwrap_err().to_string();
+        assert!(error.contains(&format!("{}:4:", bad.display())) && error.contains("unknown type Foo"), "{}", error);
+
+        // Generic types make it all the way to the C compiler, which has no such type. Its
+        // complaint should point at the script.
+        std::fs::write(&bad, "extends Node as Bad\n\nfun f():\n\tlet x: Array[int]\n").unwrap();
+        let mut compiler = Compiler::new(Output::NativeLib { library: dir.join("libbad.so"), cc: CCompiler::from_env() });
+        compiler.set_line_directives(true);
+        compiler.parse_source_file(&bad).unwrap();
+        compiler.typecheck().unwrap();
+        let error = compiler.output().unwrap_err();
+        assert!(error.contains(&format!("{}:4:", bad.display())), "{}", error);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interpreter_matches_c_backend() {
+        let Some(cc) = c_compiler() else { return; };
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/calc.pony.script");
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_file(&path).unwrap();
+        compiler.typecheck().unwrap();
+
+        let calls = ["Calc.main", "Calc.add(3, 4)", "Calc.twice(1.25)", "Calc.pick(3)", "Calc.big", "Calc.floats", "Calc.same(2, 2)", "Calc.same(2, 3)"];
+
+        // Build a C program that prints the result of each call, one per line.
+        let mut program = vec![];
+        compiler.codegen_impl(&mut program).unwrap();
+        writeln!(program, "int main(void) {{").unwrap();
+        for call in calls {
+            let (_, _, args) = interp::parse_call(call).unwrap();
+            let fun = compiler.bindings.get_fun(compiler.resolve_call(call).unwrap().0);
+
+            let format = if fun.return_type == Type::Float { "%.9g" } else { "%d" };
+            writeln!(program, "printf(\"{}\\n\", {}({}));", format, fun.output_name, args.join(", ")).unwrap();
+        }
+        writeln!(program, "return 0;\n}}").unwrap();
+
+        let dir = std::env::temp_dir().join(format!("ponyscript-parity-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("parity.c");
+        let exe = dir.join("parity");
+        std::fs::write(&source, program).unwrap();
+        let status = std::process::Command::new(&cc.program).arg("-o").arg(&exe).arg(&source).status().unwrap();
+        assert!(status.success());
+
+        let output = std::process::Command::new(&exe).output().unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for (call, c_result) in calls.iter().zip(output.lines()) {
+            let value = compiler.run(call).unwrap();
+            let matches = match value {
+                Value::Int(value) => c_result.parse::<i32>().unwrap() == value,
+                Value::Float(value) => c_result.parse::<f32>().unwrap() == value,
+                Value::Void => false
+            };
+            assert!(matches, "{}: interpreter gave {}, C gave {}", call, value, c_result);
+        }
+        assert_eq!(output.lines().count(), calls.len());
+    }
+
+    #[test]
+    fn integer_arguments_prefer_int_overloads() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/calc.pony.script");
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_file(&path).unwrap();
+        compiler.typecheck().unwrap();
+
+        assert_eq!(compiler.run("Calc.pick(3)"), Ok(Value::Int(1)));
+        assert_eq!(compiler.run("Calc.pick(2.5)"), Ok(Value::Int(2)));
+        // Without an int overload, an integer still goes to a float parameter.
+        assert_eq!(compiler.run("Calc.twice(1)"), Ok(Value::Float(2.0)));
+    }
+
+    #[test]
+    fn runaway_recursion_fails() {
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_str("b.pony.script", "extends Node as B\n\nfun rec() -> int:\n\treturn rec()\n").unwrap();
+        compiler.typecheck().unwrap();
+        assert_eq!(compiler.run("B.rec"), Err(format!("call depth exceeded {} in B.rec()", interp::MAX_CALL_DEPTH)));
+        assert_eq!(compiler.run_vm("B.rec", Natives::new()), Err(format!("call depth exceeded {} in B.rec", vm::MAX_FRAMES)));
+    }
+
+    #[test]
+    fn scripts_call_each_other() {
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_str("a.pony.script", "extends Node as A\n\nfun f(x: int) -> int:\n\treturn B.g(x) + 1\n").unwrap();
+        compiler.parse_source_str("b.pony.script", "extends Node as B\n\nfun g(x: int) -> int:\n\treturn x + x\n").unwrap();
+        compiler.typecheck().unwrap();
+
+        assert_eq!(compiler.run("A.f(4)"), Ok(Value::Int(9)));
+        assert_eq!(compiler.run_vm("A.f(4)", Natives::new()), Ok(Value::Int(9)));
+        assert!(compiler.emit_c().contains("return int32_t_op_add(psFD1B1giE(psL1x0), 1);"));
+    }
+
+    #[test]
+    fn script_variables_keep_their_values() {
+        let mut compiler = Compiler::new(Output::Stdout);
+        let source = "extends Node as Counter\n\nlet count: int = 10\nlet step: float\n\nfun bump() -> int:\n\tcount = count + 1\n\treturn count\n\nfun shadow() -> int:\n\tlet count = 1\n\treturn count\n";
+        compiler.parse_source_str("counter.pony.script", source).unwrap();
+        compiler.typecheck().unwrap();
+
+        // Each run starts from the initial values.
+        assert_eq!(compiler.run("Counter.bump"), Ok(Value::Int(11)));
+        assert_eq!(compiler.run("Counter.bump"), Ok(Value::Int(11)));
+        assert_eq!(compiler.run("Counter.shadow"), Ok(Value::Int(1)));
+        assert_eq!(compiler.run_vm("Counter.bump", Natives::new()), Ok(Value::Int(11)));
+
+        let c = compiler.emit_c();
+        assert!(c.contains("static struct ps_state ps_initial_state = {\n\t.psV7Counter5count = 10,\n};"), "{}", c);
+        assert!(c.contains("ps_state->psV7Counter5count = int32_t_op_add(ps_state->psV7Counter5count, 1);"), "{}", c);
+    }
+
+    #[test]
+    fn vm_matches_interpreter() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/calc.pony.script");
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_file(&path).unwrap();
+        compiler.typecheck().unwrap();
+
+        for call in ["Calc.main", "Calc.add(3, 4)", "Calc.twice(1.25)", "Calc.pick(3)", "Calc.pick(2.5)", "Calc.big", "Calc.floats", "Calc.same(1, 1)"] {
+            assert_eq!(compiler.run_vm(call, Natives::new()).unwrap(), compiler.run(call).unwrap(), "{}", call);
+        }
+    }
+
+    #[test]
+    fn runs_tests_in_isolation() {
+        let source = "extends Node as T\n\nfun one() -> int:\n\treturn 1\n\ntest fun passes():\n\tassert(one() == 1)\n\ntest fun fails():\n\tlet x = one() + 1\n\tassert(x != 2, \"x is two\")\n\ntest fun failsBare():\n\tassert(0)\n";
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.set_line_directives(false);
+        compiler.parse_source_str("t.pony.script", source).unwrap();
+        compiler.typecheck().unwrap();
+
+        let tests = compiler.tests(None);
+        let names: Vec<&str> = tests.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["T.passes", "T.fails", "T.failsBare"]);
+        assert_eq!(compiler.tests(Some("fails")).len(), 2);
+
+        assert_eq!(compiler.run_test(tests[0].1), Ok(()));
+        assert_eq!(compiler.run_test(tests[1].1), Err(String::from("t.pony.script:11:2: x is two")));
+        assert_eq!(compiler.run_test(tests[2].1), Err(String::from("t.pony.script:14:2: assertion failed")));
+
+        // Tests are neither callable nor part of the generated code.
+        assert!(compiler.run("T.passes").is_err());
+        assert!(!compiler.emit_c().contains("passes"));
+
+        let mut compiler = Compiler::new(Output::Stdout);
+        let error = compiler.parse_source_str("t.pony.script", "extends Node as T\n\nfun f():\n\tassert(1)\n").unwrap_err();
+        assert_eq!(error.to_string(), "t.pony.script:4:2: 'assert' is only allowed in test functions");
+        let error = compiler.parse_source_str("u.pony.script", "extends Node as U\n\ntest fun f(x: int):\n\tassert(x)\n").unwrap_err();
+        assert_eq!(error.to_string(), "u.pony.script:3:1: Test functions take no parameters and return nothing");
+    }
+
+    #[test]
+    fn compile_from_memory() {
+        let source = String::from("extends Node as Mem\n\nfun f(x: int) -> int:\n\treturn x + 1\n");
+
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.set_line_directives(false);
+        compiler.parse_source_str("mem.pony.script", &source).unwrap();
+        drop(source);
+
+        // The compiler owns everything it needs, so it can be moved around after parsing.
+        let mut compiler = Box::new(compiler);
+        compiler.typecheck().unwrap();
+
+        assert_eq!(compiler.trees().len(), 1);
+        let fun = compiler.bindings().fun_bindings().next().unwrap();
+        assert_eq!(fun.source_name(), "Mem.f(int)");
+        assert!(compiler.emit_c().contains("int32_t psFD3Mem1fiE(int32_t psL1x0) {\nreturn int32_t_op_add(psL1x0, 1);\n}\n"));
+    }
+
+    #[test]
+    fn diagnostics_carry_locations() {
+        let mut compiler = Compiler::new(Output::Stdout);
+        let error = compiler.parse_source_str("bad.pony.script", "extends Node as Bad\n\nfun f(:\n").unwrap_err();
+        assert_eq!(error.file.as_deref(), Some("bad.pony.script"));
+        assert_eq!(error.span, Some(crate::ast::Span::new(3, 7)));
+        assert_eq!(error.to_string(), "bad.pony.script:3:7: Expected ')' after function name");
+
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_str("calls.pony.script", "extends Node as Calls\n\nfun f():\n\tg()\n").unwrap();
+        let error = compiler.typecheck().unwrap_err();
+        assert_eq!(error.to_string(), "calls.pony.script:4:2: no function named Calls.g");
+    }
+
+    #[test]
+    fn ranks_overloads() {
+        let typecheck = |body: &str| {
+            let source = format!("extends Node as T\n\nfun some(x: float, y: int):\n\treturn\n\nfun some(x: int, y: int):\n\treturn\n\nfun f(a: int, b: float):\n{}\n", body);
+            let mut compiler = Compiler::new(Output::Stdout);
+            compiler.parse_source_str("t.pony.script", &source).unwrap();
+            compiler.typecheck().map(|()| compiler.emit_c()).map_err(|error| error.to_string())
+        };
+
+        // An exact match beats a literal that could be either type.
+        assert!(typecheck("\tsome(a, 1)").unwrap().contains("psFD1T4someiiE(psL1a0, 1);"));
+        assert!(typecheck("\tsome(b, 1)").unwrap().contains("psFD1T4somefiE(psL1b0, 1);"));
+
+        assert_eq!(typecheck("\tsome(1, a)").unwrap_err(), "\
+t.pony.script:10:2: ambiguous call to T.some(<numeric>, int), 2 overloads fit equally well
+  T.some(float, int) (declared at 3:5)
+  T.some(int, int) (declared at 6:5)");
+
+        assert_eq!(typecheck("\tsome(a, b)").unwrap_err(), "\
+t.pony.script:10:2: no overload fits the call T.some(int, float)
+  T.some(float, int) (declared at 3:5): argument 1 is int, but the parameter is float
+  T.some(int, int) (declared at 6:5): argument 2 is float, but the parameter is int");
+
+        assert!(typecheck("\tsome(a)").unwrap_err().ends_with("T.some(int, int) (declared at 6:5): expects 2 argument(s), but got 1"));
+    }
+
+    #[test]
+    fn duplicate_types_are_rejected() {
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_str("a.pony.script", "extends Node as Player\n").unwrap();
+        compiler.parse_source_str("b.pony.script", "extends Node as Enemy\n").unwrap();
+        let error = compiler.parse_source_str("c.pony.script", "extends Node2D as Player\n").unwrap_err();
+        assert_eq!(error.to_string(), "c.pony.script: type Player is already declared in a.pony.script");
+        assert_eq!(compiler.trees().len(), 2);
+
+        // The duplicate is found before the functions of the script are bound, so they neither
+        // clash with nor join the overloads of the first declaration.
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_str("a.pony.script", "extends Node as Player\n\nfun jump():\n\treturn\n").unwrap();
+        let source = "extends Node as Player\n\nfun jump():\n\treturn\n\nfun jump(height: float):\n\treturn\n";
+        let error = compiler.parse_source_str("b.pony.script", source).unwrap_err();
+        assert_eq!(error.to_string(), "b.pony.script: type Player is already declared in a.pony.script");
+        assert_eq!(compiler.bindings().fun_bindings().count(), 1);
+    }
+
+    #[test]
+    fn output_is_deterministic() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/test.pony.script");
+
+        let first = compile_to_vec(&path);
+        for _ in 0..8 {
+            assert_eq!(first, compile_to_vec(&path));
+        }
+    }
+
+    /// Compiles in-memory sources, with the cache in `cache_dir` if given. Returns the C code and
+    /// how many scripts were restored from the cache.
+    fn compile_cached(sources: &[(&str, &str)], natives: &Natives, cache_dir: Option<&Path>) -> (String, usize) {
+        let mut compiler = Compiler::new(Output::Stdout);
+        if let Some(dir) = cache_dir {
+            compiler.set_cache_dir(dir.to_path_buf());
+        }
+        compiler.declare_natives(natives).unwrap();
+        for (name, source) in sources {
+            compiler.parse_source_str(name, source).unwrap();
+        }
+        compiler.typecheck().unwrap();
+
+        let reused = compiler.reused.iter().filter(|fragment| fragment.is_some()).count();
+        (compiler.emit_c(), reused)
+    }
+
+    #[test]
+    fn cached_output_matches_clean_build() {
+        let dir = std::env::temp_dir().join(format!("ponyscript-cache-{}", std::process::id()));
+        let calc = std::fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/calc.pony.script")).unwrap();
+        let other = "extends Node as Other\n\nfun f(x: int) -> int:\n\tlet y = x + x\n\treturn y\n";
+        let changed = "extends Node as Other\n\nfun f(x: float) -> float:\n\treturn x\n";
+        let natives = Natives::new();
+
+        let sources = [("calc.pony.script", calc.as_str()), ("other.pony.script", other)];
+        let clean = compile_cached(&sources, &natives, None);
+        assert_eq!(compile_cached(&sources, &natives, Some(&dir)), (clean.0.clone(), 0));
+        assert_eq!(compile_cached(&sources, &natives, Some(&dir)), (clean.0.clone(), 2));
+
+        // Only the changed script is compiled again, and the result still matches a clean build.
+        let sources = [("calc.pony.script", calc.as_str()), ("other.pony.script", changed)];
+        let clean = compile_cached(&sources, &natives, None);
+        assert_eq!(compile_cached(&sources, &natives, Some(&dir)), (clean.0.clone(), 1));
+        assert_eq!(compile_cached(&sources, &natives, Some(&dir)), (clean.0, 2));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn changed_signatures_invalidate_callers() {
+        let dir = std::env::temp_dir().join(format!("ponyscript-cache-deps-{}", std::process::id()));
+        let source = [("host.pony.script", "extends Node as Host\n\nfun f():\n\tlet x = tick(1)\n")];
+
+        let mut ints = Natives::new();
+        ints.register("Host", "tick", &[vm::ValueType::Int], vm::ValueType::Int, |args| Ok(args[0]));
+        let mut floats = Natives::new();
+        floats.register("Host", "tick", &[vm::ValueType::Int], vm::ValueType::Float, |_| Ok(Value::Float(0.0)));
+
+        assert_eq!(compile_cached(&source, &ints, Some(&dir)).1, 0);
+        assert_eq!(compile_cached(&source, &ints, Some(&dir)).1, 1);
+
+        // The script did not change, but the function it calls now returns a float.
+        let clean = compile_cached(&source, &floats, None);
+        assert!(clean.0.contains("float psL1x0"));
+        assert_eq!(compile_cached(&source, &floats, Some(&dir)), (clean.0, 0));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
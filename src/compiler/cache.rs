@@ -0,0 +1,239 @@
+//! The incremental compilation cache.
+//!
+//! For every script, the cache keeps the hash of its source, the signatures of the functions it
+//! declares, the signatures of every overload set it calls into, and the C code generated for it.
+//! A script can skip typecheck and codegen when its source is unchanged and every overload set it
+//! calls still has exactly the same signatures: those are the only things from outside the script
+//! that typecheck and codegen look at.
+//!
+//! Each script gets its own entry file in the cache directory, named after a hash of the script's
+//! path. Entries that cannot be read, that were written by another version of the format or of the
+//! compiler, or with other options, are treated as missing. A new compiler may check or generate
+//! code differently, so nothing it did not write itself is reused: the compiler is identified by a
+//! hash of its sources (see `build.rs`), which changes with any change to codegen even when the
+//! package version does not.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use crate::ast::{Node, Tree};
+use crate::ast::codegen::Options;
+use crate::bindings::{Bindings, FunID};
+use crate::bindings::mangle;
+
+const MAGIC: &str = "ponyscript-cache 2";
+
+/// The compiler that writes and reads the entries.
+const COMPILER: &str = concat!(env!("CARGO_PKG_VERSION"), "+", env!("PONYSCRIPT_SOURCE_HASH"));
+
+/// Hashes bytes with 64-bit FNV-1a.
+pub fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// A function's C name and return type. Together these decide everything a caller's generated
+/// code depends on.
+fn signature(bindings: &Bindings, id: FunID) -> String {
+    let fun = bindings.get_fun(id);
+    let mut signature = format!("{}:", fun.output_name);
+    mangle::push_type(&fun.return_type, &mut signature);
+    signature
+}
+
+/// The signatures of every overload set, keyed by the function name as written in scripts.
+pub type OverloadSets = BTreeMap<String, Vec<String>>;
+
+pub fn overload_sets(bindings: &Bindings) -> OverloadSets {
+    let mut sets = OverloadSets::new();
+    for fun in bindings.fun_bindings() {
+        sets.entry(format!("{}{}", fun.namespace, fun.name)).or_insert_with(|| {
+            bindings.overloads(fun.namespace, fun.name).map(|id| signature(bindings, id)).collect()
+        });
+    }
+    sets
+}
+
+/// The signatures of the functions declared by a tree.
+pub fn exports(bindings: &Bindings, tree: &Tree) -> Vec<String> {
+    tree.children.iter()
+        .filter_map(|child| match child {
+            Node::FunDecl(f) => Some(signature(bindings, f.bind_id)),
+            _ => None
+        })
+        .collect()
+}
+
+/// The overload sets a typechecked tree calls into, along with their current signatures.
+pub fn dependencies(bindings: &Bindings, tree: &Tree, sets: &OverloadSets) -> OverloadSets {
+    let mut calls = vec![];
+    for child in &tree.children {
+        child.visit(&mut |node| {
+            if let Node::FunCall(_, point, _, _) = node {
+                if let Ok(id) = point.expect_bound() {
+                    calls.push(id);
+                }
+            }
+        });
+    }
+
+    let mut dependencies = OverloadSets::new();
+    for id in calls {
+        let fun = bindings.get_fun(id);
+        let key = format!("{}{}", fun.namespace, fun.name);
+        if let Some(signatures) = sets.get(&key) {
+            dependencies.insert(key, signatures.clone());
+        }
+    }
+    dependencies
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub hash: u64,
+    pub exports: Vec<String>,
+    pub dependencies: OverloadSets,
+    pub fragment: Vec<u8>
+}
+
+impl Entry {
+    /// Whether the entry can stand in for a script with the given source hash and exports, given
+    /// the current overload sets.
+    pub fn is_fresh(&self, hash: u64, exports: &[String], sets: &OverloadSets) -> bool {
+        self.hash == hash
+            && self.exports == exports
+            && self.dependencies.iter().all(|(key, signatures)| sets.get(key) == Some(signatures))
+    }
+}
+
+/// The part of an entry that records the compiler version and the codegen options. Entries
+/// written by another compiler or with other options are never reused.
+fn options_key(options: &Options) -> String {
+    format!("compiler={} lines={}", COMPILER, options.line_directives)
+}
+
+pub struct Cache {
+    dir: PathBuf
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Cache { dir }
+    }
+
+    fn entry_path(&self, source: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.entry", hash(source.as_bytes())))
+    }
+
+    /// Reads the entry for the script at `source`, if there is a readable one for these options.
+    pub fn load(&self, source: &str, options: &Options) -> Option<Entry> {
+        let bytes = std::fs::read(self.entry_path(source)).ok()?;
+
+        let mut rest = &bytes[..];
+        let mut line = || -> Option<&str> {
+            let end = rest.iter().position(|byte| *byte == b'\n')?;
+            let line = std::str::from_utf8(&rest[..end]).ok()?;
+            rest = &rest[end + 1..];
+            Some(line)
+        };
+
+        if line()? != MAGIC
+            || line()?.strip_prefix("source ")? != source
+            || line()?.strip_prefix("options ")? != options_key(options) {
+            return None;
+        }
+        let hash = u64::from_str_radix(line()?.strip_prefix("hash ")?, 16).ok()?;
+
+        let mut exports = vec![];
+        let mut dependencies = OverloadSets::new();
+        let length = loop {
+            let line = line()?;
+            if let Some(export) = line.strip_prefix("export ") {
+                exports.push(export.to_string());
+            }
+            else if let Some(dependency) = line.strip_prefix("depend ") {
+                let mut words = dependency.split(' ');
+                let key = words.next()?.to_string();
+                dependencies.insert(key, words.map(String::from).collect());
+            }
+            else {
+                break line.strip_prefix("fragment ")?.parse::<usize>().ok()?;
+            }
+        };
+
+        if rest.len() != length {
+            return None;
+        }
+        Some(Entry { hash, exports, dependencies, fragment: rest.to_vec() })
+    }
+
+    pub fn store(&self, source: &str, options: &Options, entry: &Entry) -> io::Result<()> {
+        let mut text = format!("{}\nsource {}\noptions {}\nhash {:016x}\n", MAGIC, source, options_key(options), entry.hash);
+        for export in &entry.exports {
+            text += &format!("export {}\n", export);
+        }
+        for (key, signatures) in &entry.dependencies {
+            text += &format!("depend {}", key);
+            for signature in signatures {
+                text += &format!(" {}", signature);
+            }
+            text.push('\n');
+        }
+        text += &format!("fragment {}\n", entry.fragment.len());
+
+        let mut bytes = text.into_bytes();
+        bytes.extend_from_slice(&entry.fragment);
+
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.entry_path(source), bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv_hash() {
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn entries_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("ponyscript-cache-entries-{}", std::process::id()));
+        let options = Options { line_directives: true, ..Options::default() };
+        let cache = Cache::new(dir.clone());
+
+        let mut dependencies = OverloadSets::new();
+        dependencies.insert(String::from("Calc.add"), vec![String::from("psFD4Calc3addiiE:i")]);
+        let entry = Entry {
+            hash: 42,
+            exports: vec![String::from("psFD4Calc4mainE:i")],
+            dependencies,
+            fragment: b"int psFD4Calc4mainE() {\n}\n".to_vec()
+        };
+
+        assert_eq!(cache.load("calc.pony.script", &options), None);
+        cache.store("calc.pony.script", &options, &entry).unwrap();
+        assert_eq!(cache.load("calc.pony.script", &options).as_ref(), Some(&entry));
+
+        // Other options, another compiler, or a damaged file, make the entry unusable.
+        assert_eq!(cache.load("calc.pony.script", &Options { line_directives: false, ..Options::default() }), None);
+        let path = cache.entry_path("calc.pony.script");
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, text.replace(&format!("compiler={} ", COMPILER), &format!("compiler={}+0000000000000000 ", env!("CARGO_PKG_VERSION")))).unwrap();
+        assert_eq!(cache.load("calc.pony.script", &options), None);
+        cache.store("calc.pony.script", &options, &entry).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.pop();
+        std::fs::write(&path, bytes).unwrap();
+        assert_eq!(cache.load("calc.pony.script", &options), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use crate::bindings::mangle;
+
+/// The C compiler used to turn generated code into a native library, along with the flags to pass
+/// to it.
+#[derive(Clone)]
+pub struct CCompiler {
+    pub program: String,
+    /// Passed as `-O<level>` when set.
+    pub opt_level: Option<String>,
+    pub pic: bool,
+    pub include_paths: Vec<PathBuf>,
+    /// Any other flags, passed through as they are.
+    pub flags: Vec<String>
+}
+
+impl CCompiler {
+    pub fn new(program: String) -> Self {
+        CCompiler {
+            program,
+            opt_level: None,
+            pic: true,
+            include_paths: vec![],
+            flags: vec![]
+        }
+    }
+
+    /// Uses the compiler named by the `CC` environment variable, or `cc` if it is not set.
+    pub fn from_env() -> Self {
+        let program = std::env::var("CC")
+            .ok()
+            .filter(|cc| !cc.trim().is_empty())
+            .unwrap_or_else(|| String::from("cc"));
+        CCompiler::new(program)
+    }
+
+    fn command(&self, source: &Path, library: &Path) -> Command {
+        let mut command = Command::new(&self.program);
+        command.arg("-shared");
+        if self.pic {
+            command.arg("-fPIC");
+        }
+        if let Some(level) = &self.opt_level {
+            command.arg(format!("-O{}", level));
+        }
+        for path in &self.include_paths {
+            command.arg("-I").arg(path);
+        }
+        command.args(&self.flags);
+        command.arg("-o").arg(library).arg(source);
+        command
+    }
+
+    /// Compiles the C file at `source` into the shared library `library`. If the compiler fails,
+    /// returns its diagnostics, with generated names translated back to PonyScript names.
+    pub fn compile(&self, source: &Path, library: &Path) -> Result<Vec<CDiagnostic>, String> {
+        let output: Output = self.command(source, library)
+            .output()
+            .map_err(|error| format!("failed to run C compiler '{}': {}", self.program, error))?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let diagnostics = parse_diagnostics(&stderr, source);
+
+        if output.status.success() {
+            return Ok(diagnostics);
+        }
+
+        let mut message = format!("C compiler '{}' failed ({})", self.program, output.status);
+        if diagnostics.is_empty() {
+            message.push('\n');
+            message.push_str(mangle::demangle_text(&stderr).trim_end());
+        }
+        for diagnostic in diagnostics {
+            message.push('\n');
+            message.push_str(&diagnostic.to_string());
+        }
+        Err(message)
+    }
+}
+
+/// A single error, warning or note reported by the C compiler.
+#[derive(Debug, PartialEq)]
+pub struct CDiagnostic {
+    /// The script the problem was reported in, or `None` if it is in generated code that has no
+    /// `#line` information.
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub severity: String,
+    pub message: String
+}
+
+impl Display for CDiagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => f.write_str(file)?,
+            None => f.write_str("[generated code]")?
+        }
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+        write!(f, ": {}: {}", self.severity, self.message)
+    }
+}
+
+fn parse_diagnostic(line: &str, generated: &str) -> Option<CDiagnostic> {
+    // gcc, clang and tcc all report "file:line[:column]: severity: message".
+    let mut rest = line;
+    let mut numbers = vec![];
+
+    let (file, after_file) = rest.split_once(':')?;
+    rest = after_file;
+    while let Some((number, after)) = rest.split_once(':') {
+        match number.trim().parse::<u32>() {
+            Ok(value) => {
+                numbers.push(value);
+                rest = after;
+            }
+            Err(_) => break
+        }
+    }
+
+    let (severity, message) = rest.trim_start().split_once(':')?;
+    let severity = severity.trim();
+    if !["error", "warning", "note", "fatal error"].contains(&severity) || numbers.is_empty() {
+        return None;
+    }
+
+    let file = if file == generated || file == "<stdin>" || file == "-" {
+        None
+    }
+    else {
+        Some(file.to_string())
+    };
+
+    Some(CDiagnostic {
+        file,
+        line: numbers.first().copied(),
+        column: numbers.get(1).copied(),
+        severity: severity.to_string(),
+        message: mangle::demangle_text(message.trim())
+    })
+}
+
+/// Extracts the diagnostics from a C compiler's stderr. Lines that are not diagnostics (source
+/// excerpts, "In function" headers and so on) are skipped.
+pub fn parse_diagnostics(stderr: &str, generated: &Path) -> Vec<CDiagnostic> {
+    let generated = generated.to_string_lossy();
+    stderr.lines().filter_map(|line| parse_diagnostic(line, &generated)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_gcc_diagnostics() {
+        let stderr = "\
+t.pony.script: In function 'psFD1T1fE':
+t.pony.script:4:8: error: unknown type name 'psL4test0'
+    4 |         let x: Foo
+      | ^       ~~~
+/tmp/gen.c:12: warning: something in psFD1T1fE
+";
+        let diagnostics = parse_diagnostics(stderr, Path::new("/tmp/gen.c"));
+        assert_eq!(diagnostics, vec![
+            CDiagnostic {
+                file: Some(String::from("t.pony.script")),
+                line: Some(4),
+                column: Some(8),
+                severity: String::from("error"),
+                message: String::from("unknown type name 'test'")
+            },
+            CDiagnostic {
+                file: None,
+                line: Some(12),
+                column: None,
+                severity: String::from("warning"),
+                message: String::from("something in T.f()")
+            }
+        ]);
+        assert_eq!(diagnostics[0].to_string(), "t.pony.script:4:8: error: unknown type name 'test'");
+        assert_eq!(diagnostics[1].to_string(), "[generated code]:12: warning: something in T.f()");
+    }
+
+    #[test]
+    fn compiler_flags() {
+        let mut cc = CCompiler::new(String::from("clang"));
+        cc.opt_level = Some(String::from("2"));
+        cc.include_paths.push(PathBuf::from("include"));
+        cc.flags.push(String::from("-g"));
+
+        let command = cc.command(Path::new("in.c"), Path::new("out.so"));
+        let args: Vec<_> = command.get_args().map(|arg| arg.to_string_lossy().to_string()).collect();
+        assert_eq!(command.get_program(), "clang");
+        assert_eq!(args, ["-shared", "-fPIC", "-O2", "-I", "include", "-g", "-o", "out.so", "in.c"]);
+    }
+}
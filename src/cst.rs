@@ -0,0 +1,244 @@
+//! A concrete syntax tree: the structure of a script over its lossless tokens, so that every byte
+//! of the source, comments included, can be found in (and written back from) the tree.
+//!
+//! The CST is built alongside the `Node` AST rather than from it, and never fails: text that does
+//! not fit the grammar ends up in `Error` nodes.
+
+use std::fmt::{Debug, Formatter};
+use std::ops::Range;
+use std::iter::Peekable;
+use crate::lexer::{lossless_tokens, LosslessToken, Token};
+use crate::string_pool::StringPool;
+
+#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq)]
+pub enum SyntaxKind {
+    Script,
+    /// The `extends Base as Own` line.
+    Header,
+    Function,
+    /// The indented statements of a function, between a `BlockStart` and a `BlockEnd`.
+    Block,
+    Statement,
+    Error
+}
+
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(LosslessToken)
+}
+
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub children: Vec<SyntaxElement>
+}
+
+impl SyntaxNode {
+    fn new(kind: SyntaxKind) -> Self {
+        SyntaxNode { kind, children: vec![] }
+    }
+
+    /// The tokens of the node, in source order.
+    pub fn tokens(&self) -> Vec<&LosslessToken> {
+        let mut tokens = vec![];
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token)
+            }
+        }
+        tokens
+    }
+
+    /// The nodes directly below this one.
+    #[allow(unused)]
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None
+        })
+    }
+
+    /// The byte offsets from the first to the last token of the node, without the trivia around
+    /// them. Block tokens take no space and do not count.
+    pub fn range(&self) -> Range<usize> {
+        let mut tokens = self.tokens();
+        tokens.retain(|token| !matches!(token.token, Token::BlockStart | Token::BlockEnd));
+        match (tokens.first(), tokens.last()) {
+            (Some(first), Some(last)) => first.range.start..last.range.end,
+            _ => 0..0
+        }
+    }
+
+    /// The source text of the node, with all of its trivia.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        self.tokens().iter().for_each(|token| token.write_to(&mut text));
+        text
+    }
+
+    fn fmt_indented(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        let range = self.range();
+        writeln!(f, "{}{:?}@{}..{}", "  ".repeat(depth), self.kind, range.start, range.end)?;
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.fmt_indented(f, depth + 1)?,
+                SyntaxElement::Token(token) => {
+                    writeln!(f, "{}{:?}@{}..{} {:?}", "  ".repeat(depth + 1), token.token, token.range.start, token.range.end, token.text)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints the tree one element per line, indented by depth.
+impl Debug for SyntaxNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+struct Builder<I: Iterator<Item = LosslessToken>> {
+    tokens: Peekable<I>
+}
+
+impl<I: Iterator<Item = LosslessToken>> Builder<I> {
+    fn peek(&mut self) -> &Token {
+        self.tokens.peek().map_or(&Token::EOF, |token| &token.token)
+    }
+
+    fn bump(&mut self, node: &mut SyntaxNode) {
+        if let Some(token) = self.tokens.next() {
+            node.children.push(SyntaxElement::Token(token));
+        }
+    }
+
+    /// Whether the next token continues the current line.
+    fn on_line(&mut self) -> bool {
+        match self.tokens.peek() {
+            Some(token) => !token.starts_line() && !matches!(token.token, Token::BlockStart | Token::BlockEnd | Token::EOF),
+            None => false
+        }
+    }
+
+    /// Takes the next token and the rest of its line.
+    fn line(&mut self, kind: SyntaxKind) -> SyntaxNode {
+        let mut node = SyntaxNode::new(kind);
+        self.bump(&mut node);
+        while self.on_line() {
+            self.bump(&mut node);
+        }
+        node
+    }
+
+    fn block(&mut self) -> SyntaxNode {
+        let mut block = SyntaxNode::new(SyntaxKind::Block);
+        self.bump(&mut block);
+        loop {
+            let child = match self.peek() {
+                Token::EOF => break,
+                Token::BlockEnd => {
+                    self.bump(&mut block);
+                    break;
+                }
+                Token::BlockStart => self.block(),
+                _ => self.line(SyntaxKind::Statement)
+            };
+            block.children.push(SyntaxElement::Node(child));
+        }
+        block
+    }
+
+    fn function(&mut self) -> SyntaxNode {
+        let mut function = self.line(SyntaxKind::Function);
+        if self.peek() == &Token::BlockStart {
+            let block = self.block();
+            function.children.push(SyntaxElement::Node(block));
+        }
+        function
+    }
+
+    fn script(&mut self) -> SyntaxNode {
+        let mut script = SyntaxNode::new(SyntaxKind::Script);
+        if self.peek() == &Token::KeyExtends {
+            let header = self.line(SyntaxKind::Header);
+            script.children.push(SyntaxElement::Node(header));
+        }
+
+        loop {
+            let child = match self.peek() {
+                Token::EOF => {
+                    self.bump(&mut script);
+                    return script;
+                }
+                Token::KeyFun => self.function(),
+                Token::ID(name) if name.eq_utf8("test") => self.function(),
+                Token::BlockStart => {
+                    // An indented block without a function is kept as it is, inside an error.
+                    let mut error = SyntaxNode::new(SyntaxKind::Error);
+                    let block = self.block();
+                    error.children.push(SyntaxElement::Node(block));
+                    error
+                }
+                _ => self.line(SyntaxKind::Error)
+            };
+            script.children.push(SyntaxElement::Node(child));
+        }
+    }
+}
+
+/// Builds the CST of a script. Names in its tokens are pooled in `pool`.
+pub fn parse(pool: &StringPool, name: &str, source: &str) -> SyntaxNode {
+    let mut builder = Builder { tokens: lossless_tokens(pool, name, source).into_iter().peekable() };
+    builder.script()
+}
+
+/// Prints the CST of a script, as `emit --stage cst` does. The tree cannot be handed out itself,
+/// since its names point into a pool that only lives as long as this call.
+pub fn dump_cst(name: &str, source: &str) -> String {
+    let pool = StringPool::new();
+    format!("{:?}", parse(&pool, name, source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_functions_and_statements() {
+        let pool = StringPool::new();
+        let source = "extends Node as Calc\n\n# Adds.\nfun add(a: int, b: int) -> int:\n\tlet x = a + b # sum\n\treturn x\n\nfun main():\n\tadd(1, 2)\n";
+        let script = parse(&pool, "calc.pony.script", source);
+        assert_eq!(script.text(), source);
+
+        let kinds: Vec<SyntaxKind> = script.nodes().map(|node| node.kind).collect();
+        assert_eq!(kinds, [SyntaxKind::Header, SyntaxKind::Function, SyntaxKind::Function]);
+
+        let add = script.nodes().nth(1).unwrap();
+        assert!(add.text().starts_with("\n\n# Adds.\nfun add"));
+        assert_eq!(&source[add.range()], "fun add(a: int, b: int) -> int:\n\tlet x = a + b # sum\n\treturn x");
+
+        let block = add.nodes().next().unwrap();
+        let statements: Vec<String> = block.nodes().map(|statement| source[statement.range()].to_string()).collect();
+        assert_eq!(statements, ["let x = a + b", "return x"]);
+    }
+
+    #[test]
+    fn keeps_broken_scripts() {
+        let pool = StringPool::new();
+        let sources = [
+            "",
+            "fun f(:\n\tlet = \n",
+            "extends Node as A\nlet x = 1\n\t\treturn é\nfun g()",
+            "extends Node as A\r\n\r\nfun f():\r\n\t\"open\r\n"
+        ];
+        for source in sources {
+            assert_eq!(parse(&pool, "a.pony.script", source).text(), source);
+        }
+
+        let script = parse(&pool, "a.pony.script", "extends Node as A\nlet x = 1\nfun g():\n\tg()\n");
+        let kinds: Vec<SyntaxKind> = script.nodes().map(|node| node.kind).collect();
+        assert_eq!(kinds, [SyntaxKind::Header, SyntaxKind::Error, SyntaxKind::Function]);
+    }
+}
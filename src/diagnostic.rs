@@ -0,0 +1,106 @@
+use std::fmt::{Display, Formatter};
+use crate::ast::Span;
+
+/// An error found in a script, along with where it was found, as far as that is known.
+#[derive(Clone)]
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The file the error is in, as given to the lexer.
+    pub file: Option<String>,
+    pub span: Option<Span>,
+    pub message: String
+}
+
+impl Diagnostic {
+    pub fn new(message: String) -> Self {
+        Diagnostic { file: None, span: None, message }
+    }
+
+    pub fn at(file: &str, span: Span, message: String) -> Self {
+        Diagnostic { file: Some(file.to_string()), span: Some(span), message }
+    }
+
+    /// A diagnostic at a position in a file that is attached later, with `in_file`.
+    pub fn spanned(span: Span, message: String) -> Self {
+        Diagnostic { file: None, span: Some(span), message }
+    }
+
+    /// Attaches a file to a diagnostic that does not know where it came from yet.
+    pub fn in_file(mut self, file: &str) -> Self {
+        if self.file.is_none() {
+            self.file = Some(file.to_string());
+        }
+        self
+    }
+}
+
+/// The edit distance between two names: how many characters have to be inserted, removed or
+/// replaced to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(a != *b);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Appends a suggestion to `message` if one of `candidates` is close enough to `name` to be what
+/// was meant: at most a third of its characters off, rounded to the nearest. The closest one
+/// wins, and the first of those on a tie.
+pub fn did_you_mean<I: IntoIterator<Item = String>>(message: String, name: &str, candidates: I) -> String {
+    let limit = (name.chars().count() + 1) / 3;
+    let best = candidates.into_iter()
+        .filter(|candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance);
+
+    match best {
+        Some((_, candidate)) => format!("{} (did you mean {}?)", message, candidate),
+        None => message
+    }
+}
+
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Self {
+        Diagnostic::new(message)
+    }
+}
+
+/// Formats the diagnostic as `file:line:column: message`, leaving out whatever is unknown.
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        if let Some(span) = &self.span {
+            write!(f, "{}:{}:", span.line, span.column)?;
+        }
+        if self.file.is_some() || self.span.is_some() {
+            f.write_str(" ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggests_close_names() {
+        assert_eq!(edit_distance("count", "cuont"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+
+        let names = || ["total", "count", "counter"].map(String::from);
+        assert_eq!(did_you_mean(String::from("unknown variable coutn"), "coutn", names()), "unknown variable coutn (did you mean count?)");
+        assert_eq!(did_you_mean(String::from("unknown variable countr"), "countr", names()), "unknown variable countr (did you mean count?)");
+        assert_eq!(did_you_mean(String::from("unknown variable x"), "x", names()), "unknown variable x");
+    }
+}
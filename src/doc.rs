@@ -0,0 +1,390 @@
+//! `ponyscript doc`, which writes API documentation for scripts from their `##` doc comments.
+//!
+//! Every script gets a page listing its base type and its functions with their signatures, and
+//! an index page links to all of them. Types that are other documented scripts link to their
+//! pages, and each page lists the scripts that refer to it.
+
+use crate::ast::{Node, Tree, Type};
+use crate::bindings::{Bindings, FunID};
+use crate::Compiler;
+
+#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq)]
+pub enum DocFormat {
+    Html,
+    Markdown
+}
+
+impl DocFormat {
+    pub fn parse(name: &str) -> Result<DocFormat, String> {
+        match name {
+            "html" => Ok(DocFormat::Html),
+            "markdown" | "md" => Ok(DocFormat::Markdown),
+            _ => Err(format!("unknown doc format '{}', expected html or markdown", name))
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            DocFormat::Html => "html",
+            DocFormat::Markdown => "md"
+        }
+    }
+}
+
+/// One generated file.
+#[derive(Debug)]
+pub struct Page {
+    pub file_name: String,
+    pub content: String
+}
+
+struct Function {
+    name: String,
+    params: Vec<(String, Type)>,
+    return_type: Type,
+    doc: Option<String>,
+    /// The anchor of the function on its page, unique even among overloads.
+    anchor: String
+}
+
+impl Function {
+    /// The names of the types in the signature.
+    fn types(&self) -> Vec<String> {
+        let mut names = vec![];
+        for typ in self.params.iter().map(|(_, typ)| typ).chain(std::iter::once(&self.return_type)) {
+            type_names(typ, &mut names);
+        }
+        names
+    }
+
+    /// The signature as it would be written in a script, with the types written by `typ`.
+    fn signature(&self, typ: impl Fn(&Type) -> String) -> String {
+        let params: Vec<String> = self.params.iter().map(|(name, param)| format!("{}: {}", name, typ(param))).collect();
+        let mut signature = format!("fun {}({})", self.name, params.join(", "));
+        if self.return_type != Type::Void {
+            signature += &format!(" -> {}", typ(&self.return_type));
+        }
+        signature
+    }
+}
+
+struct Script {
+    own: String,
+    base: String,
+    source: String,
+    doc: Option<String>,
+    functions: Vec<Function>
+}
+
+impl Script {
+    /// The types this script refers to, in the order they come up.
+    fn references(&self) -> Vec<String> {
+        let types = self.functions.iter().flat_map(|function| function.types());
+        let mut references = vec![];
+        for name in std::iter::once(self.base.clone()).chain(types) {
+            if name != self.own && !references.contains(&name) {
+                references.push(name);
+            }
+        }
+        references
+    }
+}
+
+fn type_names(typ: &Type, names: &mut Vec<String>) {
+    match typ {
+        Type::Primitive(name) => names.push(name.to_string()),
+        Type::Parameterized(name, inner) => {
+            names.push(name.to_string());
+            inner.iter().for_each(|typ| type_names(typ, names));
+        }
+        Type::Optional(inner) | Type::Deref(inner) => type_names(inner, names),
+        _ => {}
+    }
+}
+
+fn function(bindings: &Bindings, id: FunID) -> Function {
+    let fun = bindings.get_fun(id);
+    let params = fun.args.iter()
+        .map(|arg| {
+            let var = bindings.get_var(*arg);
+            (var.name.to_string(), var.typ.clone())
+        })
+        .collect();
+
+    Function {
+        name: fun.name.to_string(),
+        params,
+        return_type: fun.return_type.clone(),
+        doc: fun.doc.clone(),
+        anchor: fun.output_name.clone()
+    }
+}
+
+fn script(bindings: &Bindings, tree: &Tree) -> Script {
+    let functions = tree.children.iter()
+        .filter_map(|child| match child {
+            Node::FunDecl(f) if !bindings.get_fun(f.bind_id).test => Some(function(bindings, f.bind_id)),
+            _ => None
+        })
+        .collect();
+
+    Script {
+        own: tree.own_type.to_string(),
+        base: tree.base_type.to_string(),
+        source: tree.source.clone(),
+        doc: tree.doc.clone(),
+        functions
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// The first paragraph of a doc comment.
+fn summary(doc: &Option<String>) -> &str {
+    doc.as_deref().and_then(|doc| doc.split("\n\n").next()).unwrap_or("")
+}
+
+struct Writer<'a> {
+    format: DocFormat,
+    scripts: &'a [Script],
+    out: String
+}
+
+impl<'a> Writer<'a> {
+    fn is_script(&self, name: &str) -> bool {
+        self.scripts.iter().any(|script| script.own == name)
+    }
+
+    fn page_name(&self, own: &str) -> String {
+        format!("{}.{}", own, self.format.extension())
+    }
+
+    /// A type name, linked to its page if it is one of the scripts.
+    fn type_link(&self, name: &str) -> String {
+        match (self.format, self.is_script(name)) {
+            (DocFormat::Html, true) => format!("<a href=\"{}\">{}</a>", self.page_name(name), escape_html(name)),
+            (DocFormat::Html, false) => escape_html(name),
+            (DocFormat::Markdown, true) => format!("[`{}`]({})", name, self.page_name(name)),
+            (DocFormat::Markdown, false) => format!("`{}`", name)
+        }
+    }
+
+    fn heading(&mut self, level: usize, text: &str, anchor: Option<&str>) {
+        match self.format {
+            DocFormat::Html => {
+                let id = anchor.map(|anchor| format!(" id=\"{}\"", escape_html(anchor))).unwrap_or_default();
+                self.out += &format!("<h{level}{}>{}</h{level}>\n", id, escape_html(text));
+            }
+            DocFormat::Markdown => self.out += &format!("{} {}\n\n", "#".repeat(level), text)
+        }
+    }
+
+    fn paragraph(&mut self, html: String, markdown: String) {
+        match self.format {
+            DocFormat::Html => self.out += &format!("<p>{}</p>\n", html),
+            DocFormat::Markdown => self.out += &format!("{}\n\n", markdown)
+        }
+    }
+
+    /// A doc comment. Its text is taken to be Markdown, so in HTML only its paragraphs are kept.
+    fn doc(&mut self, doc: &Option<String>) {
+        let Some(doc) = doc else { return; };
+        match self.format {
+            DocFormat::Html => {
+                for paragraph in doc.split("\n\n").filter(|paragraph| !paragraph.trim().is_empty()) {
+                    self.out += &format!("<p>{}</p>\n", escape_html(paragraph.trim()));
+                }
+            }
+            DocFormat::Markdown => self.out += &format!("{}\n\n", doc.trim())
+        }
+    }
+
+    fn list(&mut self, items: Vec<(String, String)>) {
+        match self.format {
+            DocFormat::Html => {
+                self.out += "<ul>\n";
+                items.into_iter().for_each(|(html, _)| self.out += &format!("<li>{}</li>\n", html));
+                self.out += "</ul>\n";
+            }
+            DocFormat::Markdown => {
+                items.into_iter().for_each(|(_, markdown)| self.out += &format!("- {}\n", markdown));
+                self.out.push('\n');
+            }
+        }
+    }
+
+    fn begin(&mut self, title: &str) {
+        if self.format == DocFormat::Html {
+            self.out += &format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n", escape_html(title));
+        }
+    }
+
+    fn end(&mut self) {
+        if self.format == DocFormat::Html {
+            self.out += "</body>\n</html>\n";
+        }
+    }
+
+    /// A type as HTML, with the names of scripts linked to their pages.
+    fn html_type(&self, typ: &Type) -> String {
+        match typ {
+            Type::Primitive(name) => self.type_link(&name.to_string()),
+            Type::Parameterized(name, inner) => {
+                let inner: Vec<String> = inner.iter().map(|typ| self.html_type(typ)).collect();
+                format!("{}[{}]", self.type_link(&name.to_string()), inner.join(", "))
+            }
+            Type::Optional(inner) => format!("?{}", self.html_type(inner)),
+            Type::Deref(inner) => format!("+{}", self.html_type(inner)),
+            _ => escape_html(&typ.source_name())
+        }
+    }
+
+    fn signature(&mut self, function: &Function) {
+        match self.format {
+            DocFormat::Html => {
+                // The rest of the signature is names and punctuation, which need no escaping.
+                let code = function.signature(|typ| self.html_type(typ)).replace("->", "-&gt;");
+                self.out += &format!("<pre><code>{}</code></pre>\n", code);
+            }
+            DocFormat::Markdown => {
+                self.out += &format!("```ponyscript\n{}\n```\n\n", function.signature(Type::source_name));
+                let mut linked: Vec<String> = vec![];
+                for name in function.types() {
+                    if self.is_script(&name) && !linked.contains(&name) {
+                        linked.push(name);
+                    }
+                }
+                if !linked.is_empty() {
+                    let links: Vec<String> = linked.iter().map(|name| self.type_link(name)).collect();
+                    self.out += &format!("Uses {}.\n\n", links.join(", "));
+                }
+            }
+        }
+    }
+
+    fn script_page(&mut self, script: &Script) {
+        self.begin(&script.own);
+        self.heading(1, &script.own, None);
+
+        let base = self.type_link(&script.base);
+        let source = match self.format {
+            DocFormat::Html => format!("<code>{}</code>", escape_html(&script.source)),
+            DocFormat::Markdown => format!("`{}`", script.source)
+        };
+        self.paragraph(format!("extends {}, from {}", base, source), format!("extends {}, from {}", base, source));
+        self.doc(&script.doc);
+
+        if !script.functions.is_empty() {
+            self.heading(2, "Functions", None);
+            for function in &script.functions {
+                self.heading(3, &function.name, Some(&function.anchor));
+                self.signature(function);
+                self.doc(&function.doc);
+            }
+        }
+
+        let users: Vec<(String, String)> = self.scripts.iter()
+            .filter(|other| other.references().contains(&script.own))
+            .map(|other| (self.type_link(&other.own), self.type_link(&other.own)))
+            .collect();
+        if !users.is_empty() {
+            self.heading(2, "Used by", None);
+            self.list(users);
+        }
+        self.end();
+    }
+
+    fn index_page(&mut self) {
+        self.begin("Scripts");
+        self.heading(1, "Scripts", None);
+        let items = self.scripts.iter()
+            .map(|script| {
+                let link = self.type_link(&script.own);
+                let summary = summary(&script.doc);
+                if summary.is_empty() {
+                    (link.clone(), link)
+                }
+                else {
+                    (format!("{}: {}", link, escape_html(summary)), format!("{}: {}", link, summary.replace('\n', " ")))
+                }
+            })
+            .collect();
+        self.list(items);
+        self.end();
+    }
+}
+
+/// Generates the pages for every script the compiler parsed, plus an index page.
+pub fn generate(compiler: &Compiler, format: DocFormat) -> Vec<Page> {
+    let scripts: Vec<Script> = compiler.trees().iter()
+        .filter_map(|tree| match tree {
+            Node::Tree(tree) => Some(script(compiler.bindings(), tree)),
+            _ => None
+        })
+        .collect();
+
+    let mut pages = vec![];
+    for script in &scripts {
+        let mut writer = Writer { format, scripts: &scripts, out: String::new() };
+        writer.script_page(script);
+        pages.push(Page { file_name: writer.page_name(&script.own), content: writer.out });
+    }
+
+    let mut writer = Writer { format, scripts: &scripts, out: String::new() };
+    writer.index_page();
+    pages.push(Page { file_name: format!("index.{}", format.extension()), content: writer.out });
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Output;
+    use super::*;
+
+    fn compile() -> Compiler {
+        let mut compiler = Compiler::new(Output::Stdout);
+        compiler.parse_source_str("enemy.pony.script", "## Something to fight.\n##\n## Enemies <chase> the player.\nextends Node as Enemy\n\n## How hard it hits.\nfun damage() -> int:\n\treturn 3\n").unwrap();
+        compiler.parse_source_str("player.pony.script", "extends Node as Player\n\n## Takes a hit.\nfun hit(by: +Enemy, scale: float) -> ?Enemy:\n\treturn by\n\nfun heal():\n\treturn\n").unwrap();
+        compiler
+    }
+
+    #[test]
+    fn markdown_pages() {
+        let pages = generate(&compile(), DocFormat::Markdown);
+        let names: Vec<&str> = pages.iter().map(|page| page.file_name.as_str()).collect();
+        assert_eq!(names, ["Enemy.md", "Player.md", "index.md"]);
+
+        assert_eq!(pages[0].content, "# Enemy\n\nextends `Node`, from `enemy.pony.script`\n\nSomething to fight.\n\nEnemies <chase> the player.\n\n\
+            ## Functions\n\n### damage\n\n```ponyscript\nfun damage() -> int\n```\n\nHow hard it hits.\n\n\
+            ## Used by\n\n- [`Player`](Player.md)\n\n");
+        assert!(pages[1].content.contains("```ponyscript\nfun hit(by: +Enemy, scale: float) -> ?Enemy\n```\n\nUses [`Enemy`](Enemy.md).\n\nTakes a hit.\n\n### heal\n\n```ponyscript\nfun heal()\n```\n\n"));
+        assert!(!pages[1].content.contains("Used by"));
+        assert_eq!(pages[2].content, "# Scripts\n\n- [`Enemy`](Enemy.md): Something to fight.\n- [`Player`](Player.md)\n\n");
+    }
+
+    #[test]
+    fn links_each_used_script_once() {
+        let mut compiler = compile();
+        compiler.parse_source_str("ally.pony.script", "extends Node as Ally\n\nfun meet(a: Enemy, b: Player, c: ?Enemy):\n\treturn\n").unwrap();
+        let pages = generate(&compiler, DocFormat::Markdown);
+        let ally = pages.iter().find(|page| page.file_name == "Ally.md").unwrap();
+        assert!(ally.content.contains("Uses [`Enemy`](Enemy.md), [`Player`](Player.md).\n\n"), "{}", ally.content);
+    }
+
+    #[test]
+    fn html_pages() {
+        let pages = generate(&compile(), DocFormat::Html);
+        let enemy = &pages[0].content;
+        assert!(enemy.starts_with("<!DOCTYPE html>"));
+        assert!(enemy.contains("<p>Enemies &lt;chase&gt; the player.</p>"));
+        assert!(enemy.contains("<li><a href=\"Player.html\">Player</a></li>"));
+
+        let player = &pages[1].content;
+        assert!(player.contains("<pre><code>fun hit(by: +<a href=\"Enemy.html\">Enemy</a>, scale: float) -&gt; ?<a href=\"Enemy.html\">Enemy</a></code></pre>"));
+        assert!(player.ends_with("</body>\n</html>\n"));
+        assert_eq!(DocFormat::parse("pdf").unwrap_err(), "unknown doc format 'pdf', expected html or markdown");
+    }
+}
@@ -0,0 +1,228 @@
+//! `ponyscript fmt`, which reprints scripts in one canonical style.
+//!
+//! The style: tabs for indentation, one space around binary operators and `->`, one after commas
+//! and colons, none inside parentheses and brackets. Runs of blank lines shrink to a single one,
+//! and there is always a blank line after the `extends` line and between functions. Comments are
+//! kept where they are, re-indented to the code around them.
+//!
+//! Only scripts that parse are formatted, so formatting never changes what a script means.
+
+use crate::bindings::Bindings;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{lossless_tokens, Lexer, Token, TriviaKind};
+use crate::parser::Parser;
+use crate::string_pool::StringPool;
+
+enum Content {
+    Code { tokens: Vec<Token>, trailing: Option<String> },
+    Comment(String)
+}
+
+/// One line of output.
+struct Line {
+    /// The block level. Comments on a line of their own only find out theirs from the code after
+    /// them.
+    level: Option<i32>,
+    /// The line of the source the output line comes from.
+    source_line: i32,
+    content: Content
+}
+
+fn token_text(token: &Token) -> String {
+    match token {
+        Token::ID(name) | Token::Num(name) => name.to_utf8(),
+        Token::StringLiteral(bytes) => format!("\"{}\"", String::from_utf8_lossy(bytes)),
+        Token::LParen => String::from("("),
+        Token::RParen => String::from(")"),
+        Token::LBracket => String::from("["),
+        Token::RBracket => String::from("]"),
+        Token::Colon => String::from(":"),
+        Token::Plus => String::from("+"),
+        Token::Minus => String::from("-"),
+        Token::Equals => String::from("="),
+        Token::EqualsEquals => String::from("=="),
+        Token::NotEquals => String::from("!="),
+        Token::RArrow => String::from("->"),
+        Token::QuestionMark => String::from("?"),
+        Token::Comma => String::from(","),
+        Token::Dot => String::from("."),
+        Token::KeyLet => String::from("let"),
+        Token::KeyFun => String::from("fun"),
+        Token::KeyExtends => String::from("extends"),
+        Token::KeyAs => String::from("as"),
+        Token::KeyReturn => String::from("return"),
+        Token::BlockStart | Token::BlockEnd | Token::BadLex | Token::EOF => String::new()
+    }
+}
+
+fn is_operand(token: &Token) -> bool {
+    matches!(token, Token::ID(_) | Token::Num(_) | Token::StringLiteral(_) | Token::RParen | Token::RBracket)
+}
+
+/// Whether `+` and `-` are prefixes here (as in the type `+Node`) rather than binary operators.
+fn is_prefix(previous: Option<&Token>, token: &Token) -> bool {
+    match token {
+        Token::QuestionMark => true,
+        Token::Plus | Token::Minus => !previous.is_some_and(is_operand),
+        _ => false
+    }
+}
+
+fn format_code(tokens: &[Token]) -> String {
+    let mut code = String::new();
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 {
+            let previous = &tokens[index - 1];
+            let before_previous = index.checked_sub(2).map(|index| &tokens[index]);
+
+            let tight = matches!(token, Token::RParen | Token::RBracket | Token::Comma | Token::Colon | Token::Dot)
+                || matches!(previous, Token::LParen | Token::LBracket | Token::Dot)
+                || matches!(token, Token::LParen | Token::LBracket) && matches!(previous, Token::ID(_))
+                || is_prefix(before_previous, previous);
+            if !tight {
+                code.push(' ');
+            }
+        }
+        code += &token_text(token);
+    }
+    code
+}
+
+fn leading_tabs(source: &str, line: i32) -> i32 {
+    let text = source.split('\n').nth((line - 1) as usize).unwrap_or("");
+    text.bytes().take_while(|c| *c == b'\t').count() as i32
+}
+
+/// Splits the script into lines, with their tokens and comments. The tokens refer to `pool`.
+fn collect_lines(pool: &StringPool, name: &str, source: &str) -> Vec<Line> {
+    // Offsets only ever grow, so lines are counted from the last offset asked about.
+    let mut line = 1;
+    let mut counted = 0;
+    let mut line_at = |offset: usize| {
+        line += source[counted..offset].matches('\n').count() as i32;
+        counted = offset;
+        line
+    };
+
+    let mut lines: Vec<Line> = vec![];
+    let mut level = 0;
+    for token in lossless_tokens(pool, name, source) {
+        // Comments before a token are on lines of their own; the one after it is on its line.
+        let mut offset = token.range.start - token.leading.iter().map(|trivia| trivia.text.len()).sum::<usize>();
+        for trivia in &token.leading {
+            if trivia.kind == TriviaKind::Comment {
+                lines.push(Line { level: None, source_line: line_at(offset), content: Content::Comment(trivia.text.trim_end().to_string()) });
+            }
+            offset += trivia.text.len();
+        }
+
+        match token.token {
+            Token::BlockStart => level += 1,
+            Token::BlockEnd => level -= 1,
+            Token::EOF | Token::BadLex => break,
+            code => {
+                let source_line = line_at(token.range.start);
+                match lines.last_mut() {
+                    Some(Line { source_line: last, content: Content::Code { tokens, .. }, .. }) if *last == source_line => {
+                        tokens.push(code);
+                    }
+                    _ => lines.push(Line { level: Some(level), source_line, content: Content::Code { tokens: vec![code], trailing: None } })
+                }
+            }
+        }
+
+        if let Some(comment) = token.trailing.iter().find(|trivia| trivia.kind == TriviaKind::Comment) {
+            if let Some(Line { content: Content::Code { trailing, .. }, .. }) = lines.last_mut() {
+                *trailing = Some(comment.text.trim_end().to_string());
+            }
+        }
+    }
+
+    lines
+}
+
+/// Gives comments on lines of their own the indentation they were written with, as long as it
+/// lies between the levels of the code before and after them. A comment at the end of a block
+/// could belong to the block or to what follows it, and only its indentation tells.
+fn indent_comments(lines: &mut [Line], source: &str) {
+    let mut before = 0;
+    for index in 0..lines.len() {
+        match lines[index].level {
+            Some(level) => before = level,
+            None => {
+                let after = lines[index..].iter().find_map(|line| line.level).unwrap_or(0);
+                let tabs = leading_tabs(source, lines[index].source_line);
+                lines[index].level = Some(tabs.clamp(before.min(after), before.max(after)));
+            }
+        }
+    }
+}
+
+/// Reprints a script in the canonical style. Fails if the script does not parse.
+pub fn format(name: &str, source: &str) -> Result<String, Diagnostic> {
+    let pool = StringPool::new();
+    let mut bindings = Bindings::new();
+    Parser::new(Lexer::from_named_str(&pool, name.to_string(), source), &mut bindings).parse()?;
+
+    let mut lines = collect_lines(&pool, name, source);
+    indent_comments(&mut lines, source);
+
+    let newline = if source.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut output = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        let level = line.level.unwrap_or(0);
+        if index > 0 {
+            let previous = &lines[index - 1];
+            let previous_level = previous.level.unwrap_or(0);
+            let after_header = matches!(&previous.content, Content::Code { tokens, .. } if tokens.first() == Some(&Token::KeyExtends));
+            let blank = line.source_line > previous.source_line + 1 || level == 0 && (previous_level > 0 || after_header);
+            if blank {
+                output += newline;
+            }
+        }
+
+        output += &"\t".repeat(level.max(0) as usize);
+        match &line.content {
+            Content::Code { tokens, trailing } => {
+                output += &format_code(tokens);
+                if let Some(comment) = trailing {
+                    output.push(' ');
+                    output += comment;
+                }
+            }
+            Content::Comment(comment) => output += comment
+        }
+        output += newline;
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_spacing_and_blank_lines() {
+        let source = "extends Node as Calc\n# about add\n\n\n\nfun add ( a:int,b : ?int )->int:  # adds\n\tlet x:int=a+b\n\n\n\t# done\n\treturn x\nfun main():\n\tadd(1,2)\n\tCalc . add(1,2)\n";
+        let expected = "extends Node as Calc\n\n# about add\n\nfun add(a: int, b: ?int) -> int: # adds\n\tlet x: int = a + b\n\n\t# done\n\treturn x\n\nfun main():\n\tadd(1, 2)\n\tCalc.add(1, 2)\n";
+        let formatted = format("calc.pony.script", source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format("calc.pony.script", &formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn keeps_comment_levels_and_line_endings() {
+        let source = "extends Node as A\r\n\r\nfun f():\r\n\tlet x = 1\r\n\t# still in f\r\n# before g\r\nfun g():\r\n# in g\r\n\tlet y = 2";
+        let expected = "extends Node as A\r\n\r\nfun f():\r\n\tlet x = 1\r\n\t# still in f\r\n\r\n# before g\r\nfun g():\r\n# in g\r\n\tlet y = 2\r\n";
+        let formatted = format("a.pony.script", source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format("a.pony.script", &formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn formatted_examples_are_stable() {
+        let source = std::fs::read_to_string("examples/calc.pony.script").unwrap();
+        assert_eq!(format("calc.pony.script", &source).unwrap(), source);
+        assert!(format("bad.pony.script", "extends Node as Bad\n\nfun f(:\n").is_err());
+    }
+}
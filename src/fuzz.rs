@@ -0,0 +1,378 @@
+//! Fuzzing support: entry points that push arbitrary bytes through the front end, and a generator
+//! of random well-typed scripts for comparing the backends against each other.
+//!
+//! The entry points pass by returning; any panic is a bug. They take plain bytes so that an
+//! external fuzzer can drive them, while the tests below drive them from `Rng`.
+
+use std::fmt::Write as _;
+use std::io::BufReader;
+use std::path::Path;
+use crate::ast::{Span, Type};
+use crate::interp::Value;
+use crate::lexer::Lexer;
+use crate::lsp::Analysis;
+use crate::string_pool::StringPool;
+use crate::vm::Natives;
+use crate::{cst, format, CCompiler, Compiler, Output};
+
+const NAME: &str = "fuzz.pony.script";
+
+/// A xorshift generator, so that a run can be repeated from its seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Spread the seed out, so that small seeds do not start with long runs of zero bits.
+        let mixed = (seed ^ 0x9e37_79b9_7f4a_7c15).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        Rng(mixed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..n`. `n` must not be 0.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// True with the given chance, in percent.
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    pub fn pick<'t, T>(&mut self, items: &'t [T]) -> &'t T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// Lexes `data` as it is, invalid UTF-8 included, and prints every token.
+pub fn fuzz_lexer(data: &[u8]) {
+    let pool = StringPool::new();
+    let mut lexer = Lexer::new(&pool, NAME.to_string(), BufReader::new(data));
+
+    // Every token but the block ends consumes at least one byte.
+    let limit = data.len() * 2 + 2;
+    for _ in 0..limit {
+        let token = lexer.next();
+        let _ = format!("{:?}", token);
+        if token.is_eof() {
+            return;
+        }
+    }
+    panic!("the lexer did not reach the end of {:?}", String::from_utf8_lossy(data));
+}
+
+/// Runs `data` through everything that reads scripts: the lexer, the CST, the formatter, the
+/// compiler up to the generated C, and the language server's analysis with the cursor at the end.
+pub fn fuzz_front_end(data: &[u8]) {
+    fuzz_lexer(data);
+    let source = String::from_utf8_lossy(data);
+
+    let pool = StringPool::new();
+    assert_eq!(cst::parse(&pool, NAME, &source).text(), source, "the CST lost text");
+
+    if let Ok(formatted) = format::format(NAME, &source) {
+        assert_eq!(format::format(NAME, &formatted).as_ref(), Ok(&formatted), "formatting is not stable");
+    }
+
+    let mut compiler = Compiler::new(Output::Stdout);
+    if compiler.parse_source_str(NAME, &source).is_ok() && compiler.typecheck().is_ok() {
+        compiler.emit_c();
+        compiler.compile_bytecode().ok();
+    }
+
+    let last_line = source.rsplit('\n').next().unwrap_or("");
+    let cursor = Span::new(source.matches('\n').count() as i32 + 1, last_line.len() as i32 + 1);
+    let analysis = Analysis::with_cursor(NAME, &source, &[], cursor);
+    analysis.completions(last_line);
+    analysis.functions();
+}
+
+#[derive(Copy, Clone)]
+#[derive(PartialEq)]
+enum Kind {
+    Int,
+    Float
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Int => "int",
+            Kind::Float => "float"
+        }
+    }
+}
+
+struct Signature {
+    name: String,
+    params: Vec<Kind>,
+    result: Kind
+}
+
+/// A generated script, and calls (written as `Type.fun(args...)`) to run in it.
+pub struct Program {
+    pub source: String,
+    pub calls: Vec<String>
+}
+
+struct Generator<'r> {
+    rng: &'r mut Rng,
+    functions: Vec<Signature>,
+    locals: Vec<(String, Kind)>
+}
+
+impl Generator<'_> {
+    fn literal(&mut self) -> String {
+        self.rng.below(100).to_string()
+    }
+
+    /// An operand of a sum, of the given kind.
+    fn operand(&mut self, kind: Kind, depth: usize) -> String {
+        let locals: Vec<String> = self.locals.iter().filter(|(_, k)| *k == kind).map(|(name, _)| name.clone()).collect();
+        let callable: Vec<usize> = (0..self.functions.len()).filter(|index| self.functions[*index].result == kind).collect();
+
+        match self.rng.below(3) {
+            0 if !locals.is_empty() => self.rng.pick(&locals).clone(),
+            1 if !callable.is_empty() && depth > 0 => {
+                let index = *self.rng.pick(&callable);
+                self.call(index, depth - 1)
+            }
+            _ => self.literal()
+        }
+    }
+
+    fn call(&mut self, index: usize, depth: usize) -> String {
+        let params = self.functions[index].params.clone();
+        let args: Vec<String> = params.iter().map(|param| self.expr(*param, depth)).collect();
+        format!("{}({})", self.functions[index].name, args.join(", "))
+    }
+
+    fn sum(&mut self, kind: Kind, depth: usize) -> String {
+        let mut sum = self.operand(kind, depth);
+        while depth > 0 && self.rng.chance(30) {
+            sum = format!("{} + {}", sum, self.operand(kind, depth - 1));
+        }
+        sum
+    }
+
+    /// An expression of the given kind. Comparisons give ints, so only those may be comparisons.
+    fn expr(&mut self, kind: Kind, depth: usize) -> String {
+        if kind == Kind::Int && depth > 0 && self.rng.chance(20) {
+            let compared = *self.rng.pick(&[Kind::Int, Kind::Float]);
+            let op = self.rng.pick(&["==", "!="]);
+            return format!("{} {} {}", self.sum(compared, depth - 1), op, self.sum(compared, depth - 1));
+        }
+        self.sum(kind, depth)
+    }
+
+    fn function(&mut self, source: &mut String) {
+        let name = format!("f{}", self.functions.len());
+        let params: Vec<Kind> = (0..self.rng.below(3)).map(|_| *self.rng.pick(&[Kind::Int, Kind::Float])).collect();
+        let result = *self.rng.pick(&[Kind::Int, Kind::Float]);
+
+        self.locals = params.iter().enumerate().map(|(index, kind)| (format!("p{}", index), *kind)).collect();
+        let header: Vec<String> = self.locals.iter().map(|(name, kind)| format!("{}: {}", name, kind.name())).collect();
+        writeln!(source, "fun {}({}) -> {}:", name, header.join(", "), result.name()).unwrap();
+
+        for _ in 0..self.rng.below(5) {
+            let kind = *self.rng.pick(&[Kind::Int, Kind::Float]);
+            match self.rng.below(3) {
+                0 if self.locals.iter().any(|(_, k)| *k == kind) => {
+                    let locals: Vec<String> = self.locals.iter().filter(|(_, k)| *k == kind).map(|(name, _)| name.clone()).collect();
+                    let local = self.rng.pick(&locals).clone();
+                    let expr = self.expr(kind, 2);
+                    writeln!(source, "\t{} = {}", local, expr).unwrap();
+                }
+                1 if !self.functions.is_empty() => {
+                    let index = self.rng.below(self.functions.len());
+                    let call = self.call(index, 1);
+                    writeln!(source, "\t{}", call).unwrap();
+                }
+                _ => {
+                    let local = format!("v{}", self.locals.len());
+                    let expr = self.expr(kind, 2);
+                    writeln!(source, "\tlet {}: {} = {}", local, kind.name(), expr).unwrap();
+                    self.locals.push((local, kind));
+                }
+            }
+        }
+
+        let expr = self.expr(result, 2);
+        writeln!(source, "\treturn {}\n", expr).unwrap();
+        self.functions.push(Signature { name, params, result });
+    }
+}
+
+/// Generates a script declaring the type `own`, which typechecks. Its functions only call the
+/// ones before them, and keep their numbers small enough not to overflow.
+pub fn generate(rng: &mut Rng, own: &str) -> Program {
+    let mut generator = Generator { rng, functions: vec![], locals: vec![] };
+    let mut source = format!("extends Node as {}\n\n", own);
+    for _ in 0..generator.rng.below(5) + 1 {
+        generator.function(&mut source);
+    }
+
+    let mut calls = vec![];
+    for fun in &generator.functions {
+        let args: Vec<String> = fun.params.iter().map(|param| match param {
+            Kind::Int => generator.rng.below(50).to_string(),
+            Kind::Float => format!("{}.5", generator.rng.below(50))
+        }).collect();
+        calls.push(format!("{}.{}({})", own, fun.name, args.join(", ")));
+    }
+
+    Program { source, calls }
+}
+
+/// Compiles the programs to C with `cc`, in a scratch directory under `dir`, and checks that every
+/// call gives the same result there as on the interpreter and on the VM.
+pub fn check_backends_agree(programs: &[Program], cc: &CCompiler, dir: &Path) -> Result<(), String> {
+    let mut compiler = Compiler::new(Output::Stdout);
+    compiler.set_line_directives(false);
+    for (index, program) in programs.iter().enumerate() {
+        compiler.parse_source_str(&format!("fuzz{}.pony.script", index), &program.source)
+            .map_err(|error| format!("{}\n{}", error, program.source))?;
+    }
+    compiler.typecheck().map_err(|error| error.to_string())?;
+
+    let calls: Vec<&String> = programs.iter().flat_map(|program| &program.calls).collect();
+
+    // A C program that prints the result of each call, one per line.
+    let mut c = compiler.emit_c();
+    c += "int main(void) {\n";
+    for call in &calls {
+        let (id, _) = compiler.resolve_call(call)?;
+        let (_, _, args) = crate::interp::parse_call(call)?;
+        let fun = compiler.bindings().get_fun(id);
+        let format = if fun.return_type == Type::Float { "%.9g" } else { "%d" };
+        writeln!(c, "printf(\"{}\\n\", {}({}));", format, fun.output_name, args.join(", ")).unwrap();
+    }
+    c += "return 0;\n}\n";
+
+    std::fs::create_dir_all(dir).map_err(|error| error.to_string())?;
+    let source = dir.join("fuzz.c");
+    let exe = dir.join("fuzz");
+    std::fs::write(&source, c).map_err(|error| error.to_string())?;
+    let status = std::process::Command::new(&cc.program).arg("-o").arg(&exe).arg(&source).status()
+        .map_err(|error| error.to_string())?;
+    if !status.success() {
+        return Err(format!("{} rejected the generated code in {}", cc.program, source.display()));
+    }
+    let output = std::process::Command::new(&exe).output().map_err(|error| error.to_string())?;
+    let output = String::from_utf8_lossy(&output.stdout).to_string();
+
+    let lines: Vec<&str> = output.lines().collect();
+    if lines.len() != calls.len() {
+        return Err(format!("expected {} results from C, got {}", calls.len(), lines.len()));
+    }
+    for (call, c_result) in calls.iter().zip(lines) {
+        let value = compiler.run(call)?;
+        let matches = match value {
+            Value::Int(value) => c_result.parse::<i32>() == Ok(value),
+            Value::Float(value) => c_result.parse::<f32>() == Ok(value),
+            Value::Void => false
+        };
+        if !matches {
+            return Err(format!("{}: the interpreter gave {}, C gave {}", call, value, c_result));
+        }
+
+        let vm_value = compiler.run_vm(call, Natives::new())?;
+        if vm_value != value {
+            return Err(format!("{}: the interpreter gave {}, the VM gave {}", call, value, vm_value));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How many inputs each test tries. Set `PONYSCRIPT_FUZZ_RUNS` for longer runs.
+    fn runs(default: usize) -> usize {
+        std::env::var("PONYSCRIPT_FUZZ_RUNS").ok().and_then(|runs| runs.parse().ok()).unwrap_or(default)
+    }
+
+    /// The generator of a test. Set `PONYSCRIPT_FUZZ_SEED` to try other inputs.
+    fn rng(test: u64) -> Rng {
+        let seed: u64 = std::env::var("PONYSCRIPT_FUZZ_SEED").ok().and_then(|seed| seed.parse().ok()).unwrap_or(0);
+        Rng::new(seed.wrapping_mul(16) + test)
+    }
+
+    /// Bytes that are likely to mean something to the lexer.
+    const PIECES: [&str; 28] = [
+        "extends", "Node", "as", "fun", "let", "return", "test", "assert", "x", "1", "(", ")", ":",
+        "+", "-", "->", "=", "==", "!=", "?", "[", "]", ",", ".", "\"", "#", "\n\t", "é"
+    ];
+
+    #[test]
+    fn random_bytes_do_not_panic() {
+        let mut rng = rng(1);
+        for _ in 0..runs(300) {
+            let mut data = vec![];
+            for _ in 0..rng.below(60) {
+                match rng.below(4) {
+                    0 => data.push(rng.next_u64() as u8),
+                    1 => data.push(*rng.pick(b" \t\r\n")),
+                    _ => data.extend_from_slice(rng.pick(&PIECES).as_bytes())
+                }
+                if rng.chance(20) {
+                    data.push(b' ');
+                }
+            }
+            fuzz_front_end(&data);
+        }
+    }
+
+    #[test]
+    fn mutated_programs_do_not_panic() {
+        let mut rng = rng(2);
+        for _ in 0..runs(200) {
+            let mut data = generate(&mut rng, "Fuzz").source.into_bytes();
+            for _ in 0..rng.below(4) + 1 {
+                let at = rng.below(data.len() + 1);
+                match rng.below(3) {
+                    0 if at < data.len() => { data.remove(at); }
+                    1 => data.splice(at..at, rng.pick(&PIECES).bytes()).for_each(drop),
+                    _ => data.insert(at, rng.next_u64() as u8)
+                }
+            }
+            fuzz_front_end(&data);
+        }
+    }
+
+    #[test]
+    fn generated_programs_typecheck() {
+        let mut rng = rng(3);
+        for _ in 0..runs(100) {
+            let program = generate(&mut rng, "Fuzz");
+            let mut compiler = Compiler::new(Output::Stdout);
+            compiler.parse_source_str(NAME, &program.source).unwrap();
+            if let Err(error) = compiler.typecheck() {
+                panic!("{}\n{}", error, program.source);
+            }
+            fuzz_front_end(program.source.as_bytes());
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_generated_programs() {
+        let cc = CCompiler::from_env();
+        if std::process::Command::new(&cc.program).arg("--version").output().is_err() {
+            return;
+        }
+
+        let mut rng = rng(4);
+        let programs: Vec<Program> = (0..runs(40)).map(|index| generate(&mut rng, &format!("Fuzz{}", index))).collect();
+        let dir = std::env::temp_dir().join(format!("ponyscript-fuzz-{}", std::process::id()));
+        // Running a call looks at every script, so larger runs go in batches.
+        let result = programs.chunks(20).try_for_each(|batch| check_backends_agree(batch, &cc, &dir));
+        std::fs::remove_dir_all(&dir).ok();
+        result.unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+//! A tree-walking interpreter for typechecked trees.
+//!
+//! The interpreter runs the same `Node` trees that are handed to codegen, after typecheck has
+//! bound every variable and call. It does not resolve anything on its own: variables live in slots
+//! keyed by their `VarID`, and calls go to whichever overload typecheck bound them to. Script
+//! variables start at their initial values and keep their values for as long as the interpreter
+//! lives. Arithmetic
+//! follows the generated C code (32-bit wrapping integers and single precision floats), so results
+//! can be compared against the C backend.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::ast::{FunDecl, Node, Op, Type};
+use crate::bindings::{Bindings, FunID, VarID};
+use crate::diagnostic::Diagnostic;
+
+#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Void,
+    Int(i32),
+    Float(f32)
+}
+
+impl Value {
+    /// Parses a number given on the command line or in a test, for a parameter of type `typ`.
+    pub fn parse(text: &str, typ: &Type) -> Result<Value, String> {
+        let bad = || format!("'{}' is not a valid {}", text, typ.source_name());
+        match typ {
+            Type::Int32 => text.parse().map(Value::Int).map_err(|_| bad()),
+            Type::Float => text.parse().map(Value::Float).map_err(|_| bad()),
+            _ => Err(format!("cannot pass values of type {} to the interpreter", typ.source_name()))
+        }
+    }
+
+    /// The type of an argument written as `text`. Unlike a literal in a script, an integer given
+    /// on the command line is an exact value, and so an `int`.
+    pub fn literal_type(text: &str) -> Type {
+        if text.parse::<i32>().is_ok() { Type::Int32 } else { Type::Float }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Void => f.write_str("void"),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{:?}", value)
+        }
+    }
+}
+
+/// Splits a call written as `Type.fun(arg, ...)` (or just `Type.fun` without arguments) into the
+/// type name, the function name and the argument texts.
+pub fn parse_call(text: &str) -> Result<(String, String, Vec<String>), String> {
+    let bad = || format!("expected 'Type.function(args...)', got '{}'", text);
+
+    let (path, args) = match text.split_once('(') {
+        Some((path, rest)) => {
+            let args = rest.trim_end().strip_suffix(')').ok_or_else(bad)?;
+            let args: Vec<String> = args.split(',')
+                .map(|arg| arg.trim().to_string())
+                .filter(|arg| !arg.is_empty())
+                .collect();
+            (path, args)
+        }
+        None => (text, vec![])
+    };
+
+    let (typ, fun) = path.trim().split_once('.').ok_or_else(bad)?;
+    if typ.is_empty() || fun.is_empty() {
+        return Err(bad());
+    }
+
+    Ok((typ.to_string(), fun.to_string(), args))
+}
+
+/// The result of running a statement: either carry on with the next one, or leave the function.
+enum Flow {
+    Next,
+    Return(Value)
+}
+
+/// How deeply calls may nest before the interpreter gives up, rather than overflowing its own
+/// stack on runaway recursion.
+pub const MAX_CALL_DEPTH: usize = 256;
+
+/// The local variables of one function call, the file the function is in, and how many calls
+/// are under way below it.
+struct Frame<'a> {
+    slots: HashMap<VarID, Value>,
+    source: &'a str,
+    depth: usize
+}
+
+pub struct Interpreter<'a> {
+    bindings: &'a Bindings,
+    funs: HashMap<FunID, (&'a str, &'a FunDecl)>,
+    /// The values of the script variables.
+    state: RefCell<HashMap<VarID, Value>>
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(bindings: &'a Bindings, trees: &'a [Node]) -> Self {
+        let mut funs = HashMap::new();
+        let mut state = HashMap::new();
+        for tree in trees {
+            if let Node::Tree(tree) = tree {
+                for child in &tree.children {
+                    match child {
+                        Node::FunDecl(f) => {
+                            funs.insert(f.bind_id, (tree.source.as_str(), f));
+                        }
+                        Node::Decl(decl) => {
+                            let typ = &bindings.get_var(decl.bind_id).typ;
+                            let text = match decl.expr.as_deref() {
+                                Some(Node::NumConst(num)) => num.value_str.to_utf8(),
+                                _ => String::from("0")
+                            };
+                            // A number too large for its type leaves the variable without a value.
+                            if let Ok(value) = Value::parse(&text, typ) {
+                                state.insert(decl.bind_id, value);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        Interpreter { bindings, funs, state: RefCell::new(state) }
+    }
+
+    /// Calls a function with already-converted arguments, returning its result.
+    pub fn call(&self, id: FunID, args: &[Value]) -> Result<Value, String> {
+        self.call_at(id, args, 0)
+    }
+
+    fn call_at(&self, id: FunID, args: &[Value], depth: usize) -> Result<Value, String> {
+        let binding = self.bindings.get_fun(id);
+        if depth >= MAX_CALL_DEPTH {
+            return Err(format!("call depth exceeded {} in {}", MAX_CALL_DEPTH, binding.source_name()));
+        }
+        let (source, decl) = self.funs.get(&id)
+            .ok_or_else(|| format!("function {} has no body", binding.source_name()))?;
+
+        if binding.args.len() != args.len() {
+            return Err(format!("expected {} arguments, got {}", binding.args.len(), args.len()));
+        }
+
+        let mut frame = Frame { slots: HashMap::new(), source, depth };
+        for (param, value) in binding.args.iter().zip(args) {
+            frame.slots.insert(*param, *value);
+        }
+
+        for stmt in &decl.body {
+            if let Flow::Return(value) = self.exec(&mut frame, &stmt.node)? {
+                return Ok(value);
+            }
+        }
+
+        Ok(Value::Void)
+    }
+
+    fn exec(&self, frame: &mut Frame<'a>, node: &Node) -> Result<Flow, String> {
+        match node {
+            Node::Decl(decl) => {
+                if let Some(expr) = &decl.expr {
+                    let value = self.eval(frame, expr)?;
+                    frame.slots.insert(decl.bind_id, value);
+                }
+            }
+            Node::Assign(point, _, expr) => {
+                let id = point.expect_bound()?;
+                let value = self.eval(frame, expr)?;
+                if self.bindings.get_var(id).state {
+                    self.state.borrow_mut().insert(id, value);
+                }
+                else {
+                    frame.slots.insert(id, value);
+                }
+            }
+            Node::Return(expr) => {
+                let value = match expr {
+                    Some(expr) => self.eval(frame, expr)?,
+                    None => Value::Void
+                };
+                return Ok(Flow::Return(value));
+            }
+            Node::Assert(span, cond, message) => {
+                let holds = match self.eval(frame, cond)? {
+                    Value::Int(value) => value != 0,
+                    Value::Float(value) => value != 0.0,
+                    Value::Void => false
+                };
+                if !holds {
+                    let message = message.as_deref().unwrap_or("assertion failed");
+                    return Err(Diagnostic::at(frame.source, *span, message.to_string()).to_string());
+                }
+            }
+            Node::Empty => {}
+            _ => {
+                self.eval(frame, node)?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn eval(&self, frame: &mut Frame<'a>, node: &Node) -> Result<Value, String> {
+        match node {
+            Node::NumConst(num) => {
+                let text = num.value_str.to_utf8();
+                Value::parse(&text, &num.typ)
+            }
+            Node::VarRef(point, _) => {
+                let id = point.expect_bound()?;
+                if self.bindings.get_var(id).state {
+                    return self.state.borrow().get(&id).copied()
+                        .ok_or_else(|| format!("script variable {} has no value", self.bindings.get_var(id).source_name()));
+                }
+                frame.slots.get(&id).copied().ok_or_else(|| {
+                    format!("variable {} read before it was assigned", self.bindings.get_var(id).source_name())
+                })
+            }
+            Node::FunCall(_, point, _, args) => {
+                let id = point.expect_bound()?;
+                let mut values = vec![];
+                for arg in args {
+                    values.push(self.eval(frame, arg)?);
+                }
+                self.call_at(id, &values, frame.depth + 1)
+            }
+            Node::BinOp(op, lhs, rhs) => {
+                let lhs = self.eval(frame, lhs)?;
+                let rhs = self.eval(frame, rhs)?;
+                binop(op, lhs, rhs)
+            }
+            _ => Err(String::from("not an expression"))
+        }
+    }
+}
+
+pub fn binop(op: &Op, lhs: Value, rhs: Value) -> Result<Value, String> {
+    match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => {
+            let result = match op {
+                Op::Add => a.wrapping_add(b),
+                Op::Subtract => a.wrapping_sub(b),
+                Op::Multiply => a.wrapping_mul(b),
+                Op::Divide => {
+                    if b == 0 {
+                        return Err(String::from("integer division by zero"));
+                    }
+                    a.wrapping_div(b)
+                }
+                Op::Equal => (a == b) as i32,
+                Op::NotEqual => (a != b) as i32
+            };
+            Ok(Value::Int(result))
+        }
+        (Value::Float(a), Value::Float(b)) => {
+            let result = match op {
+                Op::Add => a + b,
+                Op::Subtract => a - b,
+                Op::Multiply => a * b,
+                Op::Divide => a / b,
+                // Comparisons give an int, even of floats.
+                Op::Equal => return Ok(Value::Int((a == b) as i32)),
+                Op::NotEqual => return Ok(Value::Int((a != b) as i32))
+            };
+            Ok(Value::Float(result))
+        }
+        _ => Err(format!("cannot apply '{}' to {} and {}", op.impl_str(), lhs, rhs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_calls() {
+        assert_eq!(parse_call("Calc.main").unwrap(), (String::from("Calc"), String::from("main"), vec![]));
+        assert_eq!(parse_call("Calc.add(1, 2.5)").unwrap(),
+                   (String::from("Calc"), String::from("add"), vec![String::from("1"), String::from("2.5")]));
+        assert!(parse_call("main").is_err());
+        assert!(parse_call("Calc.add(1").is_err());
+    }
+
+    #[test]
+    fn int_arithmetic_wraps_like_c() {
+        assert_eq!(binop(&Op::Add, Value::Int(i32::MAX), Value::Int(1)).unwrap(), Value::Int(i32::MIN));
+        assert!(binop(&Op::Divide, Value::Int(1), Value::Int(0)).is_err());
+        assert!(binop(&Op::Add, Value::Int(1), Value::Float(1.0)).is_err());
+    }
+}
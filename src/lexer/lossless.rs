@@ -0,0 +1,186 @@
+//! A lossless token stream: every byte of the source belongs to a token or to the trivia around
+//! one, so tools that rewrite scripts can reproduce them exactly.
+
+use std::ops::Range;
+use crate::string_pool::StringPool;
+use super::{Lexer, Token};
+
+#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq)]
+pub enum TriviaKind {
+    /// Spaces, tabs and stray carriage returns.
+    Whitespace,
+    /// `\n` or `\r\n`.
+    Newline,
+    /// A `#` comment, without its line break.
+    Comment
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String
+}
+
+/// A token with the source text around it. Trailing trivia is what follows the token on its own
+/// line; everything else between two tokens is leading trivia of the second one.
+#[derive(Debug)]
+pub struct LosslessToken {
+    pub token: Token,
+    /// The byte offsets of the token itself. Block tokens and the final EOF are empty.
+    pub range: Range<usize>,
+    pub text: String,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>
+}
+
+impl LosslessToken {
+    /// Whether the token is the first on its line.
+    pub fn starts_line(&self) -> bool {
+        self.leading.iter().any(|trivia| trivia.kind == TriviaKind::Newline)
+    }
+
+    /// Appends the token with its trivia, as it was in the source.
+    pub fn write_to(&self, out: &mut String) {
+        self.leading.iter().for_each(|trivia| out.push_str(&trivia.text));
+        out.push_str(&self.text);
+        self.trailing.iter().for_each(|trivia| out.push_str(&trivia.text));
+    }
+}
+
+fn split_trivia(text: &str) -> Vec<Trivia> {
+    let mut trivia = vec![];
+    let mut rest = text;
+    while !rest.is_empty() {
+        let (kind, length) = if rest.starts_with('#') {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            let end = if rest[..end].ends_with('\r') && end < rest.len() { end - 1 } else { end };
+            (TriviaKind::Comment, end)
+        }
+        else if rest.starts_with("\r\n") {
+            (TriviaKind::Newline, 2)
+        }
+        else if rest.starts_with('\n') {
+            (TriviaKind::Newline, 1)
+        }
+        else {
+            let end = rest.char_indices()
+                .find(|(index, c)| *c == '\n' || *c == '#' || rest[*index..].starts_with("\r\n"))
+                .map_or(rest.len(), |(index, _)| index);
+            (TriviaKind::Whitespace, end)
+        };
+        trivia.push(Trivia { kind, text: rest[..length].to_string() });
+        rest = &rest[length..];
+    }
+    trivia
+}
+
+/// Lexes `source` into lossless tokens, ending with `EOF`. Block tokens are kept, empty, right
+/// before the token that starts the new block level. Names in the tokens are pooled in `pool`.
+pub fn lossless_tokens(pool: &StringPool, name: &str, source: &str) -> Vec<LosslessToken> {
+    let mut lexer = Lexer::from_named_str(pool, name.to_string(), source);
+
+    let mut tokens: Vec<LosslessToken> = vec![];
+    // The end of the last token that is not a block token.
+    let mut end = 0;
+    loop {
+        let token = lexer.next();
+        let block = matches!(token, Token::BlockStart | Token::BlockEnd);
+        let mut range = if block { lexer.position()..lexer.position() } else { lexer.offset()..lexer.position() };
+
+        if token.is_bad() {
+            // Text that could not be lexed is one token, even though it is lexed a byte at a time.
+            while !source.is_char_boundary(range.end) {
+                range.end += 1;
+            }
+            if let Some(last) = tokens.last_mut().filter(|last| last.token.is_bad() && range.start <= last.range.end) {
+                last.range.end = last.range.end.max(range.end);
+                last.text = source[last.range.clone()].to_string();
+                end = last.range.end;
+                continue;
+            }
+        }
+
+        let mut leading = vec![];
+        if !block {
+            let gap = &source[end..range.start];
+            let line_end = gap.find('\n').map_or(gap.len(), |index| if gap[..index].ends_with('\r') { index - 1 } else { index });
+            let (same_line, rest) = gap.split_at(line_end);
+            // The part of the gap on the line of the last token trails it.
+            match tokens.iter_mut().rev().find(|token| !matches!(token.token, Token::BlockStart | Token::BlockEnd)) {
+                Some(last) => {
+                    last.trailing = split_trivia(same_line);
+                    leading = split_trivia(rest);
+                }
+                None => leading = split_trivia(gap)
+            }
+            end = range.end;
+        }
+
+        let eof = token.is_eof();
+        let text = source[range.clone()].to_string();
+        tokens.push(LosslessToken { token, range, text, leading, trailing: vec![] });
+        if eof {
+            return tokens;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(tokens: &[LosslessToken]) -> String {
+        let mut out = String::new();
+        tokens.iter().for_each(|token| token.write_to(&mut out));
+        out
+    }
+
+    #[test]
+    fn tokens_carry_their_trivia() {
+        let pool = StringPool::new();
+        let source = "# header\nextends Node as A  # the type\r\n\nfun f():\n\treturn 1\n";
+        let tokens = lossless_tokens(&pool, "a.pony.script", source);
+        assert_eq!(round_trip(&tokens), source);
+
+        let extends = &tokens[0];
+        assert_eq!(extends.token, Token::KeyExtends);
+        assert_eq!(extends.range, 9..16);
+        assert_eq!(extends.leading, [
+            Trivia { kind: TriviaKind::Comment, text: String::from("# header") },
+            Trivia { kind: TriviaKind::Newline, text: String::from("\n") }
+        ]);
+
+        let own = &tokens[3];
+        assert!(own.token.is_id_str("A"));
+        assert_eq!(own.trailing, [
+            Trivia { kind: TriviaKind::Whitespace, text: String::from("  ") },
+            Trivia { kind: TriviaKind::Comment, text: String::from("# the type") }
+        ]);
+
+        let fun = &tokens[4];
+        assert!(fun.starts_line());
+        assert_eq!(fun.leading.len(), 2);
+        assert!(tokens.iter().any(|token| token.token == Token::BlockStart && token.range.is_empty()));
+        assert!(tokens.last().unwrap().token.is_eof());
+    }
+
+    #[test]
+    fn round_trips_anything() {
+        let pool = StringPool::new();
+        let sources = [
+            "",
+            "extends Node as A",
+            "  \t\n\n# only comments\r\n\t# indented",
+            "extends Node as A\n\nfun f(a:int)->int:\n\t\tlet x = \"open\n\treturn é + a\t\n",
+            "extends Node as A\r\nfun f():\r\n\tlet x = 1 # c\r\n\r\nfun g():\r\n\tg()"
+        ];
+        for source in sources {
+            assert_eq!(round_trip(&lossless_tokens(&pool, "a.pony.script", source)), source);
+        }
+
+        let tokens = lossless_tokens(&pool, "a.pony.script", "é1");
+        assert!(tokens[0].token.is_bad());
+        assert_eq!(tokens[0].text, "é");
+    }
+}
@@ -0,0 +1,33 @@
+//! PonyScript compiles GodotScript-like scripts into C, or runs them on an interpreter or a
+//! bytecode VM.
+//!
+//! The [`Compiler`] is the main entry point: feed it sources (from files or from strings),
+//! typecheck them, and then either emit the result to an [`Output`], retrieve the generated C as
+//! a `String`, or run functions directly. The parsed trees and their [`bindings::Bindings`] can be
+//! inspected through the compiler once typecheck has run.
+//!
+//! The string pool, lexer and parsers stay inside the crate: pooled names point at their pool
+//! without a lifetime, so only the compiler, which owns the pool, may hand them out.
+
+pub mod ast;
+pub mod bindings;
+pub mod compiler;
+pub(crate) mod cst;
+pub mod diagnostic;
+pub mod doc;
+pub mod format;
+pub mod fuzz;
+pub mod interp;
+pub mod json;
+pub(crate) mod lexer;
+pub mod lsp;
+pub mod manifest;
+pub(crate) mod parser;
+pub(crate) mod string_pool;
+pub mod vm;
+pub mod watch;
+
+pub use compiler::{CCompiler, Compiler, Output};
+pub use diagnostic::Diagnostic;
+pub use cst::dump_cst;
+pub use lexer::dump_tokens;
@@ -0,0 +1,627 @@
+//! A language server for PonyScript, speaking the Language Server Protocol over stdio.
+//!
+//! Documents are synced in full. Every change parses and typechecks the changed document, along
+//! with the other scripts of the workspace's project, whose types it can refer to: those listed by
+//! the `pony.toml` of the workspace root, or else every script under the root. Open documents are
+//! read from the editor, the others from disk. The server publishes the document's diagnostics, and answers hover, go-to-definition, document
+//! symbol and completion requests from the bindings. Parsing recovers from errors, so that
+//! documents that are being edited can still be looked into.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::ast::Span;
+use crate::json::Json;
+use crate::manifest::{self, Manifest, MANIFEST_NAME};
+
+mod analysis;
+
+pub use analysis::{Analysis, Completion, CompletionKind, FunSymbol, Occurrence, Target};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+const SEVERITY_ERROR: i64 = 1;
+const SYMBOL_FUNCTION: i64 = 12;
+const COMPLETION_FUNCTION: i64 = 3;
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_CLASS: i64 = 7;
+
+/// The longest message body the server reads. Longer ones are skipped, rather than allocated for
+/// on the word of their header.
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum ReadError {
+    /// The message was malformed, and has been skipped as far as its headers allow. Reading can
+    /// carry on with the next one.
+    Skipped(String),
+    /// The input itself failed.
+    Io(String)
+}
+
+/// Reads one message. Returns `None` at the end of the input.
+pub fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<String>, ReadError> {
+    let mut length = None;
+    let mut bad_header = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|error| ReadError::Io(error.to_string()))? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                match value.trim().parse::<usize>() {
+                    Ok(value) => length = Some(value),
+                    Err(_) => bad_header = Some(format!("bad header '{}'", line))
+                }
+            }
+        }
+    }
+
+    if let Some(message) = bad_header {
+        return Err(ReadError::Skipped(message));
+    }
+    let length = length.ok_or_else(|| ReadError::Skipped(String::from("message without Content-Length")))?;
+    if length > MAX_MESSAGE_LENGTH {
+        io::copy(&mut Read::take(&mut *reader, length as u64), &mut io::sink()).map_err(|error| ReadError::Io(error.to_string()))?;
+        return Err(ReadError::Skipped(format!("message of {} bytes is longer than the limit of {}", length, MAX_MESSAGE_LENGTH)));
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|error| ReadError::Io(error.to_string()))?;
+    String::from_utf8(body).map(Some).map_err(|_| ReadError::Skipped(String::from("message is not UTF-8")))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// The line at `line` (1-based), without its line break.
+fn line_text(text: &str, line: i32) -> &str {
+    text.split('\n').nth((line - 1).max(0) as usize).unwrap_or("").trim_end_matches('\r')
+}
+
+/// Converts a span (1-based lines, byte columns) to an LSP position (0-based lines, UTF-16
+/// columns).
+fn to_position(text: &str, span: Span) -> Json {
+    let line = line_text(text, span.line);
+    let bytes = ((span.column - 1).max(0) as usize).min(line.len());
+    let character: usize = line.get(..bytes).unwrap_or(line).chars().map(char::len_utf16).sum();
+    Json::object([("line", Json::int(span.line as i64 - 1)), ("character", Json::int(character as i64))])
+}
+
+/// Converts an LSP position back to a span.
+fn from_position(text: &str, position: &Json) -> Option<Span> {
+    let line = position.get("line").as_i64()? as i32 + 1;
+    let character = position.get("character").as_i64()? as usize;
+
+    let mut units = 0;
+    let mut bytes = 0;
+    for c in line_text(text, line).chars() {
+        if units >= character {
+            break;
+        }
+        units += c.len_utf16();
+        bytes += c.len_utf8();
+    }
+    Some(Span::new(line, bytes as i32 + 1))
+}
+
+fn range(text: &str, start: Span, length: usize) -> Json {
+    let end = Span::new(start.line, start.column + length as i32);
+    Json::object([("start", to_position(text, start)), ("end", to_position(text, end))])
+}
+
+/// The length of the word starting at `span`, so that diagnostics underline something visible.
+fn word_length(text: &str, span: Span) -> usize {
+    let line = line_text(text, span.line);
+    let rest = line.get((span.column - 1).max(0) as usize..).unwrap_or("");
+    rest.bytes().take_while(|c| c.is_ascii_alphanumeric() || *c == b'_').count().max(1)
+}
+
+struct Document {
+    text: String,
+    analysis: Analysis
+}
+
+pub struct Server {
+    documents: BTreeMap<String, Document>,
+    /// The workspace root given to `initialize`, if any, which holds the project's scripts.
+    root: Option<PathBuf>,
+    shutdown: bool,
+    exit: Option<i32>
+}
+
+/// The file path of a `file://` URI. Other URIs are used as they are.
+fn uri_path(uri: &str) -> &str {
+    uri.strip_prefix("file://").unwrap_or(uri)
+}
+
+/// Compares paths by the file they point to, if it exists.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server { documents: BTreeMap::new(), root: None, shutdown: false, exit: None }
+    }
+
+    /// Set once the client sent `exit`: 0 if it asked to shut down first, 1 otherwise.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
+    /// Handles one message from the client, returning the messages to send back.
+    pub fn handle(&mut self, text: &str) -> Vec<Json> {
+        let message = match Json::parse(text) {
+            Ok(message) => message,
+            Err(error) => return vec![error_response(Json::Null, PARSE_ERROR, &error)]
+        };
+
+        let id = message.get("id").clone();
+        let method = message.get("method").as_str().unwrap_or("");
+        let params = message.get("params");
+
+        // Notifications have no id and get no response.
+        if id.is_null() {
+            return self.notification(method, params);
+        }
+
+        if self.shutdown {
+            return vec![error_response(id, INVALID_REQUEST, "the server is shutting down")];
+        }
+        let result = match method {
+            "initialize" => {
+                let root = params.get("rootUri").as_str().or_else(|| params.get("rootPath").as_str());
+                self.root = root.map(|root| PathBuf::from(uri_path(root)));
+                Ok(capabilities())
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/completion" => self.completion(params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method)))
+        };
+
+        match result {
+            Ok(result) => vec![Json::object([("jsonrpc", Json::str("2.0")), ("id", id), ("result", result)])],
+            Err((code, message)) => vec![error_response(id, code, &message)]
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let document = params.get("textDocument");
+        let Some(uri) = document.get("uri").as_str() else {
+            if method == "exit" {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+            }
+            return vec![];
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = document.get("text").as_str().unwrap_or("");
+                vec![self.update(uri, text.to_string())]
+            }
+            "textDocument/didChange" => {
+                // With full sync, the last change holds the whole document.
+                let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+                match changes.last().and_then(|change| change.get("text").as_str()) {
+                    Some(text) => vec![self.update(uri, text.to_string())],
+                    None => vec![]
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![publish_diagnostics(uri, vec![])]
+            }
+            _ => vec![]
+        }
+    }
+
+    /// The name and text of every script of the project besides `name`. A project whose scripts
+    /// cannot be listed is taken to have none.
+    fn other_scripts(&self, name: &str) -> Vec<(String, String)> {
+        let Some(root) = &self.root else { return vec![]; };
+        let paths = if root.join(MANIFEST_NAME).is_file() {
+            Manifest::load(root).and_then(|manifest| manifest.discover_sources())
+        }
+        else {
+            manifest::discover_scripts(std::slice::from_ref(root))
+        };
+
+        let mut scripts = vec![];
+        for path in paths.unwrap_or_default() {
+            if same_file(&path, Path::new(name)) {
+                continue;
+            }
+            let open = self.documents.iter().find(|(uri, _)| same_file(&path, Path::new(uri_path(uri))));
+            let text = match open {
+                Some((_, document)) => document.text.clone(),
+                None => match std::fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(_) => continue
+                }
+            };
+            scripts.push((path.to_string_lossy().to_string(), text));
+        }
+        scripts
+    }
+
+    fn update(&mut self, uri: &str, text: String) -> Json {
+        let name = uri_path(uri);
+        let analysis = Analysis::new(name, &text, &self.other_scripts(name));
+
+        let diagnostics = analysis.diagnostics.iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span.unwrap_or(Span::new(1, 1));
+                Json::object([
+                    ("range", range(&text, span, word_length(&text, span))),
+                    ("severity", Json::int(SEVERITY_ERROR)),
+                    ("source", Json::str("ponyscript")),
+                    ("message", Json::str(&diagnostic.message))
+                ])
+            })
+            .collect();
+
+        self.documents.insert(uri.to_string(), Document { text, analysis });
+        publish_diagnostics(uri, diagnostics)
+    }
+
+    fn document<'p>(&self, params: &'p Json) -> Result<(&'p str, &Document), (i64, String)> {
+        let uri = params.get("textDocument").get("uri").as_str()
+            .ok_or_else(|| (INVALID_PARAMS, String::from("missing textDocument.uri")))?;
+        let document = self.documents.get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("document {} is not open", uri)))?;
+        Ok((uri, document))
+    }
+
+    fn occurrence<'d>(document: &'d Document, params: &Json) -> Result<Option<&'d Occurrence>, (i64, String)> {
+        let span = from_position(&document.text, params.get("position"))
+            .ok_or_else(|| (INVALID_PARAMS, String::from("missing position")))?;
+        Ok(document.analysis.occurrence_at(span.line, span.column))
+    }
+
+    fn hover(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let (_, document) = self.document(params)?;
+        let Some(occurrence) = Self::occurrence(document, params)? else { return Ok(Json::Null); };
+
+        let mut value = format!("```ponyscript\n{}\n```", document.analysis.describe(occurrence.target));
+        if let Some(doc) = document.analysis.doc(occurrence.target) {
+            value += &format!("\n\n{}", doc);
+        }
+        Ok(Json::object([
+            ("contents", Json::object([
+                ("kind", Json::str("markdown")),
+                ("value", Json::String(value))
+            ])),
+            ("range", range(&document.text, occurrence.span, occurrence.length))
+        ]))
+    }
+
+    fn definition(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let (uri, document) = self.document(params)?;
+        let Some(occurrence) = Self::occurrence(document, params)? else { return Ok(Json::Null); };
+        let Some(declaration) = document.analysis.declaration(occurrence.target) else { return Ok(Json::Null); };
+
+        // Functions of other types are declared in the other scripts of the project.
+        let (uri, text) = match document.analysis.declaring_script(occurrence.target) {
+            Some(script) => {
+                let text = self.other_scripts(uri_path(uri)).into_iter()
+                    .find(|(name, _)| name == script)
+                    .map(|(_, text)| text)
+                    .unwrap_or_default();
+                (format!("file://{}", script), text)
+            }
+            None => (uri.to_string(), document.text.clone())
+        };
+        Ok(Json::object([
+            ("uri", Json::String(uri)),
+            ("range", range(&text, declaration.span, declaration.length))
+        ]))
+    }
+
+    fn document_symbols(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let (_, document) = self.document(params)?;
+        let text = &document.text;
+
+        let symbols = document.analysis.functions().into_iter()
+            .map(|symbol| {
+                let end = Span::new(symbol.end_line, line_text(text, symbol.end_line).len() as i32 + 1);
+                Json::object([
+                    ("name", Json::String(symbol.name)),
+                    ("detail", Json::String(symbol.detail)),
+                    ("kind", Json::int(SYMBOL_FUNCTION)),
+                    ("range", Json::object([("start", to_position(text, symbol.start)), ("end", to_position(text, end))])),
+                    ("selectionRange", range(text, symbol.name_span, symbol.name_length))
+                ])
+            })
+            .collect();
+        Ok(Json::Array(symbols))
+    }
+
+    fn completion(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let (uri, document) = self.document(params)?;
+        let text = &document.text;
+        let cursor = from_position(text, params.get("position"))
+            .ok_or_else(|| (INVALID_PARAMS, String::from("missing position")))?;
+
+        // The scope at the cursor is only needed here, so the document is analyzed again for it.
+        let name = uri_path(uri);
+        let analysis = Analysis::with_cursor(name, text, &self.other_scripts(name), cursor);
+        let line = line_text(text, cursor.line);
+        let before = line.get(..(cursor.column - 1) as usize).unwrap_or(line);
+
+        let items = analysis.completions(before).into_iter()
+            .map(|completion| {
+                let kind = match completion.kind {
+                    CompletionKind::Variable => COMPLETION_VARIABLE,
+                    CompletionKind::Function => COMPLETION_FUNCTION,
+                    CompletionKind::Type => COMPLETION_CLASS
+                };
+                Json::object([
+                    ("label", Json::String(completion.label)),
+                    ("kind", Json::int(kind)),
+                    ("detail", Json::String(completion.detail))
+                ])
+            })
+            .collect();
+        Ok(Json::Array(items))
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        ("capabilities", Json::object([
+            ("textDocumentSync", Json::int(1)),
+            ("hoverProvider", Json::Bool(true)),
+            ("definitionProvider", Json::Bool(true)),
+            ("documentSymbolProvider", Json::Bool(true)),
+            ("completionProvider", Json::object([
+                ("triggerCharacters", Json::Array(vec![Json::str("."), Json::str(":")]))
+            ]))
+        ])),
+        ("serverInfo", Json::object([("name", Json::str("ponyscript"))]))
+    ])
+}
+
+fn error_response(id: Json, code: i64, message: &str) -> Json {
+    Json::object([
+        ("jsonrpc", Json::str("2.0")),
+        ("id", id),
+        ("error", Json::object([("code", Json::int(code)), ("message", Json::str(message))]))
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object([
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/publishDiagnostics")),
+        ("params", Json::object([("uri", Json::str(uri)), ("diagnostics", Json::Array(diagnostics))]))
+    ])
+}
+
+/// Serves one client until it exits or closes the input. Returns the process exit code.
+pub fn serve<R: BufRead, W: Write>(reader: &mut R, writer: &mut W) -> Result<i32, String> {
+    let mut server = Server::new();
+    loop {
+        let replies = match read_message(reader) {
+            Ok(Some(message)) => server.handle(&message),
+            Ok(None) => break,
+            // Without a readable message there is no id to answer to.
+            Err(ReadError::Skipped(error)) => vec![error_response(Json::Null, PARSE_ERROR, &error)],
+            Err(ReadError::Io(error)) => return Err(error)
+        };
+        for reply in replies {
+            write_message(writer, &reply).map_err(|error| error.to_string())?;
+        }
+        if let Some(code) = server.exit_code() {
+            return Ok(code);
+        }
+    }
+    Ok(if server.shutdown { 0 } else { 1 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///game/calc.pony.script";
+
+    fn frame(message: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", message.len(), message)
+    }
+
+    fn open(server: &mut Server, text: &str) -> Json {
+        open_at(server, URI, text)
+    }
+
+    fn open_at(server: &mut Server, uri: &str, text: &str) -> Json {
+        let params = Json::object([("textDocument", Json::object([("uri", Json::str(uri)), ("text", Json::str(text))]))]);
+        let message = Json::object([("jsonrpc", Json::str("2.0")), ("method", Json::str("textDocument/didOpen")), ("params", params)]);
+        server.handle(&message.to_string()).remove(0)
+    }
+
+    fn request(server: &mut Server, method: &str, line: i64, character: i64) -> Json {
+        request_at(server, URI, method, line, character)
+    }
+
+    fn request_at(server: &mut Server, uri: &str, method: &str, line: i64, character: i64) -> Json {
+        let params = Json::object([
+            ("textDocument", Json::object([("uri", Json::str(uri))])),
+            ("position", Json::object([("line", Json::int(line)), ("character", Json::int(character))]))
+        ]);
+        let message = Json::object([("jsonrpc", Json::str("2.0")), ("id", Json::int(7)), ("method", Json::str(method)), ("params", params)]);
+        server.handle(&message.to_string()).remove(0).get("result").clone()
+    }
+
+    #[test]
+    fn publishes_diagnostics() {
+        let mut server = Server::new();
+        let published = open(&mut server, "extends Node as Calc\n\nfun f(:\n");
+        let diagnostic = &published.get("params").get("diagnostics").as_array().unwrap()[0];
+        assert_eq!(diagnostic.get("message").as_str(), Some("Expected ')' after function name"));
+        assert_eq!(diagnostic.get("range").get("start"), &Json::object([("line", Json::int(2)), ("character", Json::int(6))]));
+
+        let published = open(&mut server, "extends Node as Calc\n");
+        assert_eq!(published.get("params").get("diagnostics"), &Json::Array(vec![]));
+    }
+
+    #[test]
+    fn sees_the_other_scripts_of_the_workspace() {
+        let dir = std::env::temp_dir().join(format!("ponyscript-lsp-workspace-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("math")).unwrap();
+        std::fs::write(dir.join("math/vec.pony.script"), "extends Node as Vec2\n\nfun zero() -> int:\n\treturn 0\n\nfun broken(:\n").unwrap();
+        let uri = format!("file://{}", dir.join("calc.pony.script").display());
+        let messages = |published: Json| published.get("params").get("diagnostics").as_array().unwrap().iter()
+            .map(|diagnostic| diagnostic.get("message").as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+
+        let mut server = Server::new();
+        let text = "extends Node as Calc\n\nfun f(v: Vec2) -> int:\n\treturn 1\n";
+        assert_eq!(messages(open_at(&mut server, &uri, text)), ["unknown type Vec2"]);
+
+        let root = Json::object([("rootUri", Json::String(format!("file://{}", dir.display())))]);
+        let initialize = Json::object([("jsonrpc", Json::str("2.0")), ("id", Json::int(1)), ("method", Json::str("initialize")), ("params", root)]);
+        server.handle(&initialize.to_string());
+
+        // The broken function of vec.pony.script is only reported when that script is opened.
+        assert!(messages(open_at(&mut server, &uri, text)).is_empty());
+        let call = "extends Node as Calc\n\nfun f(v: Vec2) -> int:\n\treturn Vec2.zero()\n";
+        assert!(messages(open_at(&mut server, &uri, call)).is_empty());
+        let definition = request_at(&mut server, &uri, "textDocument/definition", 3, 14);
+        assert_eq!(definition.get("uri").as_str(), Some(format!("file://{}", dir.join("math/vec.pony.script").display()).as_str()));
+        assert_eq!(definition.get("range").get("start"), &Json::object([("line", Json::int(2)), ("character", Json::int(4))]));
+        let members = request_at(&mut server, &uri, "textDocument/completion", 3, 13);
+        assert_eq!(members.as_array().unwrap().len(), 1);
+
+        let typo = "extends Node as Calc\n\nfun f(v: Vec3) -> int:\n\treturn 1\n";
+        assert_eq!(messages(open_at(&mut server, &uri, typo)), ["unknown type Vec3 (did you mean Vec2?)"]);
+
+        // Open documents are read from the editor rather than from disk.
+        open_at(&mut server, &format!("file://{}", dir.join("math/vec.pony.script").display()), "extends Node as Vec4\n");
+        assert_eq!(messages(open_at(&mut server, &uri, text)), ["unknown type Vec2 (did you mean Vec4?)"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hover_definition_and_symbols() {
+        let mut server = Server::new();
+        open(&mut server, "extends Node as Calc\n\nfun add(a: int, b: int) -> int:\n\treturn a + b\n\nfun main() -> int:\n\tlet x = add(1, 2)\n\treturn x\n");
+
+        let hover = request(&mut server, "textDocument/hover", 6, 10);
+        assert_eq!(hover.get("contents").get("value").as_str(), Some("```ponyscript\nfun Calc.add(a: int, b: int) -> int\n```"));
+
+        let definition = request(&mut server, "textDocument/definition", 7, 8);
+        assert_eq!(definition.get("uri").as_str(), Some(URI));
+        assert_eq!(definition.get("range").get("start"), &Json::object([("line", Json::int(6)), ("character", Json::int(5))]));
+
+        assert!(request(&mut server, "textDocument/hover", 0, 0).is_null());
+
+        let symbols = request(&mut server, "textDocument/documentSymbol", 0, 0);
+        let names: Vec<&str> = symbols.as_array().unwrap().iter().map(|symbol| symbol.get("name").as_str().unwrap()).collect();
+        assert_eq!(names, ["add", "main"]);
+
+        // Doc comments follow the code, for functions and `let`s alike.
+        open(&mut server, "extends Node as Calc\n\n## Adds.\nfun add(a: int) -> int:\n\t## Twice a.\n\tlet x = a + a\n\treturn x\n");
+        let hover = request(&mut server, "textDocument/hover", 6, 8);
+        assert_eq!(hover.get("contents").get("value").as_str(), Some("```ponyscript\nlet x: int\n```\n\nTwice a."));
+        let hover = request(&mut server, "textDocument/hover", 3, 4);
+        assert_eq!(hover.get("contents").get("value").as_str(), Some("```ponyscript\nfun Calc.add(a: int) -> int\n```\n\nAdds."));
+    }
+
+    #[test]
+    fn completes_members() {
+        let mut server = Server::new();
+        open(&mut server, "extends Node as Calc\n\nfun add(a: int) -> int:\n\treturn Calc.\n");
+
+        let items = request(&mut server, "textDocument/completion", 3, 13);
+        let items = items.as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].get("label").as_str(), Some("add"));
+        assert_eq!(items[0].get("kind").as_i64(), Some(COMPLETION_FUNCTION));
+        assert_eq!(items[0].get("detail").as_str(), Some("(a: int) -> int"));
+    }
+
+    #[test]
+    fn completes_functions() {
+        let mut server = Server::new();
+        open(&mut server, "extends Node as Calc\n\nfun add(a: int) -> int:\n\treturn ad\n");
+
+        let items = request(&mut server, "textDocument/completion", 3, 10);
+        let items = items.as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].get("label").as_str(), Some("add"));
+        assert_eq!(items[1].get("kind").as_i64(), Some(COMPLETION_FUNCTION));
+        assert_eq!(items[1].get("detail").as_str(), Some("(a: int) -> int"));
+    }
+
+    #[test]
+    fn serves_a_session() {
+        let messages = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/rename","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#
+        ];
+        let input: String = messages.iter().map(|message| frame(message)).collect();
+
+        let mut output = vec![];
+        let code = serve(&mut input.as_bytes(), &mut output).unwrap();
+        assert_eq!(code, 0);
+
+        let mut output = &output[..];
+        let initialize = Json::parse(&read_message(&mut output).unwrap().unwrap()).unwrap();
+        assert_eq!(initialize.get("result").get("capabilities").get("hoverProvider"), &Json::Bool(true));
+        let unknown = Json::parse(&read_message(&mut output).unwrap().unwrap()).unwrap();
+        assert_eq!(unknown.get("error").get("code").as_i64(), Some(METHOD_NOT_FOUND));
+        let shutdown = Json::parse(&read_message(&mut output).unwrap().unwrap()).unwrap();
+        assert_eq!(shutdown.get("id").as_i64(), Some(3));
+        assert_eq!(read_message(&mut output).unwrap(), None);
+    }
+
+    #[test]
+    fn survives_bad_messages() {
+        let shutdown = frame(r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#);
+        let exit = frame(r#"{"jsonrpc":"2.0","method":"exit"}"#);
+        let huge = format!("Content-Length: {}\r\n\r\n{}", MAX_MESSAGE_LENGTH + 1, " ".repeat(MAX_MESSAGE_LENGTH + 1));
+        let input = format!("Content-Length: lots\r\n\r\n{}{}{}", huge, shutdown, exit);
+
+        let mut output = vec![];
+        assert_eq!(serve(&mut input.as_bytes(), &mut output).unwrap(), 0);
+
+        let mut output = &output[..];
+        let bad = Json::parse(&read_message(&mut output).unwrap().unwrap()).unwrap();
+        assert_eq!(bad.get("error").get("message").as_str(), Some("bad header 'Content-Length: lots'"));
+        let long = Json::parse(&read_message(&mut output).unwrap().unwrap()).unwrap();
+        assert_eq!(long.get("error").get("code").as_i64(), Some(PARSE_ERROR));
+        let shutdown = Json::parse(&read_message(&mut output).unwrap().unwrap()).unwrap();
+        assert_eq!(shutdown.get("id").as_i64(), Some(3));
+    }
+}
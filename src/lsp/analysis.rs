@@ -0,0 +1,430 @@
+use crate::ast::{Node, Span, Type};
+use crate::bindings::{Bindings, FunID, Namespace, VarID};
+use crate::diagnostic::Diagnostic;
+use crate::{Compiler, Output};
+
+#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Var(VarID),
+    Fun(FunID)
+}
+
+/// A place where a name is written in the document: either the declaration of a variable or
+/// function, or a reference to one.
+#[derive(Debug, PartialEq)]
+pub struct Occurrence {
+    pub span: Span,
+    /// The length of the name, in bytes.
+    pub length: usize,
+    pub target: Target
+}
+
+/// A function declared in the document.
+pub struct FunSymbol {
+    pub name: String,
+    /// The parameters and return type, e.g. `(a: int) -> int`.
+    pub detail: String,
+    /// Where the declaration starts and the line of its last statement.
+    pub start: Span,
+    pub end_line: i32,
+    pub name_span: Span,
+    pub name_length: usize
+}
+
+#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq)]
+pub enum CompletionKind {
+    Variable,
+    Function,
+    Type
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    /// The type of a variable, or the parameters of a function.
+    pub detail: String
+}
+
+/// What kind of name is being typed, judged from the text before the cursor.
+#[derive(Debug, PartialEq)]
+enum Context {
+    /// Any name: a variable, or a function of the script's own type.
+    Name,
+    /// A function of the named type, after `Type.`.
+    Member(String),
+    /// A type, after the `:` of a declaration or a `->`.
+    Type
+}
+
+fn is_ident_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric()
+}
+
+fn completion_context(before: &str) -> Context {
+    // The name being typed does not matter, only what comes before it.
+    let before = before.trim_end_matches(|c: char| c.is_ascii() && is_ident_byte(c as u8));
+
+    if let Some(rest) = before.strip_suffix('.') {
+        let start = rest.bytes().rposition(|c| !is_ident_byte(c)).map_or(0, |index| index + 1);
+        if rest.as_bytes().get(start).is_some_and(u8::is_ascii_alphabetic) {
+            return Context::Member(rest[start..].to_string());
+        }
+    }
+
+    let before = before.trim_end_matches(['?', '+']).trim_end();
+    if before.ends_with("->") {
+        return Context::Type;
+    }
+    if let Some(rest) = before.strip_suffix(':') {
+        // A declared name comes before the colon of a type, while the colon that ends a
+        // function header follows a ')' or a return type.
+        let declared = rest.trim_end().bytes().last().is_some_and(is_ident_byte);
+        let header = rest.contains("->") || rest.trim_start().starts_with("fun ") && !rest.contains('(');
+        if declared && !header {
+            return Context::Type;
+        }
+    }
+
+    Context::Name
+}
+
+/// Everything the language server knows about one document: the problems in it, and what each
+/// name in it refers to.
+pub struct Analysis {
+    compiler: Compiler,
+    /// The index of the document's tree among the compiler's trees. The ones before it belong to
+    /// the other scripts of the project.
+    document: usize,
+    pub diagnostics: Vec<Diagnostic>,
+    occurrences: Vec<Occurrence>,
+    /// The variables in scope at the cursor given to `with_cursor`.
+    scope: Option<Vec<VarID>>
+}
+
+fn name_length(name: crate::string_pool::PoolS) -> usize {
+    name.to_vec().len()
+}
+
+impl Analysis {
+    /// Parses and typechecks a document. `others` holds the name and text of the other scripts of
+    /// its project, which declare the types it can refer to; only the document's own problems are
+    /// reported. Broken statements and functions are skipped, and whatever could be bound before
+    /// the first type error can still be looked up.
+    pub fn new(name: &str, text: &str, others: &[(String, String)]) -> Analysis {
+        Analysis::analyze(name, text, others, None)
+    }
+
+    /// Like `new`, but also finds the variables in scope at `cursor`, for `completions`.
+    pub fn with_cursor(name: &str, text: &str, others: &[(String, String)], cursor: Span) -> Analysis {
+        Analysis::analyze(name, text, others, Some(cursor))
+    }
+
+    fn analyze(name: &str, text: &str, others: &[(String, String)], cursor: Option<Span>) -> Analysis {
+        let mut compiler = Compiler::new(Output::Stdout);
+
+        // The errors of the other scripts are reported when they are opened themselves.
+        for (other, text) in others {
+            compiler.parse_source_str_recovering(other, text, None);
+        }
+        let document = compiler.trees().len();
+
+        let (mut diagnostics, scope) = compiler.parse_source_str_recovering(name, text, cursor);
+        if document < compiler.trees().len() {
+            if let Err(error) = compiler.typecheck_script(document) {
+                diagnostics.push(error);
+            }
+        }
+
+        let mut analysis = Analysis { compiler, document, diagnostics, occurrences: vec![], scope };
+        analysis.occurrences = analysis.collect_occurrences();
+        analysis
+    }
+
+    fn bindings(&self) -> &Bindings {
+        self.compiler.bindings()
+    }
+
+    /// The document's tree, or nothing if it could not be parsed at all.
+    fn own_trees(&self) -> &[Node] {
+        &self.compiler.trees()[self.document..]
+    }
+
+    fn declaration_of_var(&self, id: VarID) -> Option<Occurrence> {
+        let var = self.bindings().get_var(id);
+        var.span.map(|span| Occurrence { span, length: name_length(var.name), target: Target::Var(id) })
+    }
+
+    fn collect_occurrences(&self) -> Vec<Occurrence> {
+        let bindings = self.bindings();
+        let mut occurrences = vec![];
+
+        for tree in self.own_trees() {
+            tree.visit(&mut |node| match node {
+                Node::FunDecl(f) => {
+                    let fun = bindings.get_fun(f.bind_id);
+                    if let Some(span) = fun.span {
+                        occurrences.push(Occurrence { span, length: name_length(fun.name), target: Target::Fun(f.bind_id) });
+                    }
+                    occurrences.extend(fun.args.iter().filter_map(|arg| self.declaration_of_var(*arg)));
+                }
+                Node::Decl(decl) => occurrences.extend(self.declaration_of_var(decl.bind_id)),
+                Node::VarRef(point, span) | Node::Assign(point, span, _) => {
+                    if let Ok(id) = point.expect_bound() {
+                        let length = name_length(bindings.get_var(id).name);
+                        occurrences.push(Occurrence { span: *span, length, target: Target::Var(id) });
+                    }
+                }
+                Node::FunCall(_, point, span, _) => {
+                    if let Ok(id) = point.expect_bound() {
+                        let length = name_length(bindings.get_fun(id).name);
+                        occurrences.push(Occurrence { span: *span, length, target: Target::Fun(id) });
+                    }
+                }
+                _ => {}
+            });
+        }
+
+        occurrences
+    }
+
+    /// The name written at the given line and byte column, if there is one.
+    pub fn occurrence_at(&self, line: i32, column: i32) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occurrence| {
+            occurrence.span.line == line
+                && occurrence.span.column <= column
+                && column <= occurrence.span.column + occurrence.length as i32
+        })
+    }
+
+    /// Where the target is declared. Host functions are not declared in any script.
+    pub fn declaration(&self, target: Target) -> Option<Occurrence> {
+        match target {
+            Target::Var(id) => self.declaration_of_var(id),
+            Target::Fun(id) => {
+                let fun = self.bindings().get_fun(id);
+                fun.span.map(|span| Occurrence { span, length: name_length(fun.name), target })
+            }
+        }
+    }
+
+    /// The name of the other script that declares the target, or `None` if the document itself
+    /// or the host does.
+    pub fn declaring_script(&self, target: Target) -> Option<&str> {
+        let Target::Fun(id) = target else { return None; };
+        let Namespace::DynamicCall(typ) = self.bindings().get_fun(id).namespace else { return None; };
+        self.compiler.trees()[..self.document].iter().find_map(|tree| match tree {
+            Node::Tree(tree) if tree.own_type == typ => Some(tree.source.as_str()),
+            _ => None
+        })
+    }
+
+    fn fun_detail(&self, id: FunID) -> String {
+        let bindings = self.bindings();
+        let fun = bindings.get_fun(id);
+
+        let params: Vec<String> = fun.args.iter()
+            .map(|arg| {
+                let var = bindings.get_var(*arg);
+                format!("{}: {}", var.name, var.typ.source_name())
+            })
+            .collect();
+
+        let mut detail = format!("({})", params.join(", "));
+        if fun.return_type != Type::Void {
+            detail += &format!(" -> {}", fun.return_type.source_name());
+        }
+        detail
+    }
+
+    /// A description of the target as PonyScript code, for hovers.
+    pub fn describe(&self, target: Target) -> String {
+        match target {
+            Target::Var(id) => {
+                let var = self.bindings().get_var(id);
+                format!("let {}: {}", var.name, var.typ.source_name())
+            }
+            Target::Fun(id) => {
+                let fun = self.bindings().get_fun(id);
+                let native = if fun.native { "  # provided by the host" } else { "" };
+                format!("fun {}{}{}{}", fun.namespace, fun.name, self.fun_detail(id), native)
+            }
+        }
+    }
+
+    /// The `##` doc comment of the target's declaration.
+    pub fn doc(&self, target: Target) -> Option<&str> {
+        match target {
+            Target::Var(id) => self.bindings().get_var(id).doc.as_deref(),
+            Target::Fun(id) => self.bindings().get_fun(id).doc.as_deref()
+        }
+    }
+
+    fn fun_completions(&self, namespace: impl Fn(Namespace) -> bool) -> Vec<Completion> {
+        self.bindings().fun_bindings()
+            .filter(|fun| namespace(fun.namespace) && !fun.test)
+            .map(|fun| {
+                let id = self.bindings().overloads(fun.namespace, fun.name)
+                    .find(|id| self.bindings().get_fun(*id).output_name == fun.output_name);
+                Completion {
+                    label: fun.name.to_utf8(),
+                    kind: CompletionKind::Function,
+                    detail: id.map(|id| self.fun_detail(id)).unwrap_or_default()
+                }
+            })
+            .collect()
+    }
+
+    /// What could be typed at the cursor given to `with_cursor`. `before` is the text of the
+    /// cursor's line up to the cursor.
+    pub fn completions(&self, before: &str) -> Vec<Completion> {
+        let trees = |trees: &'_ [Node]| trees.iter()
+            .filter_map(|tree| match tree {
+                Node::Tree(tree) => Some((tree.own_type, tree.base_type)),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+
+        match completion_context(before) {
+            Context::Member(typ) => {
+                self.fun_completions(|namespace| matches!(namespace, Namespace::DynamicCall(name) if name.to_utf8() == typ))
+            }
+            Context::Type => {
+                // Every script of the project declares a type.
+                let mut names = vec![String::from("int"), String::from("float")];
+                for (own_type, base_type) in trees(self.compiler.trees()) {
+                    for name in [own_type.to_utf8(), base_type.to_utf8()] {
+                        if !names.contains(&name) {
+                            names.push(name);
+                        }
+                    }
+                }
+                names.into_iter()
+                    .map(|label| Completion { label, kind: CompletionKind::Type, detail: String::new() })
+                    .collect()
+            }
+            Context::Name => {
+                let mut completions: Vec<Completion> = self.scope.iter().flatten()
+                    .map(|id| {
+                        let var = self.bindings().get_var(*id);
+                        Completion { label: var.name.to_utf8(), kind: CompletionKind::Variable, detail: var.typ.source_name() }
+                    })
+                    .collect();
+
+                for (own_type, _) in trees(self.own_trees()) {
+                    let own = Namespace::DynamicCall(own_type);
+                    completions.extend(self.fun_completions(|namespace| namespace == own));
+                }
+                completions
+            }
+        }
+    }
+
+    /// The functions declared in the document, in order.
+    pub fn functions(&self) -> Vec<FunSymbol> {
+        let mut symbols = vec![];
+        for tree in self.own_trees() {
+            let Node::Tree(tree) = tree else { continue; };
+            for child in &tree.children {
+                let Node::FunDecl(f) = child else { continue; };
+                let fun = self.bindings().get_fun(f.bind_id);
+                let name_span = fun.span.unwrap_or(f.span);
+                symbols.push(FunSymbol {
+                    name: fun.name.to_utf8(),
+                    detail: self.fun_detail(f.bind_id),
+                    start: f.span,
+                    end_line: f.body.last().map_or(f.span.line, |stmt| stmt.span.line),
+                    name_span,
+                    name_length: name_length(fun.name)
+                });
+            }
+        }
+        symbols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "extends Node as Calc\n\nfun add(a: int, b: int) -> int:\n\treturn a + b\n\nfun main() -> int:\n\tlet x = add(1, 2)\n\treturn x\n";
+
+    #[test]
+    fn occurrences_resolve_to_declarations() {
+        let analysis = Analysis::new("calc.pony.script", SOURCE, &[]);
+        assert!(analysis.diagnostics.is_empty());
+
+        // `add` in `let x = add(1, 2)`
+        let call = analysis.occurrence_at(7, 10).unwrap();
+        assert_eq!(analysis.describe(call.target), "fun Calc.add(a: int, b: int) -> int");
+        assert_eq!(analysis.declaration(call.target).unwrap().span, Span::new(3, 5));
+
+        // `x` in `return x`
+        let var = analysis.occurrence_at(8, 9).unwrap();
+        assert_eq!(analysis.describe(var.target), "let x: int");
+        assert_eq!(analysis.declaration(var.target).unwrap().span, Span::new(7, 6));
+
+        // `b` in `return a + b`
+        let param = analysis.occurrence_at(4, 13).unwrap();
+        assert_eq!(analysis.declaration(param.target).unwrap().span, Span::new(3, 17));
+
+        assert!(analysis.occurrence_at(1, 1).is_none());
+    }
+
+    #[test]
+    fn functions_and_errors() {
+        let analysis = Analysis::new("calc.pony.script", SOURCE, &[]);
+        let functions = analysis.functions();
+        assert_eq!(functions.len(), 2);
+        assert_eq!(functions[0].name, "add");
+        assert_eq!(functions[0].detail, "(a: int, b: int) -> int");
+        assert_eq!((functions[0].start, functions[0].end_line), (Span::new(3, 1), 4));
+
+        let analysis = Analysis::new("bad.pony.script", "extends Node as Bad\n\nfun f(:\n", &[]);
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert!(analysis.functions().is_empty());
+    }
+
+    #[test]
+    fn completion_contexts() {
+        assert_eq!(completion_context("\tlet x = ad"), Context::Name);
+        assert_eq!(completion_context("\treturn Calc.a"), Context::Member(String::from("Calc")));
+        assert_eq!(completion_context("\treturn f(Calc."), Context::Member(String::from("Calc")));
+        assert_eq!(completion_context("fun f(a_: "), Context::Name);
+        assert_eq!(completion_context("\tlet x = 1."), Context::Name);
+        assert_eq!(completion_context("fun f(a: i"), Context::Type);
+        assert_eq!(completion_context("fun f(a: int, b: ?"), Context::Type);
+        assert_eq!(completion_context("fun f() -> "), Context::Type);
+        assert_eq!(completion_context("\tlet x: "), Context::Type);
+        assert_eq!(completion_context("fun f():"), Context::Name);
+        assert_eq!(completion_context("fun f() -> int:"), Context::Name);
+    }
+
+    fn labels(completions: &[Completion]) -> Vec<&str> {
+        completions.iter().map(|completion| completion.label.as_str()).collect()
+    }
+
+    #[test]
+    fn completes_broken_documents() {
+        let source = "extends Node as Calc\n\nfun add(a: int, b: int) -> int:\n\treturn a + b\n\nfun main(n: float) -> int:\n\tlet x = add(1, 2)\n\tlet y = Calc.\n\tlet z = 3\n\treturn x\n";
+        let analysis = Analysis::with_cursor("calc.pony.script", source, &[], Span::new(8, 15));
+        assert_eq!(analysis.diagnostics[0].message, "Expected function name after '.'");
+
+        let members = analysis.completions("\tlet y = Calc.");
+        assert_eq!(labels(&members), ["add", "main"]);
+        assert_eq!(members[0].detail, "(a: int, b: int) -> int");
+
+        let names = analysis.completions("\tlet y = ");
+        assert_eq!(labels(&names), ["n", "x", "add", "main"]);
+        assert_eq!((names[0].kind, names[0].detail.as_str()), (CompletionKind::Variable, "float"));
+
+        let types = analysis.completions("fun f(a: ");
+        assert_eq!(labels(&types), ["int", "float", "Calc", "Node"]);
+
+        // Between functions, no variables are in scope.
+        let analysis = Analysis::with_cursor("calc.pony.script", source, &[], Span::new(6, 1));
+        assert_eq!(labels(&analysis.completions("")), ["add", "main"]);
+    }
+}
@@ -1,65 +1,683 @@
-mod lexer;
-mod string_pool;
-mod parser;
-mod ast;
-mod bindings;
-mod compiler;
-
 use std::collections::VecDeque;
 use std::env::args;
-use std::path::{PathBuf};
-use crate::compiler::{Compiler, Output};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use ponyscript::{format, CCompiler, Compiler, Output};
+use ponyscript::doc::{self, DocFormat};
+use ponyscript::manifest::{Manifest, MANIFEST_NAME, OutputKind};
+use ponyscript::vm::Natives;
+use ponyscript::watch::{self, Snapshot};
+
+/// The scripts failed to parse or typecheck, or their tests failed.
+const EXIT_COMPILE_ERROR: i32 = 1;
+/// The command line could not be understood.
+const EXIT_USAGE_ERROR: i32 = 2;
+/// The scripts were fine, but producing or running the result failed (C compiler errors, I/O
+/// errors, runtime errors).
+const EXIT_BACKEND_ERROR: i32 = 3;
+
+/// How often `watch` looks at the scripts.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Where the compilation cache is kept, relative to the project directory.
+const CACHE_DIR: &str = ".ponyscript-cache";
+
+const USAGE: &str = "\
+Usage: ponyscript <command> [options] [files...]
+
+Without files, the scripts are found through the pony.toml of the project directory.
+
+Commands:
+  check                  Parse and typecheck the scripts
+  build                  Build the scripts into a library
+  emit --stage <stage>   Print an intermediate stage: tokens, cst, ast, typed-ast or c
+  run <Type.fun(args)>   Run a function on the interpreter
+  test [filter]          Run the scripts' test functions on the interpreter, or only those whose
+                         name (Type.test) contains the filter
+  fmt                    Reformat the scripts in place in the canonical style
+  doc                    Write API documentation from the scripts' ## comments, one page per
+                         script, into the output directory (default: doc in the project)
+  lsp                    Run the language server on stdin and stdout
+  watch                  Rebuild the library as a new version whenever a script changes, for hot
+                         reloading through the generated ponyscript_loader.c
+  help                   Show this message
+
+Options:
+  -o, --output <path>    Where to write the result (build and emit), or the directory for doc
+  --project <dir>        The directory holding pony.toml (default: the current directory)
+  --kind <kind>          What build produces: lib (default), c or bytecode
+  --cc <program>         The C compiler for lib builds (default: $CC, or cc)
+  -O <level>             Optimization level passed to the C compiler
+  -I <dir>               Include path passed to the C compiler
+  --cc-flag <flag>       Any other flag to pass to the C compiler
+  --no-pic               Do not pass -fPIC to the C compiler
+  --lines, --no-lines    Turn #line directives in the generated C on or off
+  --no-cache             Compile every script from scratch, ignoring the project's compilation
+                         cache (scripts built without a project are never cached)
+  --vm                   Run on the bytecode VM instead of the interpreter
+  --check                Only check that the scripts are formatted (fmt)
+  --format <format>      What doc writes: html (default) or markdown
+  -h, --help             Show this message
+
+Exit codes: 0 on success, 1 for errors in the scripts (or unformatted scripts with fmt --check,
+or failed tests), 2 for usage errors, 3 when the backend (the C compiler, writing the output, or
+running the function) fails.
+";
+
+enum Stage {
+    Tokens,
+    Cst,
+    Ast,
+    TypedAst,
+    C
+}
+
+enum Command {
+    Check,
+    Build,
+    Emit(Stage),
+    Run(String),
+    /// Runs the tests, keeping those whose name contains the filter.
+    Test(Option<String>),
+    Fmt,
+    Doc,
+    Watch,
+    Lsp,
+    Help
+}
+
+/// C compiler settings given on the command line, which win over the ones from the manifest.
+#[derive(Default)]
+struct CcOverrides {
+    program: Option<String>,
+    opt_level: Option<String>,
+    include_paths: Vec<PathBuf>,
+    flags: Vec<String>,
+    no_pic: bool
+}
+
+impl CcOverrides {
+    fn apply(&self, cc: &mut CCompiler) {
+        if let Some(program) = &self.program {
+            cc.program = program.clone();
+        }
+        if let Some(level) = &self.opt_level {
+            cc.opt_level = Some(level.clone());
+        }
+        cc.include_paths.extend(self.include_paths.iter().cloned());
+        cc.flags.extend(self.flags.iter().cloned());
+        if self.no_pic {
+            cc.pic = false;
+        }
+    }
+}
 
 struct Config {
-    output: Output,
+    command: Command,
+    output_path: Option<PathBuf>,
+    kind: Option<OutputKind>,
+    cc: CcOverrides,
+    project_dir: PathBuf,
+    /// Whether the project directory was given with `--project`, rather than defaulting to the
+    /// current directory.
+    project_given: bool,
+    /// Loaded when no source files are given on the command line.
+    manifest: Option<Manifest>,
+    /// Reuses the C code of unchanged scripts from the project's cache directory. Only projects
+    /// have one, see `cache_dir`.
+    use_cache: bool,
+    line_directives: Option<bool>,
+    /// Runs the function on the bytecode VM rather than the interpreter.
+    use_vm: bool,
+    /// Makes fmt report unformatted scripts instead of rewriting them.
+    check: bool,
+    doc_format: DocFormat,
     source_paths: Vec<PathBuf>
 }
 
-fn compile(config: Config) {
-    let mut compiler = Compiler::new(config.output);
+fn parse_stage(stage: &str) -> Result<Stage, String> {
+    match stage {
+        "tokens" => Ok(Stage::Tokens),
+        "cst" => Ok(Stage::Cst),
+        "ast" => Ok(Stage::Ast),
+        "typed-ast" => Ok(Stage::TypedAst),
+        "c" => Ok(Stage::C),
+        _ => Err(format!("unknown stage '{}', expected tokens, cst, ast, typed-ast or c", stage))
+    }
+}
+
+fn parse_args(mut args: VecDeque<String>) -> Result<Config, String> {
+    let command = args.pop_front().ok_or_else(|| String::from("no command given"))?;
+
+    let mut config = Config {
+        command: Command::Help,
+        output_path: None,
+        kind: None,
+        cc: CcOverrides::default(),
+        project_dir: PathBuf::from("."),
+        project_given: false,
+        manifest: None,
+        use_cache: true,
+        line_directives: None,
+        use_vm: false,
+        check: false,
+        doc_format: DocFormat::Html,
+        source_paths: vec![]
+    };
+    let mut stage = None;
+    let mut positional = vec![];
 
-    let parse_errors: Vec<String> = config.source_paths.iter().map(|path| {
-        compiler.parse_source_file(path)
-    }).filter(|result| result.is_err()).map(|error| error.unwrap_err()).collect();
+    fn value(args: &mut VecDeque<String>, flag: &str) -> Result<String, String> {
+        args.pop_front().ok_or_else(|| format!("{} needs a value", flag))
+    }
 
-    if !parse_errors.is_empty() {
-        for error in parse_errors {
-            println!("{}", error);
+    while let Some(arg) = args.pop_front() {
+        match arg.as_str() {
+            "-o" | "--output" => config.output_path = Some(PathBuf::from(value(&mut args, &arg)?)),
+            "--project" => {
+                config.project_dir = PathBuf::from(value(&mut args, &arg)?);
+                config.project_given = true;
+            }
+            "--kind" => config.kind = Some(OutputKind::parse(&value(&mut args, &arg)?)?),
+            "--stage" => stage = Some(parse_stage(&value(&mut args, &arg)?)?),
+            "--cc" => config.cc.program = Some(value(&mut args, &arg)?),
+            "-O" => config.cc.opt_level = Some(value(&mut args, &arg)?),
+            "-I" => config.cc.include_paths.push(PathBuf::from(value(&mut args, &arg)?)),
+            "--cc-flag" => config.cc.flags.push(value(&mut args, &arg)?),
+            "--no-pic" => config.cc.no_pic = true,
+            "--lines" => config.line_directives = Some(true),
+            "--no-lines" => config.line_directives = Some(false),
+            "--vm" => config.use_vm = true,
+            "--no-cache" => config.use_cache = false,
+            "--check" => config.check = true,
+            "--format" => config.doc_format = DocFormat::parse(&value(&mut args, &arg)?)?,
+            "-h" | "--help" => {
+                config.command = Command::Help;
+                return Ok(config);
+            }
+            "--" => positional.extend(args.drain(..)),
+            _ => {
+                if let Some(level) = arg.strip_prefix("-O") {
+                    config.cc.opt_level = Some(level.to_string());
+                }
+                else if arg.starts_with('-') {
+                    return Err(format!("unknown option '{}'", arg));
+                }
+                else {
+                    positional.push(arg);
+                }
+            }
         }
-        return;
     }
 
-    if let Err(type_err) = compiler.typecheck() {
-        println!("Typecheck error: {}", type_err);
-        return;
+    let mut positional: VecDeque<String> = positional.into();
+    config.command = match command.as_str() {
+        "check" => Command::Check,
+        "build" => Command::Build,
+        "emit" => Command::Emit(stage.ok_or_else(|| String::from("emit needs --stage"))?),
+        "fmt" => Command::Fmt,
+        "doc" => Command::Doc,
+        "watch" => Command::Watch,
+        "lsp" => Command::Lsp,
+        "run" => Command::Run(positional.pop_front().ok_or_else(|| String::from("run needs a function to call"))?),
+        "test" => {
+            // Anything that is not a script is the filter.
+            let filter = positional.front().filter(|arg| !arg.ends_with(".pony.script")).cloned();
+            if filter.is_some() {
+                positional.pop_front();
+            }
+            Command::Test(filter)
+        }
+        "help" | "-h" | "--help" => return Ok(config),
+        _ => return Err(format!("unknown command '{}'", command))
+    };
+
+    config.source_paths = positional.into_iter().map(PathBuf::from).collect();
+
+    Ok(config)
+}
+
+/// Without source files on the command line, loads the project manifest and compiles every
+/// script it finds.
+fn find_sources(config: &mut Config) -> Result<(), String> {
+    if !config.source_paths.is_empty() {
+        return Ok(());
     }
 
-    compiler.output().expect("Failed to output code!");
+    if !config.project_dir.join(MANIFEST_NAME).exists() {
+        return Err(format!("no source files given and no {} in {}", MANIFEST_NAME, config.project_dir.display()));
+    }
+    let manifest = Manifest::load(&config.project_dir)?;
+    config.source_paths = manifest.discover_sources()?;
+    if config.source_paths.is_empty() {
+        return Err(format!("project {} has no scripts", manifest.name));
+    }
+    config.manifest = Some(manifest);
+    Ok(())
 }
 
-fn main() {
-    let mut config = Config {
-        output: Output::Stdout,
-        source_paths: vec![]
+/// Writes `text` to the output path if there is one, or to stdout otherwise.
+fn write_result(output_path: &Option<PathBuf>, text: &str) -> Result<(), i32> {
+    let result = match output_path {
+        Some(path) => std::fs::write(path, text),
+        None => std::io::stdout().write_all(text.as_bytes())
     };
+    result.map_err(|error| {
+        eprintln!("error: {}", error);
+        EXIT_BACKEND_ERROR
+    })
+}
+
+fn dump_tokens(path: &Path) -> Result<String, String> {
+    let source = std::fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    Ok(ponyscript::dump_tokens(&path.to_string_lossy(), &String::from_utf8_lossy(&source)))
+}
+
+/// Formats the scripts in place, or with `--check`, reports the ones that are not formatted.
+fn format_sources(config: &Config) -> Result<(), i32> {
+    let mut failed = false;
+    for path in &config.source_paths {
+        let source = std::fs::read_to_string(path).map_err(|error| {
+            eprintln!("error: {}: {}", path.display(), error);
+            EXIT_BACKEND_ERROR
+        })?;
+        let formatted = match format::format(&path.to_string_lossy(), &source) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                eprintln!("{}", error);
+                failed = true;
+                continue;
+            }
+        };
+
+        if formatted == source {
+            continue;
+        }
+        if config.check {
+            eprintln!("{} is not formatted", path.display());
+            failed = true;
+        }
+        else {
+            std::fs::write(path, formatted).map_err(|error| {
+                eprintln!("error: {}: {}", path.display(), error);
+                EXIT_BACKEND_ERROR
+            })?;
+        }
+    }
+
+    if failed { Err(EXIT_COMPILE_ERROR) } else { Ok(()) }
+}
+
+/// The compilation cache of the project, if there is a project: scripts given on the command line
+/// without a manifest or `--project` leave nothing behind in the current directory.
+fn cache_dir(config: &Config) -> Option<PathBuf> {
+    let project = config.manifest.is_some() || config.project_given;
+    if project && config.use_cache { Some(config.project_dir.join(CACHE_DIR)) } else { None }
+}
 
+/// Parses the scripts, and typechecks them if asked to. `cache` allows reusing C code from the
+/// compilation cache, so it must only be set when nothing but C code is needed.
+fn load(config: &Config, output: Output, typecheck: bool, cache: bool) -> Result<Compiler, i32> {
+    let mut compiler = Compiler::new(output);
+    if let Some(enabled) = config.line_directives {
+        compiler.set_line_directives(enabled);
+    }
+    if let Some(dir) = cache_dir(config).filter(|_| cache) {
+        compiler.set_cache_dir(dir);
+    }
+
+    let mut failed = false;
+    for path in &config.source_paths {
+        if let Err(error) = compiler.parse_source_file(path) {
+            eprintln!("{}", error);
+            failed = true;
+        }
+    }
+    if failed {
+        return Err(EXIT_COMPILE_ERROR);
+    }
+
+    if typecheck {
+        if let Err(error) = compiler.typecheck() {
+            eprintln!("{}", error);
+            return Err(EXIT_COMPILE_ERROR);
+        }
+    }
+
+    Ok(compiler)
+}
+
+fn build_output(config: &mut Config) -> Output {
+    let kind = config.kind
+        .or(config.manifest.as_ref().map(|manifest| manifest.kind))
+        .unwrap_or(OutputKind::Lib);
+    let path = match (&config.output_path, &config.manifest) {
+        (Some(path), _) => path.clone(),
+        (None, Some(manifest)) if manifest.kind == kind => manifest.output_path.clone(),
+        (None, Some(manifest)) => manifest.root.join(kind.default_path(&manifest.name)),
+        (None, None) => kind.default_path("scripts")
+    };
+
+    match kind {
+        OutputKind::Lib => {
+            let mut cc = match &config.manifest {
+                Some(manifest) => manifest.cc.clone(),
+                None => CCompiler::from_env()
+            };
+            config.cc.apply(&mut cc);
+            Output::NativeLib { library: path, cc }
+        }
+        OutputKind::C => {
+            let header = path.with_extension("h");
+            Output::CFile { source: path, header }
+        }
+        OutputKind::Bytecode => Output::Bytecode(path)
+    }
+}
+
+/// Builds one version of the library for `watch`. Errors are reported, but do not stop watching.
+fn build_version(config: &Config, library: &Path, cc: &CCompiler, version: u32) -> bool {
+    let versioned = watch::versioned_library(library, version);
+    let output = Output::NativeLib { library: versioned.clone(), cc: cc.clone() };
+
+    let Ok(mut compiler) = load(config, output, true, true) else { return false; };
+    compiler.set_function_table(true);
+    if let Err(error) = compiler.output() {
+        eprintln!("{}", error);
+        return false;
+    }
+
+    if let Err(error) = watch::publish(library, &versioned) {
+        eprintln!("error: {}", error);
+        return false;
+    }
+    eprintln!("built {}", versioned.display());
+    true
+}
+
+fn watch(config: &mut Config) -> Result<(), i32> {
+    let Output::NativeLib { library, cc } = build_output(config) else {
+        eprintln!("error: watch only builds native libraries");
+        return Err(EXIT_USAGE_ERROR);
+    };
+
+    let dir = match library.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from(".")
+    };
+    watch::write_loader(&dir).map_err(|error| {
+        eprintln!("error: {}", error);
+        EXIT_BACKEND_ERROR
+    })?;
+
+    let mut version = watch::current_version(&library).unwrap_or(0);
+    let mut last = None;
+    loop {
+        // Scripts may be added to or removed from a project while watching.
+        if let Some(manifest) = &config.manifest {
+            match manifest.discover_sources() {
+                Ok(sources) => config.source_paths = sources,
+                Err(error) => eprintln!("error: {}", error)
+            }
+        }
+
+        let snapshot = Snapshot::take(&config.source_paths);
+        if last.as_ref() != Some(&snapshot) {
+            last = Some(snapshot);
+            if build_version(config, &library, &cc, version + 1) {
+                // The version before the one just replaced may still be loaded by the game.
+                let _ = std::fs::remove_file(watch::versioned_library(&library, version.saturating_sub(1)));
+                version += 1;
+            }
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn execute(mut config: Config) -> Result<(), i32> {
+    if !matches!(config.command, Command::Help | Command::Lsp) {
+        find_sources(&mut config).map_err(|error| {
+            eprintln!("error: {}", error);
+            EXIT_USAGE_ERROR
+        })?;
+    }
+
+    match &config.command {
+        Command::Help => {
+            print!("{}", USAGE);
+        }
+        Command::Check => {
+            load(&config, Output::Stdout, true, false)?;
+        }
+        Command::Build => {
+            let output = build_output(&mut config);
+            let cache = !matches!(output, Output::Bytecode(_));
+            let compiler = load(&config, output, true, cache)?;
+            compiler.output().map_err(|error| {
+                eprintln!("{}", error);
+                EXIT_BACKEND_ERROR
+            })?;
+        }
+        Command::Emit(Stage::Tokens) => {
+            let mut dump = String::new();
+            for path in &config.source_paths {
+                dump += &dump_tokens(path).map_err(|error| {
+                    eprintln!("{}", error);
+                    EXIT_BACKEND_ERROR
+                })?;
+            }
+            write_result(&config.output_path, &dump)?;
+        }
+        Command::Emit(Stage::Cst) => {
+            let mut dump = String::new();
+            for path in &config.source_paths {
+                let source = std::fs::read_to_string(path).map_err(|error| {
+                    eprintln!("error: {}: {}", path.display(), error);
+                    EXIT_BACKEND_ERROR
+                })?;
+                dump += &ponyscript::dump_cst(&path.to_string_lossy(), &source);
+            }
+            write_result(&config.output_path, &dump)?;
+        }
+        Command::Emit(Stage::Ast) | Command::Emit(Stage::TypedAst) => {
+            let typed = matches!(config.command, Command::Emit(Stage::TypedAst));
+            let compiler = load(&config, Output::Stdout, typed, false)?;
+
+            let mut dump = String::new();
+            for tree in compiler.trees() {
+                dump += &format!("{}\n", ponyscript::ast::to_sexpr(compiler.bindings(), tree));
+            }
+            write_result(&config.output_path, &dump)?;
+        }
+        Command::Emit(Stage::C) => {
+            // Emitting only prints, so it neither reads nor writes the cache.
+            let compiler = load(&config, Output::Stdout, true, false)?;
+            write_result(&config.output_path, &compiler.emit_c())?;
+        }
+        Command::Fmt => {
+            format_sources(&config)?;
+        }
+        Command::Doc => {
+            let compiler = load(&config, Output::Stdout, false, false)?;
+            let dir = config.output_path.clone().unwrap_or_else(|| config.project_dir.join("doc"));
+            let written = std::fs::create_dir_all(&dir).and_then(|()| {
+                doc::generate(&compiler, config.doc_format).iter()
+                    .try_for_each(|page| std::fs::write(dir.join(&page.file_name), &page.content))
+            });
+            written.map_err(|error| {
+                eprintln!("error: {}: {}", dir.display(), error);
+                EXIT_BACKEND_ERROR
+            })?;
+        }
+        Command::Watch => {
+            watch(&mut config)?;
+        }
+        Command::Lsp => {
+            let code = ponyscript::lsp::serve(&mut std::io::stdin().lock(), &mut std::io::stdout()).map_err(|error| {
+                eprintln!("error: {}", error);
+                EXIT_BACKEND_ERROR
+            })?;
+            if code != 0 {
+                return Err(code);
+            }
+        }
+        Command::Run(call) => {
+            let compiler = load(&config, Output::Stdout, true, false)?;
+            let result = if config.use_vm {
+                compiler.run_vm(call, Natives::new())
+            }
+            else {
+                compiler.run(call)
+            };
+
+            match result {
+                Ok(value) => println!("{}", value),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return Err(EXIT_BACKEND_ERROR);
+                }
+            }
+        }
+        Command::Test(filter) => {
+            run_tests(&config, filter.as_deref())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the tests one by one, printing a line for each and the failures at the end.
+fn run_tests(config: &Config, filter: Option<&str>) -> Result<(), i32> {
+    let compiler = load(config, Output::Stdout, true, false)?;
+
+    let tests = compiler.tests(filter);
+    let mut failures = vec![];
+    for (name, id) in &tests {
+        match compiler.run_test(*id) {
+            Ok(()) => println!("test {} ... ok", name),
+            Err(error) => {
+                println!("test {} ... FAILED", name);
+                failures.push((name, error));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!();
+        println!("failures:");
+        for (name, error) in &failures {
+            println!("    {}: {}", name, error);
+        }
+    }
+    println!();
+    println!("{} passed, {} failed", tests.len() - failures.len(), failures.len());
+
+    if failures.is_empty() { Ok(()) } else { Err(EXIT_COMPILE_ERROR) }
+}
+
+fn main() {
     let mut args: VecDeque<String> = args().collect();
 
     // Ignore the program name
     args.pop_front();
 
-    if let Some(arg) = args.front() {
-        if arg == "-tcclib" {
-            args.pop_front();
-            if let Some(output_name) = args.pop_front() {
-                config.output = Output::TccLib(PathBuf::from(output_name));
-            }
+    let config = match parse_args(args) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            eprintln!("Run 'ponyscript --help' for usage.");
+            std::process::exit(EXIT_USAGE_ERROR);
         }
+    };
+
+    if let Err(code) = execute(config) {
+        std::process::exit(code);
     }
+}
 
-    for arg in args {
-        config.source_paths.push(PathBuf::from(arg));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn parse_build() {
+        let config = parse(&["build", "-o", "out.c", "--kind", "c", "-O2", "a.pony.script", "b.pony.script"]).unwrap();
+        assert!(matches!(config.command, Command::Build));
+        assert_eq!(config.kind, Some(OutputKind::C));
+        assert_eq!(config.output_path, Some(PathBuf::from("out.c")));
+        assert_eq!(config.cc.opt_level.as_deref(), Some("2"));
+        assert_eq!(config.source_paths, vec![PathBuf::from("a.pony.script"), PathBuf::from("b.pony.script")]);
+    }
+
+    #[test]
+    fn parse_run_and_emit() {
+        let config = parse(&["run", "--vm", "Calc.add(1, 2)", "calc.pony.script"]).unwrap();
+        assert!(matches!(config.command, Command::Run(ref call) if call == "Calc.add(1, 2)"));
+        assert!(config.use_vm);
+
+        let config = parse(&["emit", "--stage", "typed-ast", "calc.pony.script"]).unwrap();
+        assert!(matches!(config.command, Command::Emit(Stage::TypedAst)));
+        assert!(matches!(parse(&["emit", "--stage", "cst"]).unwrap().command, Command::Emit(Stage::Cst)));
+
+        assert!(matches!(parse(&["watch", "--project", "game"]).unwrap().command, Command::Watch));
+
+        let config = parse(&["doc", "--format", "markdown", "-o", "docs"]).unwrap();
+        assert!(matches!(config.command, Command::Doc));
+        assert_eq!(config.doc_format, DocFormat::Markdown);
+
+        let config = parse(&["test", "adds", "calc.pony.script"]).unwrap();
+        assert!(matches!(config.command, Command::Test(Some(ref filter)) if filter == "adds"));
+        assert_eq!(config.source_paths, vec![PathBuf::from("calc.pony.script")]);
+        assert!(matches!(parse(&["test", "calc.pony.script"]).unwrap().command, Command::Test(None)));
+
+        let config = parse(&["fmt", "--check", "calc.pony.script"]).unwrap();
+        assert!(matches!(config.command, Command::Fmt));
+        assert!(config.check);
     }
 
-    compile(config);
+    #[test]
+    fn parse_usage_errors() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["frob", "a.pony.script"]).is_err());
+        assert!(parse(&["emit", "a.pony.script"]).is_err());
+        assert!(parse(&["emit", "--stage", "bytes", "a.pony.script"]).is_err());
+        assert!(parse(&["build", "--bogus", "a.pony.script"]).is_err());
+        assert!(parse(&["build", "-o"]).is_err());
+        assert!(matches!(parse(&["build", "--help"]).unwrap().command, Command::Help));
+    }
+
+    #[test]
+    fn build_from_manifest() {
+        let dir = std::env::temp_dir().join(format!("ponyscript-project-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("scripts")).unwrap();
+        std::fs::write(dir.join("scripts/calc.pony.script"), "extends Node as Calc\n").unwrap();
+        std::fs::write(dir.join(MANIFEST_NAME), "[project]\nname = \"game\"\nsources = [\"scripts\"]\noutput = \"c\"\n").unwrap();
+
+        let mut config = parse(&["build", "--project", dir.to_str().unwrap()]).unwrap();
+        find_sources(&mut config).unwrap();
+        assert_eq!(config.source_paths, vec![dir.join("scripts/calc.pony.script")]);
+        assert!(matches!(build_output(&mut config), Output::CFile { source, .. } if source == dir.join("game.c")));
+
+        let mut config = parse(&["build", "--kind", "bytecode", "--project", dir.to_str().unwrap()]).unwrap();
+        find_sources(&mut config).unwrap();
+        assert!(matches!(build_output(&mut config), Output::Bytecode(path) if path == dir.join("game.psbc")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut config = parse(&["check", "--project", dir.to_str().unwrap()]).unwrap();
+        assert!(find_sources(&mut config).is_err());
+    }
+
+    #[test]
+    fn only_projects_are_cached() {
+        assert_eq!(cache_dir(&parse(&["build", "calc.pony.script"]).unwrap()), None);
+        assert_eq!(cache_dir(&parse(&["build", "--project", "game", "calc.pony.script"]).unwrap()), Some(PathBuf::from("game").join(CACHE_DIR)));
+        assert_eq!(cache_dir(&parse(&["build", "--no-cache", "--project", "game"]).unwrap()), None);
+    }
 }
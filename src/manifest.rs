@@ -0,0 +1,389 @@
+//! Project manifests (`pony.toml`).
+//!
+//! A manifest names the project, lists the directories holding its scripts, and says what a build
+//! produces:
+//!
+//! ```toml
+//! [project]
+//! name = "game"
+//! sources = ["scripts", "ui/scripts"]
+//! output = "lib"                 # lib, c or bytecode
+//! path = "build/libgame.so"      # optional, derived from the name otherwise
+//!
+//! [cc]
+//! program = "clang"              # optional, $CC or cc otherwise
+//! opt-level = "2"
+//! pic = true
+//! include = ["engine/include"]
+//! flags = ["-g"]
+//! ```
+//!
+//! Only the small subset of TOML needed for this is understood: tables, strings, booleans and
+//! arrays of strings.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::compiler::CCompiler;
+
+pub const MANIFEST_NAME: &str = "pony.toml";
+pub const SCRIPT_EXTENSION: &str = ".pony.script";
+
+/// What a build produces.
+#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq)]
+pub enum OutputKind {
+    /// A native shared library, built with the C compiler.
+    Lib,
+    /// A C source file and a matching header.
+    C,
+    /// A bytecode module for the VM.
+    Bytecode
+}
+
+impl OutputKind {
+    pub fn parse(kind: &str) -> Result<OutputKind, String> {
+        match kind {
+            "lib" => Ok(OutputKind::Lib),
+            "c" => Ok(OutputKind::C),
+            "bytecode" => Ok(OutputKind::Bytecode),
+            _ => Err(format!("unknown output kind '{}', expected lib, c or bytecode", kind))
+        }
+    }
+
+    /// Where a project called `name` is built to, unless the manifest says otherwise.
+    pub fn default_path(&self, name: &str) -> PathBuf {
+        match self {
+            OutputKind::Lib => PathBuf::from(format!("lib{}.so", name)),
+            OutputKind::C => PathBuf::from(format!("{}.c", name)),
+            OutputKind::Bytecode => PathBuf::from(format!("{}.psbc", name))
+        }
+    }
+}
+
+#[derive(Clone)]
+#[derive(Debug, PartialEq)]
+enum Value {
+    Str(String),
+    Bool(bool),
+    Array(Vec<String>)
+}
+
+type Table = BTreeMap<String, Value>;
+
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut chars = text.char_indices();
+    if chars.next().map(|(_, c)| c) != Some('"') {
+        return Err(String::from("expected a string"));
+    }
+
+    let mut result = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((result, &text[index + 1..])),
+            '\\' => {
+                let escaped = match chars.next().map(|(_, c)| c) {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    _ => return Err(String::from("unknown escape in string"))
+                };
+                result.push(escaped);
+            }
+            _ => result.push(c)
+        }
+    }
+    Err(String::from("unterminated string"))
+}
+
+/// Checks that nothing but a comment follows a value.
+fn expect_end(rest: &str) -> Result<(), String> {
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    }
+    else {
+        Err(format!("unexpected '{}' after value", rest))
+    }
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim_start();
+    if text.starts_with('"') {
+        let (string, rest) = parse_string(text)?;
+        expect_end(rest)?;
+        return Ok(Value::Str(string));
+    }
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut items = vec![];
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                expect_end(after)?;
+                return Ok(Value::Array(items));
+            }
+            let (item, after) = parse_string(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            }
+            else if !rest.starts_with(']') {
+                return Err(String::from("expected ',' or ']' in array"));
+            }
+        }
+    }
+    for (word, value) in [("true", true), ("false", false)] {
+        if let Some(rest) = text.strip_prefix(word) {
+            expect_end(rest)?;
+            return Ok(Value::Bool(value));
+        }
+    }
+    Err(format!("unsupported value '{}'", text.trim_end()))
+}
+
+fn parse_tables(text: &str) -> Result<BTreeMap<String, Table>, String> {
+    let mut tables: BTreeMap<String, Table> = BTreeMap::new();
+    let mut current = String::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let at = |message: String| format!("{}:{}: {}", MANIFEST_NAME, index + 1, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, rest) = header.split_once(']').ok_or_else(|| at(String::from("expected ']'")))?;
+            expect_end(rest).map_err(at)?;
+            current = name.trim().to_string();
+            tables.entry(current.clone()).or_default();
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| at(String::from("expected 'key = value'")))?;
+        let value = parse_value(value).map_err(at)?;
+        let table = tables.entry(current.clone()).or_default();
+        if table.insert(key.trim().to_string(), value).is_some() {
+            return Err(at(format!("'{}' is set twice", key.trim())));
+        }
+    }
+
+    Ok(tables)
+}
+
+fn take_str(table: &mut Table, key: &str) -> Result<Option<String>, String> {
+    match table.remove(key) {
+        None => Ok(None),
+        Some(Value::Str(string)) => Ok(Some(string)),
+        Some(_) => Err(format!("'{}' must be a string", key))
+    }
+}
+
+fn take_bool(table: &mut Table, key: &str) -> Result<Option<bool>, String> {
+    match table.remove(key) {
+        None => Ok(None),
+        Some(Value::Bool(value)) => Ok(Some(value)),
+        Some(_) => Err(format!("'{}' must be true or false", key))
+    }
+}
+
+fn take_array(table: &mut Table, key: &str) -> Result<Vec<String>, String> {
+    match table.remove(key) {
+        None => Ok(vec![]),
+        Some(Value::Array(items)) => Ok(items),
+        Some(_) => Err(format!("'{}' must be an array of strings", key))
+    }
+}
+
+fn expect_empty(table: &Table, name: &str) -> Result<(), String> {
+    match table.keys().next() {
+        Some(key) => Err(format!("unknown key '{}' in [{}]", key, name)),
+        None => Ok(())
+    }
+}
+
+pub struct Manifest {
+    /// The directory the manifest is in. Every path in the manifest is relative to it.
+    pub root: PathBuf,
+    pub name: String,
+    pub source_roots: Vec<PathBuf>,
+    pub kind: OutputKind,
+    pub output_path: PathBuf,
+    pub cc: CCompiler
+}
+
+impl Manifest {
+    /// Parses the text of a manifest found in the directory `root`.
+    pub fn parse(text: &str, root: &Path) -> Result<Manifest, String> {
+        let mut tables = parse_tables(text)?;
+
+        let mut project = tables.remove("project").ok_or_else(|| format!("{} has no [project] table", MANIFEST_NAME))?;
+        let name = take_str(&mut project, "name")?.ok_or_else(|| String::from("[project] needs a name"))?;
+        let mut sources = take_array(&mut project, "sources")?;
+        if sources.is_empty() {
+            sources.push(String::from("."));
+        }
+        let kind = match take_str(&mut project, "output")? {
+            Some(kind) => OutputKind::parse(&kind)?,
+            None => OutputKind::Lib
+        };
+        let output_path = take_str(&mut project, "path")?
+            .map(PathBuf::from)
+            .unwrap_or_else(|| kind.default_path(&name));
+        expect_empty(&project, "project")?;
+
+        let mut cc = CCompiler::from_env();
+        if let Some(mut table) = tables.remove("cc") {
+            if let Some(program) = take_str(&mut table, "program")? {
+                cc.program = program;
+            }
+            cc.opt_level = take_str(&mut table, "opt-level")?;
+            if let Some(pic) = take_bool(&mut table, "pic")? {
+                cc.pic = pic;
+            }
+            cc.include_paths = take_array(&mut table, "include")?.iter().map(|path| root.join(path)).collect();
+            cc.flags = take_array(&mut table, "flags")?;
+            expect_empty(&table, "cc")?;
+        }
+
+        if let Some(name) = tables.keys().next() {
+            return Err(format!("unknown table [{}] in {}", name, MANIFEST_NAME));
+        }
+
+        Ok(Manifest {
+            root: root.to_path_buf(),
+            name,
+            source_roots: sources.iter().map(|source| root.join(source)).collect(),
+            kind,
+            output_path: root.join(output_path),
+            cc
+        })
+    }
+
+    /// Reads `pony.toml` from the directory `dir`.
+    pub fn load(dir: &Path) -> Result<Manifest, String> {
+        let path = dir.join(MANIFEST_NAME);
+        let text = std::fs::read_to_string(&path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        Manifest::parse(&text, dir)
+    }
+
+    /// Every script under the source roots, sorted so that projects always compile in the same
+    /// order.
+    pub fn discover_sources(&self) -> Result<Vec<PathBuf>, String> {
+        discover_scripts(&self.source_roots)
+    }
+}
+
+/// Every script under the directories `roots`, sorted. Used for projects without a manifest,
+/// where the scripts are all those under the project directory.
+pub fn discover_scripts(roots: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut sources = vec![];
+    let mut visited = vec![];
+    for root in roots {
+        find_scripts(root, &mut sources, &mut visited)?;
+    }
+    sources.sort();
+    sources.dedup();
+    Ok(sources)
+}
+
+/// Adds the scripts under `dir` to `found`. Directories are followed through symbolic links, but
+/// each is only searched once, so links that loop back up the tree end the search.
+fn find_scripts(dir: &Path, found: &mut Vec<PathBuf>, visited: &mut Vec<PathBuf>) -> Result<(), String> {
+    let canonical = dir.canonicalize().map_err(|error| format!("{}: {}", dir.display(), error))?;
+    if visited.contains(&canonical) {
+        return Ok(());
+    }
+    visited.push(canonical);
+
+    let entries = std::fs::read_dir(dir).map_err(|error| format!("{}: {}", dir.display(), error))?;
+    for entry in entries {
+        let path = entry.map_err(|error| format!("{}: {}", dir.display(), error))?.path();
+        if path.is_dir() {
+            find_scripts(&path, found, visited)?;
+        }
+        else if path.to_string_lossy().ends_with(SCRIPT_EXTENSION) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full_manifest() {
+        let text = r#"
+# The game's scripts
+[project]
+name = "game"
+sources = ["scripts", "ui/scripts"]  # two roots
+output = "c"
+
+[cc]
+program = "clang"
+opt-level = "2"
+pic = false
+include = ["engine/include"]
+flags = ["-g", "-Wall"]
+"#;
+        let manifest = Manifest::parse(text, Path::new("/proj")).unwrap();
+        assert_eq!(manifest.name, "game");
+        assert_eq!(manifest.source_roots, vec![PathBuf::from("/proj/scripts"), PathBuf::from("/proj/ui/scripts")]);
+        assert_eq!(manifest.kind, OutputKind::C);
+        assert_eq!(manifest.output_path, PathBuf::from("/proj/game.c"));
+        assert_eq!(manifest.cc.program, "clang");
+        assert_eq!(manifest.cc.opt_level.as_deref(), Some("2"));
+        assert!(!manifest.cc.pic);
+        assert_eq!(manifest.cc.include_paths, vec![PathBuf::from("/proj/engine/include")]);
+        assert_eq!(manifest.cc.flags, vec![String::from("-g"), String::from("-Wall")]);
+    }
+
+    #[test]
+    fn parse_minimal_manifest() {
+        let manifest = Manifest::parse("[project]\nname = \"tiny\"\n", Path::new("p")).unwrap();
+        assert_eq!(manifest.source_roots, vec![PathBuf::from("p/.")]);
+        assert_eq!(manifest.kind, OutputKind::Lib);
+        assert_eq!(manifest.output_path, PathBuf::from("p/libtiny.so"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| Manifest::parse(text, Path::new(".")).err().unwrap();
+
+        assert_eq!(error("name = \"x\""), "pony.toml has no [project] table");
+        assert_eq!(error("[project]\nname = x"), "pony.toml:2: unsupported value 'x'");
+        assert_eq!(error("[project]\nname = \"x\"\nname = \"y\""), "pony.toml:3: 'name' is set twice");
+        assert_eq!(error("[project]\nname = \"x\"\nsources = [\"a\" \"b\"]"), "pony.toml:3: expected ',' or ']' in array");
+        assert_eq!(error("[project]\nname = \"x\"\ncolor = \"red\""), "unknown key 'color' in [project]");
+        assert_eq!(error("[project]\nname = \"x\"\n[extra]"), "unknown table [extra] in pony.toml");
+        assert_eq!(error("[project]\nname = \"x\"\noutput = \"exe\""), "unknown output kind 'exe', expected lib, c or bytecode");
+    }
+
+    #[test]
+    fn discover_sorted_sources() {
+        let dir = std::env::temp_dir().join(format!("ponyscript-manifest-{}", std::process::id()));
+        for path in ["scripts/b.pony.script", "scripts/a.pony.script", "scripts/enemies/z.pony.script", "scripts/notes.txt"] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        std::fs::write(dir.join(MANIFEST_NAME), "[project]\nname = \"game\"\nsources = [\"scripts\"]\n").unwrap();
+        // A link back up the tree is searched once, not forever.
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("scripts"), dir.join("scripts/enemies/loop")).unwrap();
+
+        let manifest = Manifest::load(&dir).unwrap();
+        let sources = manifest.discover_sources().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(sources, vec![
+            dir.join("scripts/a.pony.script"),
+            dir.join("scripts/b.pony.script"),
+            dir.join("scripts/enemies/z.pony.script")
+        ]);
+    }
+}
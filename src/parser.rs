@@ -1,323 +1,601 @@
-use std::io::{Read};
-use crate::ast;
-use crate::ast::{FunDecl, Node, Type};
-use crate::ast::Node::{Empty};
-use crate::bindings::{Bindings, FunID, Namespace, VarID};
-
-use crate::lexer::{Lexer, Token};
-use crate::string_pool::{PoolS, StringPool};
-use crate::lexer::token;
-use crate::parser::scope::Scopes;
-
-mod scope;
-
-pub struct Parser<'a, R: Read> {
-    lexer: Lexer<'a, R>,
-
-    current: Token,
-
-    bindings: &'a mut Bindings,
-
-    scope: Scopes,
-
-    namespace: Namespace
-}
-
-impl<'a> Parser<'a, &[u8]> {
-    #[allow(unused)]
-    pub fn from_str(pool: &'a StringPool, string: &'static str, bindings: &'a mut Bindings) -> Self {
-        Parser::new(Lexer::from_str(pool,string), bindings)
-    }
-}
-
-impl<'a, R: Read> Parser<'a, R> {
-    fn advance(&mut self) {
-        self.current = self.lexer.next();
-    }
-
-    fn bind_var(&mut self, string: PoolS) -> ast::BindPoint<VarID> {
-        self.scope.find_var(string)
-    }
-
-    fn unresolved_fun(&mut self, name: PoolS) -> ast::BindPoint<FunID> {
-        ast::BindPoint::<FunID>::unresolved(name)
-    }
-
-    fn new_var_binding(&mut self, string: PoolS, typ: Type) -> VarID {
-        let id = self.bindings.new_var_binding(string, typ);
-        self.scope.add_var(string, id);
-        id
-    }
-
-    fn eat(&mut self, tok: Token) -> bool {
-        if self.current == tok {
-            self.advance();
-            true
-        }
-        else { false }
-    }
-
-    fn err(&self, msg: &'static str) -> ast::RNode {
-        Err(self.lexer.err_msg(msg))
-    }
-
-    fn eat_or_err(&mut self, tok: Token, msg: &'static str) -> Result<(), String> {
-        if self.eat(tok) {
-            Ok(())
-        }
-        else {
-            Err(self.lexer.err_msg(msg))
-        }
-    }
-
-    fn eat_id(&mut self) -> Option<PoolS> {
-        if let Token::ID(string) = self.current {
-            self.advance();
-            return Some(string);
-        }
-        None
-    }
-
-    fn eat_id_or_err(&mut self, msg: &'static str) -> Result<PoolS, String> {
-        self.eat_id().ok_or(self.lexer.err_msg(msg))
-    }
-
-    pub fn new(lexer: Lexer<'a, R>, bindings: &'a mut Bindings) -> Self {
-       Parser {
-            lexer,
-            current: token::bad(),
-            bindings,
-            scope: Scopes::new(),
-            namespace: Namespace::Global
-        }
-    }
-
-    fn parse_id_type(&mut self) -> Result<ast::Type, String> {
-        let id = self.eat_id_or_err("Expected type")?;
-
-        if self.eat(Token::LBracket) {
-            let mut inner = vec![];
-
-            loop {
-                let next_type = self.parse_type()?;
-                inner.push(next_type);
-
-                if !self.eat(Token::Comma) {
-                    self.eat_or_err(Token::RBracket, "Expected ',' or ']' in arg list")?;
-
-                    return Ok(ast::Type::Parameterized(id, inner));
-                }
-            }
-        }
-        else {
-            return Ok(ast::Type::Primitive(id).to_specific());
-        }
-    }
-
-    fn parse_type(&mut self) -> Result<ast::Type, String> {
-        if self.eat(Token::Plus) {
-            return self.parse_id_type().map(|inner| ast::Type::Deref(Box::new(inner)));
-        }
-        if self.eat(Token::QuestionMark) {
-            return self.parse_id_type().map(|inner| ast::Type::Optional(Box::new(inner)));
-        }
-        return self.parse_id_type();
-    }
-
-    fn parse_expr(&mut self) -> ast::RNode {
-        let lhs = match self.current {
-            Token::Num(str) => {
-                self.advance();
-                Ok(ast::NumConst::new(str, ast::Type::UnspecificNumeric).to_node())
-            },
-            Token::ID(_) => {
-                self.parse_expr_id()
-            }
-            _ => { self.err("Expected expression") }
-        }?;
-
-        match self.current {
-            Token::Plus => {
-                self.advance();
-                let rhs = self.parse_expr()?;
-
-                ast::op::add(lhs, rhs)
-            }
-            _ => { Ok(lhs) }
-        }
-    }
-
-    fn parse_let(&mut self) -> ast::RNode {
-        self.advance();
-
-        let id = self.eat_id_or_err("Expected identifier after let")?;
-
-        let mut typ = ast::Type::Unset;
-
-        if self.eat(Token::Colon) {
-            typ = self.parse_type()?;
-        }
-
-        if self.eat(Token::Equals) {
-            let expr = self.parse_expr()?;
-            let expr = Some(Box::new(expr));
-            let bind_id = self.new_var_binding(id, typ);
-            return Ok(ast::Declaration::new_expr(bind_id, expr).to_node());
-        }
-        else {
-            let bind_id = self.new_var_binding(id, typ);
-            return Ok(ast::Declaration::new(bind_id).to_node());
-        }
-    }
-
-    fn parse_expr_id(&mut self) -> ast::RNode {
-        let id = self.eat_id_or_err("Failed to consume identifier when parsing identifier")?;
-        if self.eat(Token::LParen) {
-            let mut args = vec![];
-
-            if !self.eat(Token::RParen) {
-                // Only look for arguments if there isn't an immediate right parenthesis
-                loop {
-                    args.push(self.parse_expr()?);
-
-                    if !self.eat(Token::Comma) {
-                        if self.eat(Token::RParen) {
-                            break;
-                        }
-                        return self.err("Expected ')' or ',' in function call");
-                    }
-                }
-            }
-            // We can't actually bind to a specific function call yet, even if we have seen it...
-            // In particular, resolving which function to bind to has to be done with type information.
-            return Ok(Node::FunCall(self.namespace,self.unresolved_fun(id), args));
-        }
-        else {
-            return Ok(Node::VarRef(self.bind_var(id)));
-        }
-    }
-
-    fn parse_statement_id(&mut self) -> ast::RNode {
-        let lhs = self.parse_expr_id()?;
-
-        if self.eat(Token::Equals) {
-            let rhs = self.parse_expr()?;
-
-            match lhs {
-                Node::VarRef(var) => {
-                    return Ok(Node::Assign(var, Box::new(rhs)));
-                }
-                _ => {
-                    return Err(format!("Only variable assignment supported at the moment"));
-                }
-            }
-        }
-
-        // Function calls are valid statements even if there is no equals
-        if let Node::FunCall(_, _, _) = lhs {
-            return Ok(lhs);
-        }
-
-        // If there is no assignment and no function call, it's not a valid statement (for now).
-
-        self.err("Expected function call or arithmetic expression")
-    }
-
-    fn parse_statement(&mut self) -> ast::RNode {
-        match &self.current {
-            Token::KeyLet => {
-                self.parse_let()
-            }
-            Token::ID(_) => {
-                self.parse_statement_id()
-            }
-            _ => {
-                self.err("Unknown statement")
-            }
-        }
-    }
-
-    fn parse_fun_impl(&mut self) -> ast::RNode {
-        self.advance();
-
-        let id = self.eat_id_or_err("Unexpected token after 'fun'")?;
-
-        let mut args = vec![];
-        let mut return_type = Type::Void;
-
-        self.eat_or_err(Token::LParen,"Expected '(' after function name")?;
-
-        while let Token::ID(param) = self.current {
-            self.advance();
-            self.eat_or_err(Token::Colon,"Expected ':' after function parameter name")?;
-            let next_type = self.parse_type()?;
-
-            let var = self.new_var_binding(param, next_type);
-            args.push(var);
-
-            if !self.eat(Token::Comma) {
-                break;
-            }
-        }
-
-        self.eat_or_err(Token::RParen, "Expected ')' after function name")?;
-
-        // Return type comes after arrow, before colon
-        if self.eat(Token::RArrow) {
-            return_type = self.parse_type()?;
-        }
-
-        self.eat_or_err(Token::Colon,"Expected ':' after function")?;
-        self.eat_or_err(Token::BlockStart,"Expected block after function")?;
-
-        let func_id = self.bindings.new_fun_binding(self.namespace, id, return_type, args)?;
-        let mut func = FunDecl::new(func_id);
-
-        while !self.eat(Token::BlockEnd) {
-            let statement = self.parse_statement();
-            func.body.push(statement?);
-        }
-
-        return func.to_rnode();
-    }
-
-    fn parse_fun(&mut self) -> ast::RNode {
-        self.scope.push();
-        let result = self.parse_fun_impl();
-        self.scope.pop();
-        result
-    }
-
-    fn parse_top_level(&mut self) -> ast::RNode {
-        match self.current {
-            Token::EOF => Ok(Empty),
-            Token::KeyFun => self.parse_fun(),
-            _ => {
-                self.advance();
-                self.err("Unexpected token at top level. Expected 'fun'")
-            }
-        }
-    }
-
-    pub fn parse(&mut self) -> ast::RNode {
-        self.advance();
-
-        self.eat_or_err(Token::KeyExtends, "Expected 'extends' at top of file")?;
-
-        let base = self.eat_id_or_err("Expected base type at top of file")?;
-
-        self.eat_or_err(Token::KeyAs, "Expected 'as' at top of file")?;
-
-        let own = self.eat_id_or_err("Expected node type at top of file")?;
-
-        self.namespace = Namespace::DynamicCall(own);
-
-        let mut tree = ast::Tree { base_type: base, own_type: own, children: vec![] };
-
-        while self.current.is_something() {
-            tree.children.push(self.parse_top_level()?);
-        }
-
-        Ok(Node::Tree(tree))
-    }
+use std::io::{Read};
+use crate::ast;
+use crate::ast::{FunDecl, Node, Span, Stmt, Type};
+use crate::ast::Node::{Empty};
+use crate::bindings::{Bindings, FunID, Namespace, VarID};
+use crate::diagnostic::{did_you_mean, Diagnostic};
+
+use crate::lexer::{Lexer, Token};
+use crate::string_pool::{PoolS, StringPool};
+use crate::lexer::token;
+use crate::parser::scope::Scopes;
+
+mod scope;
+
+pub struct Parser<'a, R: Read> {
+    lexer: Lexer<'a, R>,
+
+    current: Token,
+
+    bindings: &'a mut Bindings,
+
+    scope: Scopes,
+
+    namespace: Namespace,
+
+    /// Whether the function being parsed is a test, where `assert` may be used.
+    in_test: bool,
+
+    /// When set, errors inside a function body or at the top level are collected here, and parsing
+    /// carries on with the next statement or function.
+    recovered: Option<Vec<Diagnostic>>,
+
+    /// A position to report the variables in scope at, and what was found there. See
+    /// `probe_scope`.
+    probe: Option<Span>,
+    probed: Option<Vec<VarID>>,
+
+    /// The node types declared by other scripts, with the script declaring each. See
+    /// `declared_types`.
+    declared: Vec<(PoolS, String)>
+}
+
+impl<'a, 's> Parser<'a, &'s [u8]> {
+    #[allow(unused)]
+    pub fn from_str(pool: &'a StringPool, string: &'s str, bindings: &'a mut Bindings) -> Self {
+        Parser::new(Lexer::from_str(pool,string), bindings)
+    }
+}
+
+impl<'a, R: Read> Parser<'a, R> {
+    fn advance(&mut self) {
+        self.current = self.lexer.next();
+    }
+
+    /// Binds a variable reference to the variable in scope, or fails with the names of the
+    /// variables in scope that are close to it.
+    fn bind_var(&mut self, string: PoolS, span: Span) -> Result<ast::BindPoint<VarID>, Diagnostic> {
+        let point = self.scope.find_var(string);
+        if let ast::BindPoint::Unbound(name) = point {
+            let visible = self.scope.visible().into_iter().map(|id| self.bindings.get_var(id).name.to_utf8());
+            let message = did_you_mean(format!("unknown variable {}", name), &name.to_utf8(), visible);
+            return Err(Diagnostic::at(self.lexer.tagline(), span, message));
+        }
+        Ok(point)
+    }
+
+    fn unresolved_fun(&mut self, name: PoolS) -> ast::BindPoint<FunID> {
+        ast::BindPoint::<FunID>::unresolved(name)
+    }
+
+    fn new_var_binding(&mut self, string: PoolS, span: Span, typ: Type) -> VarID {
+        let id = self.bindings.new_var_binding(string, typ);
+        self.bindings.get_var_mut(id).span = Some(span);
+        self.scope.add_var(string, id);
+        id
+    }
+
+    fn eat(&mut self, tok: Token) -> bool {
+        if self.current == tok {
+            self.advance();
+            true
+        }
+        else { false }
+    }
+
+    fn err(&self, msg: &'static str) -> ast::RNode {
+        Err(self.lexer.err_msg(msg))
+    }
+
+    fn eat_or_err(&mut self, tok: Token, msg: &'static str) -> Result<(), Diagnostic> {
+        if self.eat(tok) {
+            Ok(())
+        }
+        else {
+            Err(self.lexer.err_msg(msg))
+        }
+    }
+
+    fn eat_id(&mut self) -> Option<PoolS> {
+        if let Token::ID(string) = self.current {
+            self.advance();
+            return Some(string);
+        }
+        None
+    }
+
+    fn eat_id_or_err(&mut self, msg: &'static str) -> Result<PoolS, Diagnostic> {
+        self.eat_id().ok_or(self.lexer.err_msg(msg))
+    }
+
+    pub fn new(lexer: Lexer<'a, R>, bindings: &'a mut Bindings) -> Self {
+       Parser {
+            lexer,
+            current: token::bad(),
+            bindings,
+            scope: Scopes::new(),
+            namespace: Namespace::Global,
+            in_test: false,
+            recovered: None,
+            probe: None,
+            probed: None,
+            declared: vec![]
+        }
+    }
+
+    /// Turns on error recovery: a broken statement or function is skipped, and its error is kept
+    /// (see `errors`) rather than ending the parse. Errors in the `extends` line still end it.
+    pub fn recover_errors(&mut self) {
+        self.recovered = Some(vec![]);
+    }
+
+    /// The errors skipped over by a recovering parse.
+    pub fn errors(&self) -> &[Diagnostic] {
+        self.recovered.as_deref().unwrap_or(&[])
+    }
+
+    /// Tells the parser which node types other scripts already declare, with the script declaring
+    /// each. Declaring one of them again fails right after the `extends` line, before any function
+    /// of the script is bound.
+    pub fn declared_types(&mut self, types: Vec<(PoolS, String)>) {
+        self.declared = types;
+    }
+
+    /// Asks the parser to record the variables in scope at `at`, for completion.
+    pub fn probe_scope(&mut self, at: Span) {
+        self.probe = Some(at);
+    }
+
+    /// The variables in scope at the probed position, or `None` if it is not inside a function.
+    pub fn probed_scope(&self) -> Option<&[VarID]> {
+        self.probed.as_deref()
+    }
+
+    /// Records the current scope if the probe lies at or after `span`. Called at the start of
+    /// each function body and statement, so the last call before the probe wins.
+    fn check_probe(&mut self, span: Span) {
+        if self.probe.is_some_and(|probe| span <= probe) {
+            self.probed = Some(self.scope.visible());
+        }
+    }
+
+    /// Keeps `error` if recovering, or returns it otherwise.
+    fn recover(&mut self, error: Diagnostic) -> Result<(), Diagnostic> {
+        match &mut self.recovered {
+            Some(errors) => {
+                errors.push(error);
+                Ok(())
+            }
+            None => Err(error)
+        }
+    }
+
+    fn parse_id_type(&mut self) -> Result<ast::Type, Diagnostic> {
+        let id = self.eat_id_or_err("Expected type")?;
+
+        if self.eat(Token::LBracket) {
+            let mut inner = vec![];
+
+            loop {
+                let next_type = self.parse_type()?;
+                inner.push(next_type);
+
+                if !self.eat(Token::Comma) {
+                    self.eat_or_err(Token::RBracket, "Expected ',' or ']' in arg list")?;
+
+                    return Ok(ast::Type::Parameterized(id, inner));
+                }
+            }
+        }
+        else {
+            return Ok(ast::Type::Primitive(id).to_specific());
+        }
+    }
+
+    fn parse_type(&mut self) -> Result<ast::Type, Diagnostic> {
+        if self.eat(Token::Plus) {
+            return self.parse_id_type().map(|inner| ast::Type::Deref(Box::new(inner)));
+        }
+        if self.eat(Token::QuestionMark) {
+            return self.parse_id_type().map(|inner| ast::Type::Optional(Box::new(inner)));
+        }
+        return self.parse_id_type();
+    }
+
+    /// Parses a sum, and a comparison of two sums. Comparisons do not chain.
+    fn parse_expr(&mut self) -> ast::RNode {
+        let lhs = self.parse_sum()?;
+
+        let op = match self.current {
+            Token::EqualsEquals => ast::Op::Equal,
+            Token::NotEquals => ast::Op::NotEqual,
+            _ => return Ok(lhs)
+        };
+        self.advance();
+        let rhs = self.parse_sum()?;
+
+        ast::op::compare(op, lhs, rhs)
+    }
+
+    fn parse_sum(&mut self) -> ast::RNode {
+        let lhs = match self.current {
+            Token::Num(str) => {
+                self.advance();
+                Ok(ast::NumConst::new(str, ast::Type::UnspecificNumeric).to_node())
+            },
+            Token::ID(_) => {
+                self.parse_expr_id()
+            }
+            _ => { self.err("Expected expression") }
+        }?;
+
+        match self.current {
+            Token::Plus => {
+                self.advance();
+                let rhs = self.parse_sum()?;
+
+                ast::op::add(lhs, rhs)
+            }
+            _ => { Ok(lhs) }
+        }
+    }
+
+    fn parse_let(&mut self) -> ast::RNode {
+        let doc = self.lexer.doc().map(str::to_string);
+        self.advance();
+
+        let span = self.lexer.span();
+        let id = self.eat_id_or_err("Expected identifier after let")?;
+
+        let mut typ = ast::Type::Unset;
+
+        if self.eat(Token::Colon) {
+            typ = self.parse_type()?;
+        }
+
+        if self.eat(Token::Equals) {
+            let expr = self.parse_expr()?;
+            let expr = Some(Box::new(expr));
+            let bind_id = self.new_var_binding(id, span, typ);
+            self.bindings.get_var_mut(bind_id).doc = doc;
+            return Ok(ast::Declaration::new_expr(bind_id, expr).to_node());
+        }
+        else {
+            let bind_id = self.new_var_binding(id, span, typ);
+            self.bindings.get_var_mut(bind_id).doc = doc;
+            return Ok(ast::Declaration::new(bind_id).to_node());
+        }
+    }
+
+    /// Parses the arguments of a call, after its '('.
+    fn parse_call_args(&mut self) -> Result<Vec<Node>, Diagnostic> {
+        let mut args = vec![];
+
+        if !self.eat(Token::RParen) {
+            // Only look for arguments if there isn't an immediate right parenthesis
+            loop {
+                args.push(self.parse_expr()?);
+
+                if !self.eat(Token::Comma) {
+                    if self.eat(Token::RParen) {
+                        break;
+                    }
+                    return Err(self.lexer.err_msg("Expected ')' or ',' in function call"));
+                }
+            }
+        }
+        Ok(args)
+    }
+
+    fn parse_expr_id(&mut self) -> ast::RNode {
+        let span = self.lexer.span();
+        let id = self.eat_id_or_err("Failed to consume identifier when parsing identifier")?;
+
+        // `Type.fun(args)` calls a function of the script declaring `Type`. Which script that is
+        // only matters once the call is resolved, so the type does not have to be declared yet.
+        if self.eat(Token::Dot) {
+            let span = self.lexer.span();
+            let name = self.eat_id_or_err("Expected function name after '.'")?;
+            self.eat_or_err(Token::LParen, "Expected '(' after function name")?;
+            let args = self.parse_call_args()?;
+            return Ok(Node::FunCall(Namespace::DynamicCall(id), self.unresolved_fun(name), span, args));
+        }
+
+        if self.eat(Token::LParen) {
+            let args = self.parse_call_args()?;
+            // We can't actually bind to a specific function call yet, even if we have seen it...
+            // In particular, resolving which function to bind to has to be done with type information.
+            return Ok(Node::FunCall(self.namespace, self.unresolved_fun(id), span, args));
+        }
+        else {
+            return Ok(Node::VarRef(self.bind_var(id, span)?, span));
+        }
+    }
+
+    fn parse_statement_id(&mut self) -> ast::RNode {
+        let lhs = self.parse_expr_id()?;
+
+        if self.eat(Token::Equals) {
+            let rhs = self.parse_expr()?;
+
+            match lhs {
+                Node::VarRef(var, span) => {
+                    return Ok(Node::Assign(var, span, Box::new(rhs)));
+                }
+                _ => {
+                    return Err(self.lexer.err_msg("Only variable assignment supported at the moment"));
+                }
+            }
+        }
+
+        // Function calls are valid statements even if there is no equals
+        if let Node::FunCall(..) = lhs {
+            return Ok(lhs);
+        }
+
+        // If there is no assignment and no function call, it's not a valid statement (for now).
+
+        self.err("Expected function call or arithmetic expression")
+    }
+
+    fn parse_return(&mut self) -> ast::RNode {
+        let line = self.lexer.span().line;
+        self.advance();
+
+        // A bare 'return' is followed by the next statement on a new line (or the end of the
+        // block), while a returned expression has to start on the same line.
+        let bare = matches!(self.current, Token::BlockEnd | Token::EOF) || self.lexer.span().line != line;
+        if bare {
+            return Ok(Node::Return(None));
+        }
+
+        let expr = self.parse_expr()?;
+        Ok(Node::Return(Some(Box::new(expr))))
+    }
+
+    fn parse_assert(&mut self) -> ast::RNode {
+        let span = self.lexer.span();
+        if !self.in_test {
+            return self.err("'assert' is only allowed in test functions");
+        }
+        self.advance();
+
+        self.eat_or_err(Token::LParen, "Expected '(' after 'assert'")?;
+        let cond = self.parse_expr()?;
+
+        let mut message = None;
+        if self.eat(Token::Comma) {
+            match &self.current {
+                Token::StringLiteral(bytes) => message = Some(String::from_utf8_lossy(bytes).to_string()),
+                _ => return self.err("Expected a string message in 'assert'")
+            }
+            self.advance();
+        }
+        self.eat_or_err(Token::RParen, "Expected ')' after assertion")?;
+
+        Ok(Node::Assert(span, Box::new(cond), message))
+    }
+
+    fn parse_statement(&mut self) -> ast::RNode {
+        match &self.current {
+            Token::KeyLet => {
+                self.parse_let()
+            }
+            Token::KeyReturn => {
+                self.parse_return()
+            }
+            Token::ID(name) if name.eq_utf8("assert") => {
+                self.parse_assert()
+            }
+            Token::ID(_) => {
+                self.parse_statement_id()
+            }
+            _ => {
+                self.err("Unknown statement")
+            }
+        }
+    }
+
+    fn parse_fun_impl(&mut self) -> ast::RNode {
+        let span = self.lexer.span();
+        let doc = self.lexer.doc().map(str::to_string);
+        if self.in_test {
+            self.advance();
+            if self.current != Token::KeyFun {
+                return self.err("Expected 'fun' after 'test'");
+            }
+        }
+        self.advance();
+
+        let name_span = self.lexer.span();
+        let id = self.eat_id_or_err("Unexpected token after 'fun'")?;
+
+        let mut args = vec![];
+        let mut return_type = Type::Void;
+
+        self.eat_or_err(Token::LParen,"Expected '(' after function name")?;
+
+        while let Token::ID(param) = self.current {
+            let param_span = self.lexer.span();
+            self.advance();
+            self.eat_or_err(Token::Colon,"Expected ':' after function parameter name")?;
+            let next_type = self.parse_type()?;
+
+            let var = self.new_var_binding(param, param_span, next_type);
+            args.push(var);
+
+            if !self.eat(Token::Comma) {
+                break;
+            }
+        }
+
+        self.eat_or_err(Token::RParen, "Expected ')' after function name")?;
+
+        // Return type comes after arrow, before colon
+        if self.eat(Token::RArrow) {
+            return_type = self.parse_type()?;
+        }
+
+        self.eat_or_err(Token::Colon,"Expected ':' after function")?;
+        if self.in_test && (!args.is_empty() || return_type != Type::Void) {
+            return Err(Diagnostic::at(self.lexer.tagline(), span, String::from("Test functions take no parameters and return nothing")));
+        }
+        self.eat_or_err(Token::BlockStart,"Expected block after function")?;
+
+        let func_id = self.bindings.new_fun_binding(self.namespace, id, return_type, args)
+            .map_err(|message| Diagnostic::at(self.lexer.tagline(), span, message))?;
+        let binding = self.bindings.get_fun_mut(func_id);
+        binding.span = Some(name_span);
+        binding.doc = doc;
+        binding.test = self.in_test;
+        let mut func = FunDecl::new(func_id, span);
+        // The body starts on the line after the header.
+        self.check_probe(Span::new(span.line + 1, 1));
+
+        let mut line = span.line;
+        while !self.eat(Token::BlockEnd) {
+            let span = self.lexer.span();
+            // Whatever is left of a broken statement still belongs to the statement before it.
+            if span.line != line {
+                self.check_probe(span);
+                line = span.line;
+            }
+
+            match self.parse_statement() {
+                Ok(statement) => func.body.push(Stmt::new(span, statement)),
+                Err(error) => {
+                    self.recover(error)?;
+                    // Skip the rest of the statement, which ends with its line.
+                    while !self.current.is_eof() && self.current != Token::BlockEnd && self.lexer.span().line == span.line {
+                        self.advance();
+                    }
+                    if self.current.is_eof() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Past the end of the function, its variables are out of scope again.
+        if self.probe.is_some_and(|probe| self.lexer.span() <= probe) {
+            self.probed = None;
+        }
+
+        return func.to_rnode();
+    }
+
+    /// Parses a function, or a test function starting with `test` if `test` is set.
+    fn parse_fun(&mut self, test: bool) -> ast::RNode {
+        self.bindings.begin_fun();
+        self.scope.push();
+        self.in_test = test;
+        let result = self.parse_fun_impl();
+        self.in_test = false;
+        self.scope.pop();
+        result
+    }
+
+    /// Parses a script variable: a `let` outside of any function, which keeps its value from one
+    /// call to the next. It needs a type, `int` or `float`, and can only be given a number, so that
+    /// its initial value is known without running anything.
+    fn parse_state(&mut self, own: PoolS) -> ast::RNode {
+        let doc = self.lexer.doc().map(str::to_string);
+        self.advance();
+
+        let span = self.lexer.span();
+        let name = self.eat_id_or_err("Expected identifier after let")?;
+        if let ast::BindPoint::BoundTo(_) = self.scope.find_var(name) {
+            let message = format!("script variable {} is already declared", name);
+            return Err(Diagnostic::at(self.lexer.tagline(), span, message));
+        }
+
+        self.eat_or_err(Token::Colon, "Expected ':' and a type after script variable name")?;
+        let type_span = self.lexer.span();
+        let typ = self.parse_type()?;
+        if !typ.is_specific_numeric() {
+            return Err(Diagnostic::at(self.lexer.tagline(), type_span, String::from("Script variables can only be int or float")));
+        }
+
+        let mut expr = None;
+        if self.eat(Token::Equals) {
+            let Token::Num(value) = self.current else {
+                return self.err("Expected a number as the value of a script variable");
+            };
+            self.advance();
+            expr = Some(Box::new(ast::NumConst::new(value, Type::UnspecificNumeric).to_node()));
+        }
+
+        let bind_id = self.bindings.new_state_binding(own, name, typ);
+        let binding = self.bindings.get_var_mut(bind_id);
+        binding.span = Some(span);
+        binding.doc = doc;
+        self.scope.add_var(name, bind_id);
+
+        Ok(match expr {
+            Some(expr) => ast::Declaration::new_expr(bind_id, Some(expr)).to_node(),
+            None => ast::Declaration::new(bind_id).to_node()
+        })
+    }
+
+    fn parse_top_level(&mut self, own: PoolS) -> ast::RNode {
+        match self.current {
+            Token::EOF => Ok(Empty),
+            Token::KeyFun => self.parse_fun(false),
+            Token::KeyLet => self.parse_state(own),
+            // `test` is only a keyword in front of `fun`, so it stays usable as a name.
+            Token::ID(name) if name.eq_utf8("test") => self.parse_fun(true),
+            _ => {
+                self.advance();
+                self.err("Unexpected token at top level. Expected 'fun' or 'let'")
+            }
+        }
+    }
+
+    pub fn parse(&mut self) -> ast::RNode {
+        self.advance();
+        let doc = self.lexer.doc().map(str::to_string);
+
+        self.eat_or_err(Token::KeyExtends, "Expected 'extends' at top of file")?;
+
+        let base = self.eat_id_or_err("Expected base type at top of file")?;
+
+        self.eat_or_err(Token::KeyAs, "Expected 'as' at top of file")?;
+
+        let own = self.eat_id_or_err("Expected node type at top of file")?;
+        if let Some((_, source)) = self.declared.iter().find(|(name, _)| *name == own) {
+            let message = format!("type {} is already declared in {}", own, source);
+            return Err(Diagnostic::new(message).in_file(self.lexer.tagline()));
+        }
+
+        self.namespace = Namespace::DynamicCall(own);
+
+        let mut tree = ast::Tree {
+            source: self.lexer.tagline().to_string(),
+            base_type: base,
+            own_type: own,
+            doc,
+            children: vec![]
+        };
+
+        // The script's variables are in scope in every function after them.
+        self.scope.push();
+
+        // A recovering parse skips over tokens that could not be lexed, too.
+        while self.current.is_something() || (self.recovered.is_some() && self.current.is_bad()) {
+            match self.parse_top_level(own) {
+                Ok(child) => tree.children.push(child),
+                Err(error) => {
+                    self.recover(error)?;
+                    // Skip to the next function or script variable.
+                    while !self.current.is_eof() && self.current != Token::KeyFun && self.current != Token::KeyLet && !self.current.is_id_str("test") {
+                        self.advance();
+                    }
+                }
+            }
+        }
+
+        self.scope.pop();
+
+        Ok(Node::Tree(tree))
+    }
 }
\ No newline at end of file
@@ -0,0 +1,364 @@
+//! A bytecode backend for running scripts inside a Rust host.
+//!
+//! Typechecked trees are lowered into a [`Module`] (see `lower`), which can be serialized with
+//! [`Module::to_bytes`] and loaded into a [`Vm`]. The host can provide its own functions through
+//! [`Natives`]: they are declared to the compiler before parsing, so scripts call them (and
+//! overload resolution picks them) exactly like script functions.
+
+use std::collections::HashMap;
+use crate::ast::{Op, Type};
+use crate::bindings::{Bindings, Namespace};
+use crate::interp::{binop, Value};
+use crate::string_pool::StringPool;
+
+mod bytecode;
+mod lower;
+
+pub use bytecode::{Function, Global, Instr, Module, NativeRef, ValueType, signature};
+pub use lower::lower;
+
+pub type NativeFn = Box<dyn Fn(&[Value]) -> Result<Value, String>>;
+
+struct Native {
+    typ: String,
+    name: String,
+    params: Vec<ValueType>,
+    return_type: ValueType,
+    fun: NativeFn
+}
+
+/// The Rust functions that a host makes available to scripts.
+#[derive(Default)]
+pub struct Natives {
+    natives: Vec<Native>
+}
+
+fn to_type(typ: ValueType) -> Type {
+    match typ {
+        ValueType::Void => Type::Void,
+        ValueType::Int => Type::Int32,
+        ValueType::Float => Type::Float
+    }
+}
+
+fn type_of(value: &Value) -> ValueType {
+    match value {
+        Value::Void => ValueType::Void,
+        Value::Int(_) => ValueType::Int,
+        Value::Float(_) => ValueType::Float
+    }
+}
+
+impl Natives {
+    pub fn new() -> Self {
+        Natives::default()
+    }
+
+    /// Registers `fun` as `typ.name(params...)`, callable from scripts of type `typ`. Several
+    /// natives (and script functions) may share a name as long as their parameters differ.
+    pub fn register<F>(&mut self, typ: &str, name: &str, params: &[ValueType], return_type: ValueType, fun: F)
+        where F: Fn(&[Value]) -> Result<Value, String> + 'static
+    {
+        self.natives.push(Native {
+            typ: typ.to_string(),
+            name: name.to_string(),
+            params: params.to_vec(),
+            return_type,
+            fun: Box::new(fun)
+        });
+    }
+
+    /// Declares every native in `bindings`, so that typecheck can bind calls to them.
+    pub fn declare(&self, pool: &StringPool, bindings: &mut Bindings) -> Result<(), String> {
+        for native in &self.natives {
+            let namespace = Namespace::DynamicCall(pool.pool_ref(&native.typ.as_bytes().to_vec()));
+            let name = pool.pool_ref(&native.name.as_bytes().to_vec());
+            let params: Vec<_> = native.params.iter().enumerate()
+                .map(|(index, typ)| (pool.pool(format!("arg{}", index).into_bytes()), to_type(*typ)))
+                .collect();
+
+            bindings.new_native_fun_binding(namespace, name, to_type(native.return_type), &params)?;
+        }
+        Ok(())
+    }
+}
+
+/// How deeply calls may nest. Frames live on the heap, so this only guards against runaway
+/// recursion growing the stacks without bound.
+pub const MAX_FRAMES: usize = 10_000;
+
+struct Frame {
+    function: usize,
+    pc: usize,
+    /// Where this frame's slots start in `Vm::slots`.
+    base: usize
+}
+
+/// Runs a loaded module. Script variables keep their values from one call to the next.
+pub struct Vm {
+    module: Module,
+    /// For each of the module's natives, the host function implementing it.
+    natives: Vec<NativeFn>,
+    stack: Vec<Value>,
+    slots: Vec<Option<Value>>,
+    globals: Vec<Value>
+}
+
+impl Vm {
+    /// Loads `module`, linking each native it calls to the host function registered with the same
+    /// name and signature.
+    pub fn load(module: Module, natives: Natives) -> Result<Vm, String> {
+        let mut available: HashMap<String, Native> = natives.natives.into_iter()
+            .map(|native| (signature(&format!("{}.{}", native.typ, native.name), &native.params), native))
+            .collect();
+
+        let mut linked = vec![];
+        for wanted in &module.natives {
+            let key = signature(&wanted.name, &wanted.params);
+            let native = available.remove(&key).ok_or_else(|| format!("native {} is not registered", key))?;
+            if native.return_type != wanted.return_type {
+                return Err(format!("native {} returns {}, but the module expects {}", key, native.return_type, wanted.return_type));
+            }
+            linked.push(native.fun);
+        }
+
+        let globals = module.globals.iter().map(|global| global.initial).collect();
+        Ok(Vm { module, natives: linked, stack: vec![], slots: vec![], globals })
+    }
+
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Calls the function `name` (written as `Type.function`), picking the overload whose
+    /// parameter types match the arguments exactly.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, String> {
+        let arg_types: Vec<ValueType> = args.iter().map(type_of).collect();
+        let index = self.module.functions.iter()
+            .position(|function| function.name == name && function.params == arg_types)
+            .ok_or_else(|| format!("no function {}", signature(name, &arg_types)))?;
+
+        self.stack.clear();
+        self.slots.clear();
+        self.stack.extend_from_slice(args);
+        let result = self.run(index);
+        if result.is_err() {
+            self.stack.clear();
+            self.slots.clear();
+        }
+        result
+    }
+
+    /// Pushes a frame for `function`, moving its arguments from the stack into its slots.
+    fn enter(&mut self, frames: &mut Vec<Frame>, function: usize) -> Result<(), String> {
+        let callee = &self.module.functions[function];
+        if frames.len() >= MAX_FRAMES {
+            return Err(format!("call depth exceeded {} in {}", MAX_FRAMES, callee.name));
+        }
+        let argc = callee.params.len();
+        if self.stack.len() < argc {
+            return Err(format!("stack underflow calling {}", callee.name));
+        }
+
+        let base = self.slots.len();
+        self.slots.extend(self.stack.drain(self.stack.len() - argc..).map(Some));
+        self.slots.resize(base + callee.slots as usize, None);
+        frames.push(Frame { function, pc: 0, base });
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Value, String> {
+        self.stack.pop().ok_or_else(|| String::from("stack underflow"))
+    }
+
+    fn run(&mut self, entry: usize) -> Result<Value, String> {
+        let mut frames = vec![];
+        self.enter(&mut frames, entry)?;
+
+        loop {
+            let frame = frames.last_mut().unwrap();
+            let function = &self.module.functions[frame.function];
+            let instr = *function.code.get(frame.pc)
+                .ok_or_else(|| format!("ran off the end of {}", function.name))?;
+            frame.pc += 1;
+            let base = frame.base;
+
+            match instr {
+                Instr::PushInt(value) => self.stack.push(Value::Int(value)),
+                Instr::PushFloat(value) => self.stack.push(Value::Float(value)),
+                Instr::Load(slot) => {
+                    let value = self.slots.get(base + slot as usize).copied().flatten()
+                        .ok_or_else(|| format!("slot {} read before it was assigned", slot))?;
+                    self.stack.push(value);
+                }
+                Instr::Store(slot) => {
+                    let value = self.pop()?;
+                    let slot = self.slots.get_mut(base + slot as usize)
+                        .ok_or_else(|| format!("bad slot {}", slot))?;
+                    *slot = Some(value);
+                }
+                Instr::LoadGlobal(index) => {
+                    let value = *self.globals.get(index as usize)
+                        .ok_or_else(|| format!("bad global {}", index))?;
+                    self.stack.push(value);
+                }
+                Instr::StoreGlobal(index) => {
+                    let value = self.pop()?;
+                    let global = self.globals.get_mut(index as usize)
+                        .ok_or_else(|| format!("bad global {}", index))?;
+                    *global = value;
+                }
+                Instr::Add | Instr::Sub | Instr::Mul | Instr::Div | Instr::Eq | Instr::Ne => {
+                    let rhs = self.pop()?;
+                    let lhs = self.pop()?;
+                    let op = match instr {
+                        Instr::Add => Op::Add,
+                        Instr::Sub => Op::Subtract,
+                        Instr::Mul => Op::Multiply,
+                        Instr::Eq => Op::Equal,
+                        Instr::Ne => Op::NotEqual,
+                        _ => Op::Divide
+                    };
+                    self.stack.push(binop(&op, lhs, rhs)?);
+                }
+                Instr::Call(index) => {
+                    let index = index as usize;
+                    if index >= self.module.functions.len() {
+                        return Err(format!("call to missing function {}", index));
+                    }
+                    self.enter(&mut frames, index)?;
+                }
+                Instr::CallNative(index) => {
+                    let native = self.module.natives.get(index as usize)
+                        .ok_or_else(|| format!("call to missing native {}", index))?;
+                    let argc = native.params.len();
+                    if self.stack.len() < argc {
+                        return Err(format!("stack underflow calling {}", native.name));
+                    }
+                    let args = self.stack.split_off(self.stack.len() - argc);
+                    let result = (self.natives[index as usize])(&args)?;
+                    self.stack.push(result);
+                }
+                Instr::Pop => {
+                    self.pop()?;
+                }
+                Instr::Return | Instr::ReturnVoid => {
+                    let result = if instr == Instr::Return { self.pop()? } else { Value::Void };
+                    let frame = frames.pop().unwrap();
+                    self.slots.truncate(frame.base);
+                    if frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.stack.push(result);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::ast::typecheck;
+    use crate::parser::Parser;
+    use super::*;
+
+    fn compile(source: &'static str, natives: &Natives) -> Module {
+        let pool = StringPool::new();
+        let mut bindings = Bindings::new();
+        natives.declare(&pool, &mut bindings).unwrap();
+
+        let mut tree = Parser::from_str(&pool, source, &mut bindings).parse().unwrap();
+        typecheck(&mut bindings, &mut tree).unwrap();
+        lower(&bindings, &[tree]).unwrap()
+    }
+
+    fn host_natives(log: Rc<RefCell<Vec<Value>>>) -> Natives {
+        let mut natives = Natives::new();
+        natives.register("Host", "report", &[ValueType::Int], ValueType::Void, move |args| {
+            log.borrow_mut().push(args[0]);
+            Ok(Value::Void)
+        });
+        natives.register("Host", "scale", &[ValueType::Float], ValueType::Float, |args| {
+            match args[0] {
+                Value::Float(value) => Ok(Value::Float(value * 10.0)),
+                _ => Err(String::from("expected a float"))
+            }
+        });
+        natives.register("Host", "scale", &[ValueType::Int], ValueType::Int, |args| {
+            match args[0] {
+                Value::Int(value) => Ok(Value::Int(value * 10)),
+                _ => Err(String::from("expected an int"))
+            }
+        });
+        natives
+    }
+
+    const SOURCE: &str = "extends Node as Host
+
+fun main(x: int) -> int:
+	report(x)
+	let y: int = scale(x) + 1
+	report(y)
+	return y
+
+fun main(x: float) -> float:
+	return scale(x)
+";
+
+    #[test]
+    fn natives_take_part_in_overloads() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let module = compile(SOURCE, &host_natives(log.clone()));
+
+        let mut vm = Vm::load(module, host_natives(log.clone())).unwrap();
+        assert_eq!(vm.call("Host.main", &[Value::Int(4)]).unwrap(), Value::Int(41));
+        assert_eq!(*log.borrow(), vec![Value::Int(4), Value::Int(41)]);
+
+        assert_eq!(vm.call("Host.main", &[Value::Float(0.5)]).unwrap(), Value::Float(5.0));
+        assert!(vm.call("Host.main", &[]).is_err());
+        assert!(vm.call("Host.other", &[Value::Int(1)]).is_err());
+    }
+
+    #[test]
+    fn missing_natives_fail_to_load() {
+        let module = compile(SOURCE, &host_natives(Rc::new(RefCell::new(vec![]))));
+        let error = Vm::load(module, Natives::new()).err().unwrap();
+        assert_eq!(error, "native Host.report(int) is not registered");
+    }
+
+    #[test]
+    fn script_variables_keep_their_values() {
+        let source = "extends Node as Counter\n\nlet count: int = 10\nlet step: float = 2\n\nfun bump() -> int:\n\tcount = count + 1\n\treturn count\n";
+        let module = compile(source, &Natives::new());
+        assert_eq!(module.globals, vec![
+            Global { name: String::from("Counter.count"), typ: ValueType::Int, initial: Value::Int(10) },
+            Global { name: String::from("Counter.step"), typ: ValueType::Float, initial: Value::Float(2.0) }
+        ]);
+        assert_eq!(Module::from_bytes(&module.to_bytes().unwrap()).unwrap(), module);
+
+        let mut vm = Vm::load(module, Natives::new()).unwrap();
+        assert_eq!(vm.call("Counter.bump", &[]).unwrap(), Value::Int(11));
+        assert_eq!(vm.call("Counter.bump", &[]).unwrap(), Value::Int(12));
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let module = compile(SOURCE, &host_natives(Rc::new(RefCell::new(vec![]))));
+        let bytes = module.to_bytes().unwrap();
+        assert!(bytes.starts_with(b"PSBC"));
+        assert_eq!(Module::from_bytes(&bytes).unwrap(), module);
+
+        assert!(Module::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Module::from_bytes(b"nope").is_err());
+        let mut old = bytes.clone();
+        old[4] = 1;
+        assert_eq!(Module::from_bytes(&old).unwrap_err(), "unsupported bytecode version 1");
+
+        // Parameter counts are a byte, and are never cut short.
+        let mut module = module;
+        module.natives[0].params = vec![ValueType::Int; 256];
+        assert_eq!(module.to_bytes().unwrap_err(), format!("{} has more than 255 parameters", module.natives[0].name));
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt::{Display, Formatter};
+use crate::interp::Value;
+
+/// The types a value can have at runtime, as seen by the VM and by the host.
+#[derive(Copy, Clone)]
+#[derive(Debug, Eq, PartialEq)]
+pub enum ValueType {
+    Void,
+    Int,
+    Float
+}
+
+impl ValueType {
+    fn tag(self) -> u8 {
+        match self {
+            ValueType::Void => 0,
+            ValueType::Int => 1,
+            ValueType::Float => 2
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(ValueType::Void),
+            1 => Some(ValueType::Int),
+            2 => Some(ValueType::Float),
+            _ => None
+        }
+    }
+}
+
+impl Display for ValueType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueType::Void => f.write_str("void"),
+            ValueType::Int => f.write_str("int"),
+            ValueType::Float => f.write_str("float")
+        }
+    }
+}
+
+/// A single stack machine instruction. Operands of arithmetic are popped from the stack, and the
+/// result is pushed back onto it. Every call pushes a result, which is `Void` for functions that
+/// do not return a value.
+#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq)]
+pub enum Instr {
+    PushInt(i32),
+    PushFloat(f32),
+    Load(u16),
+    Store(u16),
+    /// Pushes the script variable at the given index in the module's global list.
+    LoadGlobal(u16),
+    StoreGlobal(u16),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// Pushes 1 if the operands are equal and 0 otherwise.
+    Eq,
+    Ne,
+    /// Calls the function at the given index in the module.
+    Call(u32),
+    /// Calls the native at the given index in the module's native list.
+    CallNative(u32),
+    Pop,
+    Return,
+    ReturnVoid
+}
+
+/// A function lowered to bytecode. Its parameters occupy the first slots.
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    /// The function's name without parameters, e.g. `Calc.add`.
+    pub name: String,
+    pub params: Vec<ValueType>,
+    pub return_type: ValueType,
+    pub slots: u16,
+    pub code: Vec<Instr>
+}
+
+/// A script variable, which keeps its value from one call to the next.
+#[derive(Debug, PartialEq)]
+pub struct Global {
+    /// The variable's name, e.g. `Player.health`.
+    pub name: String,
+    pub typ: ValueType,
+    pub initial: Value
+}
+
+/// A native function that a module calls, but that has to be provided by the host.
+#[derive(Debug, PartialEq)]
+pub struct NativeRef {
+    pub name: String,
+    pub params: Vec<ValueType>,
+    pub return_type: ValueType
+}
+
+/// Formats a function signature the way natives and overloads are named, e.g. `Calc.add(int, int)`.
+pub fn signature(name: &str, params: &[ValueType]) -> String {
+    let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+    format!("{}({})", name, params.join(", "))
+}
+
+/// A compiled set of scripts.
+#[derive(Debug, Default, PartialEq)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    pub natives: Vec<NativeRef>,
+    pub globals: Vec<Global>
+}
+
+const MAGIC: &[u8; 4] = b"PSBC";
+/// Bumped whenever the format changes, so that older modules are rejected instead of misread.
+/// Version 2 added the `Eq` and `Ne` opcodes, and version 3 the globals and their opcodes.
+const VERSION: u8 = 3;
+
+struct Writer {
+    bytes: Vec<u8>
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) { self.bytes.push(value); }
+    fn u16(&mut self, value: u16) { self.bytes.extend_from_slice(&value.to_le_bytes()); }
+    fn u32(&mut self, value: u32) { self.bytes.extend_from_slice(&value.to_le_bytes()); }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    /// Writes a parameter list. Its length is a single byte, so `name` can have at most 255
+    /// parameters.
+    fn types(&mut self, name: &str, types: &[ValueType]) -> Result<(), String> {
+        let len = u8::try_from(types.len()).map_err(|_| format!("{} has more than 255 parameters", name))?;
+        self.u8(len);
+        for typ in types {
+            self.u8(typ.tag());
+        }
+        Ok(())
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        match *instr {
+            Instr::PushInt(value) => { self.u8(0); self.u32(value as u32); }
+            Instr::PushFloat(value) => { self.u8(1); self.u32(value.to_bits()); }
+            Instr::Load(slot) => { self.u8(2); self.u16(slot); }
+            Instr::Store(slot) => { self.u8(3); self.u16(slot); }
+            Instr::Add => self.u8(4),
+            Instr::Sub => self.u8(5),
+            Instr::Mul => self.u8(6),
+            Instr::Div => self.u8(7),
+            Instr::Call(index) => { self.u8(8); self.u32(index); }
+            Instr::CallNative(index) => { self.u8(9); self.u32(index); }
+            Instr::Pop => self.u8(10),
+            Instr::Return => self.u8(11),
+            Instr::ReturnVoid => self.u8(12),
+            Instr::Eq => self.u8(13),
+            Instr::Ne => self.u8(14),
+            Instr::LoadGlobal(index) => { self.u8(15); self.u16(index); }
+            Instr::StoreGlobal(index) => { self.u8(16); self.u16(index); }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| String::from("bytecode ends unexpectedly"))?;
+        let result = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> { Ok(self.take(1)?[0]) }
+    fn u16(&mut self) -> Result<u16, String> { Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap())) }
+    fn u32(&mut self) -> Result<u32, String> { Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap())) }
+
+    fn str(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| String::from("bytecode contains a bad string"))
+    }
+
+    fn typ(&mut self) -> Result<ValueType, String> {
+        ValueType::from_tag(self.u8()?).ok_or_else(|| String::from("bytecode contains a bad type"))
+    }
+
+    fn types(&mut self) -> Result<Vec<ValueType>, String> {
+        let len = self.u8()?;
+        (0..len).map(|_| self.typ()).collect()
+    }
+
+    fn instr(&mut self) -> Result<Instr, String> {
+        Ok(match self.u8()? {
+            0 => Instr::PushInt(self.u32()? as i32),
+            1 => Instr::PushFloat(f32::from_bits(self.u32()?)),
+            2 => Instr::Load(self.u16()?),
+            3 => Instr::Store(self.u16()?),
+            4 => Instr::Add,
+            5 => Instr::Sub,
+            6 => Instr::Mul,
+            7 => Instr::Div,
+            8 => Instr::Call(self.u32()?),
+            9 => Instr::CallNative(self.u32()?),
+            10 => Instr::Pop,
+            11 => Instr::Return,
+            12 => Instr::ReturnVoid,
+            13 => Instr::Eq,
+            14 => Instr::Ne,
+            15 => Instr::LoadGlobal(self.u16()?),
+            16 => Instr::StoreGlobal(self.u16()?),
+            op => return Err(format!("bytecode contains unknown opcode {}", op))
+        })
+    }
+}
+
+impl Module {
+    /// Serializes the module into its compact binary form. Fails if a function has too many
+    /// parameters for it.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut writer = Writer { bytes: MAGIC.to_vec() };
+        writer.u8(VERSION);
+
+        writer.u32(self.functions.len() as u32);
+        for function in &self.functions {
+            writer.str(&function.name);
+            writer.types(&function.name, &function.params)?;
+            writer.u8(function.return_type.tag());
+            writer.u16(function.slots);
+            writer.u32(function.code.len() as u32);
+            for instr in &function.code {
+                writer.instr(instr);
+            }
+        }
+
+        writer.u32(self.natives.len() as u32);
+        for native in &self.natives {
+            writer.str(&native.name);
+            writer.types(&native.name, &native.params)?;
+            writer.u8(native.return_type.tag());
+        }
+
+        writer.u32(self.globals.len() as u32);
+        for global in &self.globals {
+            writer.str(&global.name);
+            writer.u8(global.typ.tag());
+            writer.u32(match global.initial {
+                Value::Int(value) => value as u32,
+                Value::Float(value) => value.to_bits(),
+                Value::Void => 0
+            });
+        }
+
+        Ok(writer.bytes)
+    }
+
+    /// Reads a module written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Module, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(String::from("not a PonyScript bytecode module"));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("unsupported bytecode version {}", version));
+        }
+
+        let mut module = Module::default();
+
+        let count = reader.u32()?;
+        for _ in 0..count {
+            let name = reader.str()?;
+            let params = reader.types()?;
+            let return_type = reader.typ()?;
+            let slots = reader.u16()?;
+            let len = reader.u32()?;
+            let code = (0..len).map(|_| reader.instr()).collect::<Result<_, _>>()?;
+            module.functions.push(Function { name, params, return_type, slots, code });
+        }
+
+        let count = reader.u32()?;
+        for _ in 0..count {
+            let name = reader.str()?;
+            let params = reader.types()?;
+            let return_type = reader.typ()?;
+            module.natives.push(NativeRef { name, params, return_type });
+        }
+
+        let count = reader.u32()?;
+        for _ in 0..count {
+            let name = reader.str()?;
+            let typ = reader.typ()?;
+            let bits = reader.u32()?;
+            let initial = match typ {
+                ValueType::Int => Value::Int(bits as i32),
+                ValueType::Float => Value::Float(f32::from_bits(bits)),
+                ValueType::Void => return Err(String::from("bytecode contains a void global"))
+            };
+            module.globals.push(Global { name, typ, initial });
+        }
+
+        if reader.pos != bytes.len() {
+            return Err(String::from("trailing data after bytecode module"));
+        }
+
+        Ok(module)
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use crate::ast::{FunDecl, Node, Op, Type};
+use crate::bindings::{Bindings, FunBinding, FunID, VarID};
+use crate::interp::Value;
+use super::bytecode::{Function, Global, Instr, Module, NativeRef, ValueType};
+
+fn value_type(typ: &Type) -> Result<ValueType, String> {
+    match typ {
+        Type::Void => Ok(ValueType::Void),
+        Type::Int32 => Ok(ValueType::Int),
+        Type::Float => Ok(ValueType::Float),
+        _ => Err(format!("type {} is not supported by the bytecode backend", typ.source_name()))
+    }
+}
+
+/// The name of a function without its parameter list, e.g. `Calc.add`.
+fn plain_name(fun: &FunBinding) -> String {
+    let name = fun.source_name();
+    match name.split_once('(') {
+        Some((name, _)) => name.to_string(),
+        None => name
+    }
+}
+
+fn signature(bindings: &Bindings, fun: &FunBinding) -> Result<(Vec<ValueType>, ValueType), String> {
+    let params = fun.args.iter()
+        .map(|arg| value_type(&bindings.get_var(*arg).typ))
+        .collect::<Result<_, _>>()?;
+    Ok((params, value_type(&fun.return_type)?))
+}
+
+enum Callee {
+    Function(u32),
+    Native(u32)
+}
+
+struct Lowering<'a> {
+    bindings: &'a Bindings,
+    callees: HashMap<FunID, Callee>,
+    /// The index of each script variable in the module's globals.
+    globals: HashMap<VarID, u16>,
+    module: Module
+}
+
+/// Lowers one function body. Slots are numbered in the order variables are declared, starting
+/// with the parameters.
+struct FunLowering<'l, 'a> {
+    lowering: &'l mut Lowering<'a>,
+    slots: HashMap<VarID, u16>,
+    code: Vec<Instr>
+}
+
+impl<'l, 'a> FunLowering<'l, 'a> {
+    fn slot(&mut self, id: VarID) -> Result<u16, String> {
+        if let Some(slot) = self.slots.get(&id) {
+            return Ok(*slot);
+        }
+        let slot = u16::try_from(self.slots.len()).map_err(|_| String::from("too many variables in one function"))?;
+        self.slots.insert(id, slot);
+        Ok(slot)
+    }
+
+    fn callee(&mut self, id: FunID) -> Result<Instr, String> {
+        if let Some(callee) = self.lowering.callees.get(&id) {
+            return Ok(match callee {
+                Callee::Function(index) => Instr::Call(*index),
+                Callee::Native(index) => Instr::CallNative(*index)
+            });
+        }
+
+        let bindings = self.lowering.bindings;
+        let fun = bindings.get_fun(id);
+        if !fun.native {
+            return Err(format!("function {} has no body", fun.source_name()));
+        }
+
+        let (params, return_type) = signature(bindings, fun)?;
+        let natives = &mut self.lowering.module.natives;
+        let index = natives.len() as u32;
+        natives.push(NativeRef { name: plain_name(fun), params, return_type });
+        self.lowering.callees.insert(id, Callee::Native(index));
+        Ok(Instr::CallNative(index))
+    }
+
+    fn stmt(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Decl(decl) => {
+                let slot = self.slot(decl.bind_id)?;
+                if let Some(expr) = &decl.expr {
+                    self.expr(expr)?;
+                    self.code.push(Instr::Store(slot));
+                }
+            }
+            Node::Assign(point, _, expr) => {
+                let id = point.expect_bound()?;
+                self.expr(expr)?;
+                let store = match self.lowering.globals.get(&id) {
+                    Some(global) => Instr::StoreGlobal(*global),
+                    None => Instr::Store(self.slot(id)?)
+                };
+                self.code.push(store);
+            }
+            Node::Return(Some(expr)) => {
+                self.expr(expr)?;
+                self.code.push(Instr::Return);
+            }
+            Node::Return(None) => {
+                self.code.push(Instr::ReturnVoid);
+            }
+            Node::Empty => {}
+            _ => {
+                // An expression statement, i.e. a call whose result is thrown away.
+                self.expr(node)?;
+                self.code.push(Instr::Pop);
+            }
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::NumConst(num) => {
+                let text = num.value_str.to_utf8();
+                let bad = || format!("bad numeric constant '{}'", text);
+                let instr = match value_type(&num.typ)? {
+                    ValueType::Int => Instr::PushInt(text.parse().map_err(|_| bad())?),
+                    ValueType::Float => Instr::PushFloat(text.parse().map_err(|_| bad())?),
+                    ValueType::Void => return Err(bad())
+                };
+                self.code.push(instr);
+            }
+            Node::VarRef(point, _) => {
+                let id = point.expect_bound()?;
+                let load = match self.lowering.globals.get(&id) {
+                    Some(global) => Instr::LoadGlobal(*global),
+                    None => Instr::Load(self.slot(id)?)
+                };
+                self.code.push(load);
+            }
+            Node::FunCall(_, point, _, args) => {
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                let call = self.callee(point.expect_bound()?)?;
+                self.code.push(call);
+            }
+            Node::BinOp(op, lhs, rhs) => {
+                self.expr(lhs)?;
+                self.expr(rhs)?;
+                self.code.push(match op {
+                    Op::Add => Instr::Add,
+                    Op::Subtract => Instr::Sub,
+                    Op::Multiply => Instr::Mul,
+                    Op::Divide => Instr::Div,
+                    Op::Equal => Instr::Eq,
+                    Op::NotEqual => Instr::Ne
+                });
+            }
+            _ => return Err(String::from("not an expression"))
+        }
+        Ok(())
+    }
+}
+
+/// Lowers typechecked trees into a bytecode module. Functions and script variables keep the order
+/// they were declared in, and natives are listed in the order they are first called.
+pub fn lower(bindings: &Bindings, trees: &[Node]) -> Result<Module, String> {
+    let mut lowering = Lowering { bindings, callees: HashMap::new(), globals: HashMap::new(), module: Module::default() };
+
+    let mut decls: Vec<&FunDecl> = vec![];
+    for tree in trees {
+        if let Node::Tree(tree) = tree {
+            for child in &tree.children {
+                match child {
+                    Node::FunDecl(f) if !bindings.get_fun(f.bind_id).test => decls.push(f),
+                    Node::Decl(decl) => {
+                        let var = bindings.get_var(decl.bind_id);
+                        let typ = value_type(&var.typ)?;
+                        let text = match decl.expr.as_deref() {
+                            Some(Node::NumConst(num)) => num.value_str.to_utf8(),
+                            _ => String::from("0")
+                        };
+                        let initial = Value::parse(&text, &var.typ)?;
+
+                        let index = u16::try_from(lowering.module.globals.len()).map_err(|_| String::from("too many script variables"))?;
+                        let name = format!("{}.{}", tree.own_type, var.name);
+                        lowering.module.globals.push(Global { name, typ, initial });
+                        lowering.globals.insert(decl.bind_id, index);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    for (index, decl) in decls.iter().enumerate() {
+        lowering.callees.insert(decl.bind_id, Callee::Function(index as u32));
+    }
+
+    for decl in decls {
+        let fun = bindings.get_fun(decl.bind_id);
+        let (params, return_type) = signature(bindings, fun)?;
+
+        let mut lowerer = FunLowering { lowering: &mut lowering, slots: HashMap::new(), code: vec![] };
+        for arg in &fun.args {
+            lowerer.slot(*arg)?;
+        }
+        for stmt in &decl.body {
+            lowerer.stmt(&stmt.node)?;
+        }
+        lowerer.code.push(Instr::ReturnVoid);
+
+        let slots = lowerer.slots.len() as u16;
+        let code = lowerer.code;
+        lowering.module.functions.push(Function { name: plain_name(fun), params, return_type, slots, code });
+    }
+
+    Ok(lowering.module)
+}
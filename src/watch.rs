@@ -0,0 +1,202 @@
+//! Support for `ponyscript watch`, which rebuilds a library whenever its scripts change.
+//!
+//! Each rebuild goes to a new versioned library next to the configured one (`libgame.so` becomes
+//! `libgame.1.so`, `libgame.2.so`, ...), and once it is complete, the `.current` file
+//! (`libgame.so.current`) is updated to name it. The game picks new versions up through the
+//! loader from [`write_loader`], which swaps the function table of the old version for the new
+//! one.
+//!
+//! Script variables (top-level `let`s) are not lost on a reload. Their values are kept in memory
+//! that belongs to the loader, and each new version is handed what the previous one left behind.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::ast::codegen;
+
+/// The modification times of a set of files, compared to find out whether any of them changed.
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    times: BTreeMap<PathBuf, Option<SystemTime>>
+}
+
+impl Snapshot {
+    /// Records the modification time of every path. Files that cannot be read are recorded too, so
+    /// that deleting or recreating a file also counts as a change.
+    pub fn take(paths: &[PathBuf]) -> Snapshot {
+        let times = paths.iter()
+            .map(|path| (path.clone(), std::fs::metadata(path).and_then(|meta| meta.modified()).ok()))
+            .collect();
+        Snapshot { times }
+    }
+}
+
+fn split_library(library: &Path) -> (String, String) {
+    let name = library.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    match name.split_once('.') {
+        Some((stem, extension)) => (stem.to_string(), format!(".{}", extension)),
+        None => (name, String::new())
+    }
+}
+
+/// The path of one version of `library`.
+pub fn versioned_library(library: &Path, version: u32) -> PathBuf {
+    let (stem, extension) = split_library(library);
+    library.with_file_name(format!("{}.{}{}", stem, version, extension))
+}
+
+/// The file naming the newest version of `library`, which the loader reads.
+pub fn current_file(library: &Path) -> PathBuf {
+    let mut name = library.file_name().unwrap_or_default().to_os_string();
+    name.push(".current");
+    library.with_file_name(name)
+}
+
+/// The version the current file points at, if there is one. Watching continues from there, so
+/// that a running game never sees a library it has loaded being overwritten.
+pub fn current_version(library: &Path) -> Option<u32> {
+    let (stem, extension) = split_library(library);
+    let current = std::fs::read_to_string(current_file(library)).ok()?;
+    current.trim()
+        .strip_prefix(&format!("{}.", stem))?
+        .strip_suffix(&extension)?
+        .parse()
+        .ok()
+}
+
+/// Makes `versioned` the newest version of `library`. The current file is replaced in one step,
+/// so the loader never reads a partly written name.
+pub fn publish(library: &Path, versioned: &Path) -> io::Result<()> {
+    let current = current_file(library);
+    let temporary = current.with_extension("current.tmp");
+
+    let name = versioned.file_name().unwrap_or_default().to_string_lossy();
+    std::fs::write(&temporary, format!("{}\n", name))?;
+    std::fs::rename(&temporary, &current)
+}
+
+/// Writes the C loader (`ponyscript_loader.h` and `ponyscript_loader.c`) into `dir`, to be built
+/// into the game.
+pub fn write_loader(dir: &Path) -> io::Result<()> {
+    codegen::write_loader_header(&mut File::create(dir.join(codegen::LOADER_HEADER))?)?;
+    codegen::write_loader_source(&mut File::create(dir.join(codegen::LOADER_SOURCE))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::{Command, Stdio};
+    use crate::{CCompiler, Compiler, Output};
+    use super::*;
+
+    #[test]
+    fn library_versions() {
+        let library = Path::new("build/libgame.so");
+        assert_eq!(versioned_library(library, 3), PathBuf::from("build/libgame.3.so"));
+        assert_eq!(current_file(library), PathBuf::from("build/libgame.so.current"));
+        assert_eq!(versioned_library(Path::new("game"), 1), PathBuf::from("game.1"));
+    }
+
+    #[test]
+    fn snapshots_notice_changes() {
+        let dir = std::env::temp_dir().join(format!("ponyscript-watch-snapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.pony.script");
+        std::fs::write(&path, "extends Node as A\n").unwrap();
+
+        let paths = vec![path.clone()];
+        let before = Snapshot::take(&paths);
+        assert_eq!(before, Snapshot::take(&paths));
+
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert_ne!(before, Snapshot::take(&paths));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(before, Snapshot::take(&paths));
+    }
+
+    fn build_version(library: &Path, version: u32, source: &str, cc: &CCompiler) {
+        let versioned = versioned_library(library, version);
+        let mut compiler = Compiler::new(Output::NativeLib { library: versioned.clone(), cc: cc.clone() });
+        compiler.set_function_table(true);
+        compiler.parse_source_str("hot.pony.script", source).unwrap();
+        compiler.typecheck().unwrap();
+        compiler.output().unwrap();
+        publish(library, &versioned).unwrap();
+    }
+
+    #[test]
+    fn loader_swaps_versions() {
+        let cc = CCompiler::from_env();
+        if Command::new(&cc.program).arg("--version").output().is_err() {
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("ponyscript-watch-loader-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let library = dir.join("libhot.so");
+        write_loader(&dir).unwrap();
+
+        // A host that bumps the script's counter and prints it, and reloads whenever it reads a line.
+        let host = r#"
+#include <stdint.h>
+#include <stdio.h>
+#include "ponyscript_loader.h"
+
+int main(int argc, char **argv) {
+	ps_loader loader;
+	if (ps_loader_open(&loader, argv[1]) != 0) return 2;
+	char line[16];
+	do {
+		int32_t (*bump)(void) = (int32_t (*)(void)) ps_loader_find(&loader, "Hot.bump()");
+		int32_t *count = (int32_t *) ps_loader_state(&loader, "Hot.count");
+		int32_t value = bump();
+		printf("%d %d\n", value, *count);
+		fflush(stdout);
+	} while (fgets(line, sizeof line, stdin) && ps_loader_reload(&loader) == 1);
+	ps_loader_close(&loader);
+	return 0;
+}
+"#;
+        std::fs::write(dir.join("host.c"), host).unwrap();
+        let status = Command::new(&cc.program)
+            .arg("-o").arg(dir.join("host"))
+            .arg(dir.join("host.c")).arg(dir.join(codegen::LOADER_SOURCE))
+            .arg("-ldl")
+            .status().unwrap();
+        assert!(status.success());
+
+        let first = "extends Node as Hot\n\nlet count: int = 0\n\nfun bump() -> int:\n\tcount = count + 1\n\treturn count\n";
+        build_version(&library, 1, first, &cc);
+        assert_eq!(current_version(&library), Some(1));
+
+        let mut child = Command::new(dir.join("host"))
+            .arg(current_file(&library))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn().unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+
+        stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, "1 1\n");
+
+        // The counter keeps its value, and the new variable starts at its initial value.
+        let second = "extends Node as Hot\n\nlet extra: int = 100\nlet count: int = 0\n\nfun bump() -> int:\n\tcount = count + 1\n\treturn count + extra\n";
+        build_version(&library, 2, second, &cc);
+        stdin.write_all(b"reload\n").unwrap();
+        line.clear();
+        stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, "102 2\n");
+
+        // Nothing new: the host stops.
+        stdin.write_all(b"reload\n").unwrap();
+        assert!(child.wait().unwrap().success());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+//! Golden-file tests for the compiler pipeline.
+//!
+//! Every `*.pony.script` in `tests/golden` is lexed, parsed, typechecked and compiled to C. The
+//! output of each stage is compared against the file next to the case with the stage's extension:
+//!
+//! - `.tokens`: the token dump, as printed by `emit --stage tokens`
+//! - `.ast`: the parsed trees, as printed by `emit --stage ast`
+//! - `.diagnostics`: the error that stopped the compilation
+//! - `.c`: the generated code, without `#line` directives
+//!
+//! Stages after an error produce nothing, and must not have a file. Run with `BLESS=1` to write
+//! the current output as the expected one, removing the files of stages that no longer run.
+
+use std::path::{Path, PathBuf};
+use ponyscript::{ast, dump_tokens, Compiler, Output};
+
+const STAGES: [&str; 4] = ["tokens", "ast", "diagnostics", "c"];
+
+/// Runs a case through the pipeline, giving the output of each stage that ran.
+fn run_case(name: &str, source: &str) -> Vec<(&'static str, String)> {
+    let mut outputs = vec![("tokens", dump_tokens(name, source))];
+
+    let mut compiler = Compiler::new(Output::Stdout);
+    compiler.set_line_directives(false);
+    if let Err(error) = compiler.parse_source_str(name, source) {
+        outputs.push(("diagnostics", format!("{}\n", error)));
+        return outputs;
+    }
+
+    let mut ast = String::new();
+    for tree in compiler.trees() {
+        ast += &format!("{}\n", ast::to_sexpr(compiler.bindings(), tree));
+    }
+    outputs.push(("ast", ast));
+
+    match compiler.typecheck() {
+        Ok(()) => outputs.push(("c", compiler.emit_c())),
+        Err(error) => outputs.push(("diagnostics", format!("{}\n", error)))
+    }
+    outputs
+}
+
+fn expected_path(case: &Path, stage: &str) -> PathBuf {
+    let name = case.file_name().unwrap().to_string_lossy();
+    case.with_file_name(format!("{}.{}", name.trim_end_matches(".pony.script"), stage))
+}
+
+/// Describes where `actual` first differs from `expected`.
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (None, None) => return String::from("line endings differ"),
+            (e, a) => return format!("line {}: expected {:?}, got {:?}", line, e.unwrap_or("<end>"), a.unwrap_or("<end>"))
+        }
+    }
+}
+
+/// Compares the outputs of a case against its files, or with `bless`, rewrites the files.
+fn check_case(case: &Path, bless: bool, failures: &mut Vec<String>) {
+    let name = case.file_name().unwrap().to_string_lossy().to_string();
+    let source = std::fs::read_to_string(case).unwrap();
+    let outputs = run_case(&name, &source);
+
+    for stage in STAGES {
+        let path = expected_path(case, stage);
+        let actual = outputs.iter().find(|(s, _)| *s == stage).map(|(_, output)| output);
+        let expected = std::fs::read_to_string(&path).ok();
+
+        if bless {
+            match actual {
+                Some(actual) => std::fs::write(&path, actual).unwrap(),
+                None if expected.is_some() => std::fs::remove_file(&path).unwrap(),
+                None => {}
+            }
+            continue;
+        }
+
+        match (expected, actual) {
+            (Some(expected), Some(actual)) if &expected != actual => {
+                failures.push(format!("{}: {} differs, {}", name, stage, first_difference(&expected, actual)));
+            }
+            (None, Some(_)) => failures.push(format!("{}: no expected {} output", name, stage)),
+            (Some(_), None) => failures.push(format!("{}: {} was expected, but the stage did not run", name, stage)),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn golden_files() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let bless = std::env::var_os("BLESS").is_some_and(|value| !value.is_empty() && value != "0");
+
+    let mut cases: Vec<PathBuf> = std::fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".pony.script"))
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no cases in {}", dir.display());
+
+    let mut failures = vec![];
+    for case in &cases {
+        check_case(case, bless, &mut failures);
+    }
+
+    assert!(failures.is_empty(), "{} golden file mismatches (run with BLESS=1 to update them):\n{}", failures.len(), failures.join("\n"));
+}
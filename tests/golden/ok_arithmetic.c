@@ -0,0 +1,26 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include <stdio.h>
+
+/* Integers wrap around on overflow, as they do in the interpreter and the VM. */
+#define int32_t_op_add(a, b) ((int32_t) ((uint32_t) (a) + (uint32_t) (b)))
+#define float_op_add(a, b) ((a) + (b))
+#define int32_t_op_eq(a, b) ((a) == (b))
+#define float_op_eq(a, b) ((a) == (b))
+#define int32_t_op_ne(a, b) ((a) != (b))
+#define float_op_ne(a, b) ((a) != (b))
+int32_t psFD1T3addiiE(int32_t psL1a0, int32_t psL1b0);
+float psFD1T5twicefE(float psL1x0);
+int32_t psFD1T4mainE();
+int32_t psFD1T3addiiE(int32_t psL1a0, int32_t psL1b0) {
+return int32_t_op_add(psL1a0, psL1b0);
+}
+float psFD1T5twicefE(float psL1x0) {
+float psL1y0 = float_op_add(psL1x0, psL1x0);
+return psL1y0;
+}
+int32_t psFD1T4mainE() {
+int32_t psL1a0 = int32_t_op_add(20, 10);
+psL1a0 = psFD1T3addiiE(psL1a0, 12);
+return psL1a0;
+}
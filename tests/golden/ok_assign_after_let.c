@@ -0,0 +1,23 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include <stdio.h>
+
+/* Integers wrap around on overflow, as they do in the interpreter and the VM. */
+#define int32_t_op_add(a, b) ((int32_t) ((uint32_t) (a) + (uint32_t) (b)))
+#define float_op_add(a, b) ((a) + (b))
+#define int32_t_op_eq(a, b) ((a) == (b))
+#define float_op_eq(a, b) ((a) == (b))
+#define int32_t_op_ne(a, b) ((a) != (b))
+#define float_op_ne(a, b) ((a) != (b))
+int32_t psFD1T4takeiE(int32_t psL1n0);
+int32_t psFD1T5laterE();
+int32_t psFD1T4takeiE(int32_t psL1n0) {
+return psL1n0;
+}
+int32_t psFD1T5laterE() {
+int32_t psL1n0;
+psL1n0 = 1;
+int32_t psL1m0;
+psL1m0 = psFD1T4takeiE(psL1n0);
+return psL1m0;
+}
@@ -0,0 +1,34 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include <stdio.h>
+
+/* Integers wrap around on overflow, as they do in the interpreter and the VM. */
+#define int32_t_op_add(a, b) ((int32_t) ((uint32_t) (a) + (uint32_t) (b)))
+#define float_op_add(a, b) ((a) + (b))
+#define int32_t_op_eq(a, b) ((a) == (b))
+#define float_op_eq(a, b) ((a) == (b))
+#define int32_t_op_ne(a, b) ((a) != (b))
+#define float_op_ne(a, b) ((a) != (b))
+int32_t psFD1T4takeiE(int32_t psL1n0);
+int32_t psFD1T5countE();
+float psFD1T5ratiofE(float psL1a0);
+int32_t psFD1T8fallbackE();
+int32_t psFD1T4takeiE(int32_t psL1n0) {
+return psL1n0;
+}
+int32_t psFD1T5countE() {
+int32_t psL1x0 = 5;
+int32_t psL1y0 = int32_t_op_add(psL1x0, 1);
+psFD1T4takeiE(psL1y0);
+return psL1x0;
+}
+float psFD1T5ratiofE(float psL1a0) {
+float psL1r0 = 2;
+psL1r0 = psL1a0;
+return psL1r0;
+}
+int32_t psFD1T8fallbackE() {
+float psL1z0 = 3;
+int32_t psL4same0 = float_op_eq(psL1z0, 3);
+return psL4same0;
+}
@@ -0,0 +1,30 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include <stdio.h>
+
+/* Integers wrap around on overflow, as they do in the interpreter and the VM. */
+#define int32_t_op_add(a, b) ((int32_t) ((uint32_t) (a) + (uint32_t) (b)))
+#define float_op_add(a, b) ((a) + (b))
+#define int32_t_op_eq(a, b) ((a) == (b))
+#define float_op_eq(a, b) ((a) == (b))
+#define int32_t_op_ne(a, b) ((a) != (b))
+#define float_op_ne(a, b) ((a) != (b))
+int32_t psFD1P4pickiE(int32_t psL1x0);
+int32_t psFD1P4pickfE(float psL1x0);
+int32_t psFD1P1fE();
+int32_t psFD1P1gE();
+int32_t psFD1P4pickiE(int32_t psL1x0) {
+return 1;
+}
+int32_t psFD1P4pickfE(float psL1x0) {
+return 2;
+}
+int32_t psFD1P1fE() {
+int32_t psL1x0 = 5;
+int32_t psL1y0 = psL1x0;
+return psFD1P4pickiE(psL1x0);
+}
+int32_t psFD1P1gE() {
+float psL1z0 = 5;
+return psFD1P4pickfE(psL1z0);
+}
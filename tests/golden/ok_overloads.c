@@ -0,0 +1,25 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include <stdio.h>
+
+/* Integers wrap around on overflow, as they do in the interpreter and the VM. */
+#define int32_t_op_add(a, b) ((int32_t) ((uint32_t) (a) + (uint32_t) (b)))
+#define float_op_add(a, b) ((a) + (b))
+#define int32_t_op_eq(a, b) ((a) == (b))
+#define float_op_eq(a, b) ((a) == (b))
+#define int32_t_op_ne(a, b) ((a) != (b))
+#define float_op_ne(a, b) ((a) != (b))
+void psFD1T4somefE(float psL1x0);
+void psFD1T4someiE(int32_t psL1x0);
+void psFD1T4tickfE(float psL5delta0);
+void psFD1T4somefE(float psL1x0) {
+float psL1y0 = psL1x0;
+}
+void psFD1T4someiE(int32_t psL1x0) {
+int32_t psL1y0 = psL1x0;
+}
+void psFD1T4tickfE(float psL5delta0) {
+int32_t psL5first0 = 10;
+psFD1T4someiE(psL5first0);
+psFD1T4somefE(psL5delta0);
+}
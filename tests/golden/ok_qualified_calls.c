@@ -0,0 +1,21 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include <stdio.h>
+
+/* Integers wrap around on overflow, as they do in the interpreter and the VM. */
+#define int32_t_op_add(a, b) ((int32_t) ((uint32_t) (a) + (uint32_t) (b)))
+#define float_op_add(a, b) ((a) + (b))
+#define int32_t_op_eq(a, b) ((a) == (b))
+#define float_op_eq(a, b) ((a) == (b))
+#define int32_t_op_ne(a, b) ((a) != (b))
+#define float_op_ne(a, b) ((a) != (b))
+int32_t psFD1T3addiiE(int32_t psL1a0, int32_t psL1b0);
+int32_t psFD1T1fE();
+int32_t psFD1T3addiiE(int32_t psL1a0, int32_t psL1b0) {
+return int32_t_op_add(psL1a0, psL1b0);
+}
+int32_t psFD1T1fE() {
+int32_t psL1x0 = psFD1T3addiiE(1, 2);
+psFD1T3addiiE(psL1x0, 3);
+return psFD1T3addiiE(psL1x0, psFD1T3addiiE(4, 5));
+}
@@ -0,0 +1,34 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include <stdio.h>
+
+/* Integers wrap around on overflow, as they do in the interpreter and the VM. */
+#define int32_t_op_add(a, b) ((int32_t) ((uint32_t) (a) + (uint32_t) (b)))
+#define float_op_add(a, b) ((a) + (b))
+#define int32_t_op_eq(a, b) ((a) == (b))
+#define float_op_eq(a, b) ((a) == (b))
+#define int32_t_op_ne(a, b) ((a) != (b))
+#define float_op_ne(a, b) ((a) != (b))
+int32_t psFD7Counter4bumpE();
+float psFD7Counter6scaledE();
+int32_t psFD7Counter8shadowedE();
+struct ps_state {
+	int32_t psV7Counter5count;
+	float psV7Counter4rate;
+};
+static struct ps_state ps_initial_state = {
+	.psV7Counter5count = 0,
+	.psV7Counter4rate = 2,
+};
+struct ps_state *ps_state = &ps_initial_state;
+int32_t psFD7Counter4bumpE() {
+ps_state->psV7Counter5count = int32_t_op_add(ps_state->psV7Counter5count, 1);
+return ps_state->psV7Counter5count;
+}
+float psFD7Counter6scaledE() {
+return float_op_add(ps_state->psV7Counter4rate, 3);
+}
+int32_t psFD7Counter8shadowedE() {
+int32_t psL5count0 = 5;
+return psL5count0;
+}
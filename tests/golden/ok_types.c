@@ -0,0 +1,15 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include <stdio.h>
+
+/* Integers wrap around on overflow, as they do in the interpreter and the VM. */
+#define int32_t_op_add(a, b) ((int32_t) ((uint32_t) (a) + (uint32_t) (b)))
+#define float_op_add(a, b) ((a) + (b))
+#define int32_t_op_eq(a, b) ((a) == (b))
+#define float_op_eq(a, b) ((a) == (b))
+#define int32_t_op_ne(a, b) ((a) != (b))
+#define float_op_ne(a, b) ((a) != (b))
+void psFD1T1fOiRP4NodeT5ArrayifEE(Opint32_t psL1a0, DrTNode psL1b0, ParArrayWint32_tfloat psL1c0);
+void psFD1T1fOiRP4NodeT5ArrayifEE(Opint32_t psL1a0, DrTNode psL1b0, ParArrayWint32_tfloat psL1c0) {
+return;
+}